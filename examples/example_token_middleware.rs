@@ -6,20 +6,23 @@
 
 use actix_web::http::StatusCode;
 use actix_web::{web, App, HttpResponse, HttpServer};
+use simbld_http::helpers::auth_middleware::{Principal, StaticTokenValidator, TokenSource};
 use simbld_http::AuthMiddleware;
 use std::io;
 
 /// Starts an HTTP server with a protected route that requires authentication.
 ///
 /// # Authentication Flow
-/// The server uses `AuthMiddleware` to validate access tokens provided as query parameters.
+/// The server uses `AuthMiddleware` to validate access tokens provided as bearer tokens
+/// or as the `key` query parameter. A `StaticTokenValidator` knows one valid token
+/// (`validated`) and one expired token (`expired`).
 /// The middleware intercepts requests to all routes and checks the validity of the token.
 ///
 /// # Protected Route
 /// - Path: `/protected`
 /// - Method: GET
 /// - Access control: Requires a valid authentication token
-/// - Success response: Returns "Access Granted" with HTTP 200 status
+/// - Success response: Returns "Access Granted" and the caller id with HTTP 200 status
 ///
 /// # Testing URLs
 /// The application can be tested with these URLs:
@@ -37,14 +40,21 @@ async fn main() -> io::Result<()> {
 
     HttpServer::new(|| {
         App::new()
-            .wrap(AuthMiddleware)
+            .wrap(
+                AuthMiddleware::new(
+                    StaticTokenValidator::new()
+                        .with_token("validated", Principal::new("demo-user"))
+                        .with_expired_token("expired"),
+                )
+                .with_token_source(TokenSource::Query("key".to_string())),
+            )
             // We protect this route via middleware
             .route(
                 "/protected",
-                web::get().to(|| async {
+                web::get().to(|principal: Principal| async move {
                     HttpResponse::build(StatusCode::from_u16(200).unwrap())
                         .insert_header(("X-HTTP-Status-Code", "200"))
-                        .body("Access Granted")
+                        .body(format!("Access Granted to {}", principal.id))
                 }),
             )
     })
@@ -1,5 +1,13 @@
 use actix_web::{web, App, HttpResponse, HttpServer, Responder};
 use serde_json::{json, Value};
+use simbld_http::helpers::cors_helper::CorsPolicy;
+use simbld_http::helpers::rate_limit_helper::{
+    RateLimitHeaders, SlidingWindowCounter, SystemClock,
+};
+use simbld_http::helpers::rate_limit_key_helper::{
+    ClientIpKey, MissingKeyPolicy, RateLimitOverrides,
+};
+use simbld_http::helpers::rate_limit_store_helper::InMemoryStore;
 use simbld_http::helpers::{
     http_interceptor_helper::HttpInterceptor, response_helpers,
     response_with_cookie_helper::ok_with_cookie, response_with_headers_helper::ok_with_headers,
@@ -13,7 +21,7 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::rc::Rc;
 use std::result::Result;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::time::Duration;
 
 fn examples_with_helpers() {
@@ -88,17 +96,17 @@ fn examples_with_helpers() {
 
     // Example 6: Using helpers with unified middleware
     let unified_middleware = UnifiedMiddleware::new(
-        "*".to_string(),                      // Allowed origins
-        Arc::new(Mutex::new(HashMap::new())), // Rate limiters
-        100,                                  // Max requests
-        Duration::from_secs(60),              // Window duration
-        Rc::new(|_req| true),                 // Intercept dependencies
+        "*".to_string(),                // Allowed origins
+        Arc::new(InMemoryStore::new()), // Rate limiters
+        100,                            // Max requests
+        Duration::from_secs(60),        // Window duration
+        Rc::new(|_req| true),           // Intercept dependencies
         None,
     );
     println!("Created UnifiedMiddleware: {:?}", unified_middleware);
 
     // Example 7: Using helpers with http interceptor
-    let http_interceptor = HttpInterceptor;
+    let http_interceptor = HttpInterceptor::new();
     println!("Created HttpInterceptor: {:?}", http_interceptor);
 
     // Example 8: Using helpers with custom responses
@@ -280,13 +288,20 @@ async fn main() -> std::io::Result<()> {
                     set.insert("*".to_string());
                     set
                 },
-                rate_limiters: Arc::new(Mutex::new(HashMap::new())),
+                cors: CorsPolicy::new().with_max_age(Duration::from_secs(600)),
+                rate_limiters: Arc::new(InMemoryStore::new()),
                 max_requests: 100,
                 window_duration: Duration::from_secs(60),
                 intercept_dependencies: Rc::new(|_req| true),
                 condition: Rc::new(Box::new(|_req| true)),
+                rate_limit_algorithm: Arc::new(SlidingWindowCounter),
+                clock: Arc::new(SystemClock),
+                rate_limit_key: Rc::new(ClientIpKey),
+                missing_key_policy: MissingKeyPolicy::SharedBucket,
+                rate_limit_overrides: Rc::new(RateLimitOverrides::new()),
+                rate_limit_headers: RateLimitHeaders::Separate,
             })
-            .wrap(HttpInterceptor) // Specific interceptor
+            .wrap(HttpInterceptor::new()) // Specific interceptor
             .route("/transform_bad_request_to_json", web::get().to(transform_bad_request_to_json))
             .route("/example_success", web::get().to(example_success))
             .route("/success", web::get().to(example_ok_with_metadata))
@@ -44,7 +44,7 @@ async fn example_bad_request() -> impl Responder {
 async fn main() -> std::io::Result<()> {
     HttpServer::new(|| {
         App::new()
-            .wrap(HttpInterceptor)
+            .wrap(HttpInterceptor::new())
             .route("/success", web::get().to(example_success))
             .route("/bad_request", web::post().to(example_bad_request))
     })
@@ -2,13 +2,13 @@ use actix_web::dev::ServiceRequest;
 use actix_web::HttpRequest;
 use actix_web::{web, App, HttpServer, Responder};
 use simbld_http::helpers::http_interceptor_helper::HttpInterceptor;
+use simbld_http::helpers::rate_limit_store_helper::InMemoryStore;
 use simbld_http::helpers::unified_middleware_helper::UnifiedMiddleware;
 use simbld_http::responses::CustomResponse;
 use simbld_http::ResponsesSuccessCodes;
-use std::collections::HashMap;
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::time::Duration;
 
 // Example of an answer that processes data
 async fn example_response(path: web::Path<String>) -> impl Responder {
@@ -91,17 +91,17 @@ async fn main() -> std::io::Result<()> {
     let create_api_middleware = || {
         UnifiedMiddleware::new(
             "https://app.example.com,https://admin.example.com".to_string(),
-            Arc::new(Mutex::new(HashMap::<String, (u64, Instant)>::new())),
+            Arc::new(InMemoryStore::new()),
             200,
             Duration::from_secs(120),
             Rc::new(|req: &ServiceRequest| {
-                return if let Some(content_type) = req.headers().get("Content-Type") {
+                if let Some(content_type) = req.headers().get("Content-Type") {
                     let content_type = content_type.to_str().unwrap_or("");
                     content_type.contains("application/json")
                         || content_type.contains("application/x-www-form-urlencoded")
                 } else {
                     req.method() == actix_web::http::Method::GET
-                };
+                }
             }),
             Some(Box::new(|req: &ServiceRequest| req.path().contains("/auth"))),
         )
@@ -124,7 +124,7 @@ async fn main() -> std::io::Result<()> {
                     .wrap(create_public_middleware())
                     .route("/health", web::get().to(health_check)),
             )
-            .wrap(HttpInterceptor)
+            .wrap(HttpInterceptor::new())
     })
     .bind("127.0.0.1:8090")?
     .workers(4) // Specify a number of workers
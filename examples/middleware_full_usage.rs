@@ -1,13 +1,12 @@
 use actix_web::dev::ServiceRequest;
 use actix_web::{web, App, HttpResponse, HttpServer};
 use simbld_http::helpers::{
-    http_interceptor_helper::HttpInterceptor, unified_middleware_helper::UnifiedMiddleware,
+    http_interceptor_helper::HttpInterceptor, rate_limit_store_helper::InMemoryStore,
+    unified_middleware_helper::UnifiedMiddleware,
 };
-use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::Arc;
-use std::sync::Mutex;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 /// # Demonstration of UnifiedMiddleware and HttpInterceptor
 ///
@@ -43,7 +42,7 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .wrap(UnifiedMiddleware::new(
                 "allowed_origins".to_string(),
-                Arc::new(Mutex::new(HashMap::<String, (u64, Instant)>::new())),
+                Arc::new(InMemoryStore::new()),
                 100,
                 Duration::from_secs(60),
                 Rc::new(|req: &ServiceRequest| {
@@ -55,7 +54,7 @@ async fn main() -> std::io::Result<()> {
                     req.path().starts_with("/api")
                 })),
             )) // Applies a custom middleware
-            .wrap(HttpInterceptor) // Applies another custom middleware
+            .wrap(HttpInterceptor::new()) // Applies another custom middleware
             .route("/", web::get().to(home)) // Modify here to call the `home` function
             .route("/custom", web::get().to(custom_example))
     })
@@ -41,7 +41,7 @@ async fn main() -> std::io::Result<()> {
                 100,
                 std::time::Duration::from_secs(60),
             ))
-            .wrap(HttpInterceptor)
+            .wrap(HttpInterceptor::new())
             .route("/", web::get().to(home))
             .route("/custom", web::get().to(custom_example))
     })
@@ -0,0 +1,354 @@
+//! # Access Logging
+//!
+//! Provides `AccessLog`, which `HttpInterceptor` uses to emit one record per request.
+//!
+//! Each `AccessLogRecord` holds the method, path, status, internal catalog code and name
+//! (when the response was rendered from the catalog), duration, request ID, client IP and
+//! user agent. Records are rendered as JSON, Common Log Format or Combined Log Format and
+//! handed to a pluggable `AccessLogSink`:
+//! - `LogSink` writes through the `log` facade (target `simbld_http::access`),
+//! - `FileSink` appends lines to a file,
+//! - `ChannelSink` sends records to a channel, for custom processing.
+//!
+//! The level of a record follows its status family: `Error` for 5xx, `Warn` for 4xx,
+//! `Info` otherwise.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::{web, App, HttpResponse};
+//! use simbld_http::helpers::access_log_helper::{AccessLog, AccessLogFormat, LogSink};
+//! use simbld_http::HttpInterceptor;
+//!
+//! let app = App::new()
+//!     .wrap(HttpInterceptor::new().with_access_log(AccessLog::new(AccessLogFormat::Json, LogSink)))
+//!     .route("/", web::get().to(HttpResponse::Ok));
+//! ```
+
+use crate::responses::ResponsesTypes;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// What is known about a request once its response is ready.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessLogRecord {
+    /// When the request was received.
+    pub timestamp: DateTime<Utc>,
+    pub method: String,
+    /// Path and query string.
+    pub path: String,
+    /// Protocol version (e.g. `HTTP/1.1`).
+    pub version: String,
+    pub status: u16,
+    /// Internal code, when the response was rendered from the catalog.
+    pub internal_code: Option<u16>,
+    /// Internal name, when the response was rendered from the catalog.
+    pub internal_name: Option<String>,
+    pub duration: Duration,
+    /// Size of the response body, when known.
+    pub bytes: Option<u64>,
+    pub request_id: Option<String>,
+    pub client_ip: Option<String>,
+    /// Authenticated principal, if any.
+    pub user: Option<String>,
+    pub user_agent: Option<String>,
+    pub referer: Option<String>,
+}
+
+impl AccessLogRecord {
+    /// Fills the internal code and name from the catalog response type.
+    ///
+    /// Responses without a distinct internal code are logged with their standard one.
+    pub fn with_response_type(mut self, response: &ResponsesTypes) -> Self {
+        let code = response.as_tuple();
+        self.internal_code = Some(code.internal_code.unwrap_or(code.standard_code));
+        self.internal_name = Some(code.internal_name.unwrap_or(code.standard_name).to_string());
+        self
+    }
+
+    /// Returns the level matching the status family.
+    pub fn level(&self) -> log::Level {
+        match self.status {
+            500.. => log::Level::Error,
+            400..=499 => log::Level::Warn,
+            _ => log::Level::Info,
+        }
+    }
+
+    /// Returns the record as a JSON object, without the fields that are unknown.
+    pub fn to_json(&self) -> Value {
+        let mut record = json!({
+            "timestamp": self.timestamp.to_rfc3339(),
+            "level": self.level().as_str(),
+            "method": self.method,
+            "path": self.path,
+            "version": self.version,
+            "status": self.status,
+            "duration_ms": self.duration.as_millis() as u64,
+        });
+        let optional = [
+            ("internal_code", self.internal_code.map(Value::from)),
+            ("internal_name", self.internal_name.clone().map(Value::from)),
+            ("bytes", self.bytes.map(Value::from)),
+            ("request_id", self.request_id.clone().map(Value::from)),
+            ("client_ip", self.client_ip.clone().map(Value::from)),
+            ("user", self.user.clone().map(Value::from)),
+            ("user_agent", self.user_agent.clone().map(Value::from)),
+            ("referer", self.referer.clone().map(Value::from)),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                record[name] = value;
+            }
+        }
+        record
+    }
+
+    /// Renders the record in `format`.
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        let field = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+        let quoted = |value: &Option<String>| match value {
+            Some(value) => format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+            None => "\"-\"".to_string(),
+        };
+        let common = || {
+            format!(
+                "{} - {} [{}] \"{} {} {}\" {} {}",
+                field(&self.client_ip),
+                field(&self.user),
+                self.timestamp.format("%d/%b/%Y:%H:%M:%S %z"),
+                self.method,
+                self.path,
+                self.version,
+                self.status,
+                self.bytes.map_or_else(|| "-".to_string(), |bytes| bytes.to_string())
+            )
+        };
+        match format {
+            AccessLogFormat::Json => self.to_json().to_string(),
+            AccessLogFormat::Common => common(),
+            AccessLogFormat::Combined => {
+                format!("{} {} {}", common(), quoted(&self.referer), quoted(&self.user_agent))
+            }
+        }
+    }
+}
+
+/// Layout of access log lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// One JSON object per line, with every known field.
+    Json,
+    /// NCSA Common Log Format.
+    Common,
+    /// NCSA Combined Log Format (Common, plus referer and user agent).
+    Combined,
+}
+
+/// Destination of access log records.
+pub trait AccessLogSink: Send + Sync {
+    /// Writes a record, already rendered as `line`.
+    fn write(&self, record: &AccessLogRecord, line: &str);
+}
+
+/// Sink writing through the `log` facade, at the level of the record.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogSink;
+
+impl AccessLogSink for LogSink {
+    fn write(&self, record: &AccessLogRecord, line: &str) {
+        log::log!(target: "simbld_http::access", record.level(), "{}", line);
+    }
+}
+
+/// Sink appending one line per record to a file.
+#[derive(Debug)]
+pub struct FileSink {
+    file: Mutex<File>,
+}
+
+impl FileSink {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+}
+
+impl AccessLogSink for FileSink {
+    fn write(&self, _record: &AccessLogRecord, line: &str) {
+        let written = match self.file.lock() {
+            Ok(mut file) => writeln!(file, "{}", line),
+            Err(_) => return,
+        };
+        if let Err(err) = written {
+            log::warn!("Failed to write access log: {}", err);
+        }
+    }
+}
+
+/// Sink sending records to a channel; records are dropped once the receiver is gone.
+#[derive(Debug)]
+pub struct ChannelSink {
+    sender: Mutex<Sender<AccessLogRecord>>,
+}
+
+impl ChannelSink {
+    /// Creates a sink sending to `sender`.
+    pub fn new(sender: Sender<AccessLogRecord>) -> Self {
+        Self { sender: Mutex::new(sender) }
+    }
+}
+
+impl AccessLogSink for ChannelSink {
+    fn write(&self, record: &AccessLogRecord, _line: &str) {
+        if let Ok(sender) = self.sender.lock() {
+            let _ = sender.send(record.clone());
+        }
+    }
+}
+
+/// Access log configuration: a format and a sink.
+#[derive(Clone)]
+pub struct AccessLog {
+    format: AccessLogFormat,
+    sink: Arc<dyn AccessLogSink>,
+}
+
+impl AccessLog {
+    /// Creates an access log rendering records in `format` into `sink`.
+    pub fn new(format: AccessLogFormat, sink: impl AccessLogSink + 'static) -> Self {
+        Self { format, sink: Arc::new(sink) }
+    }
+
+    /// Returns the format of the lines.
+    pub fn format(&self) -> AccessLogFormat {
+        self.format
+    }
+
+    /// Renders and writes a record.
+    pub fn emit(&self, record: &AccessLogRecord) {
+        self.sink.write(record, &record.format(self.format));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::auth_middleware::{AuthMiddleware, Principal, StaticTokenValidator};
+    use crate::helpers::http_interceptor_helper::HttpInterceptor;
+    use crate::responses::ResponsesClientCodes;
+    use actix_web::{test, web, App, HttpResponse};
+    use chrono::TimeZone;
+    use std::sync::mpsc;
+
+    fn record() -> AccessLogRecord {
+        AccessLogRecord {
+            timestamp: Utc.with_ymd_and_hms(2000, 10, 10, 13, 55, 36).unwrap(),
+            method: "GET".to_string(),
+            path: "/apache_pb.gif?size=1".to_string(),
+            version: "HTTP/1.1".to_string(),
+            status: 200,
+            internal_code: None,
+            internal_name: None,
+            duration: Duration::from_millis(12),
+            bytes: Some(2326),
+            request_id: Some("req-42".to_string()),
+            client_ip: Some("127.0.0.1".to_string()),
+            user: Some("frank".to_string()),
+            user_agent: Some("Mozilla/4.08 \"beta\"".to_string()),
+            referer: None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_formats() {
+        let record = record();
+        assert_eq!(
+            record.format(AccessLogFormat::Common),
+            "127.0.0.1 - frank [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif?size=1 HTTP/1.1\" 200 2326"
+        );
+        assert!(record
+            .format(AccessLogFormat::Combined)
+            .ends_with("200 2326 \"-\" \"Mozilla/4.08 \\\"beta\\\"\""));
+
+        let json: Value = serde_json::from_str(&record.format(AccessLogFormat::Json)).unwrap();
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["duration_ms"], 12);
+        assert_eq!(json["request_id"], "req-42");
+        assert_eq!(json["level"], "INFO");
+        assert!(json.get("internal_code").is_none());
+
+        let not_found = AccessLogRecord { status: 404, ..record.clone() };
+        assert_eq!(not_found.level(), log::Level::Warn);
+        let failed = AccessLogRecord { status: 503, ..record }
+            .with_response_type(&ResponsesTypes::ClientError(ResponsesClientCodes::InvalidToken));
+        assert_eq!(failed.level(), log::Level::Error);
+        assert_eq!(failed.internal_code, Some(498));
+    }
+
+    #[actix_web::test]
+    async fn test_interceptor_emits_records() {
+        let (sender, receiver) = mpsc::channel();
+        let validator =
+            StaticTokenValidator::new().with_token("validated", Principal::new("alice"));
+        let app = test::init_service(
+            App::new()
+                .wrap(AuthMiddleware::new(validator))
+                .wrap(HttpInterceptor::new().with_access_log(AccessLog::new(
+                    AccessLogFormat::Json,
+                    ChannelSink::new(sender),
+                )))
+                .route("/orders", web::get().to(|| async { HttpResponse::Ok().body("[]") })),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/orders?page=2")
+            .insert_header(("Authorization", "Bearer validated"))
+            .insert_header(("User-Agent", "curl/8.0"))
+            .insert_header(("X-Request-ID", "req-42"))
+            .peer_addr("10.0.0.7:5000".parse().unwrap())
+            .to_request();
+        test::call_service(&app, req).await;
+
+        let record = receiver.try_recv().unwrap();
+        assert_eq!(record.method, "GET");
+        assert_eq!(record.path, "/orders?page=2");
+        assert_eq!(record.status, 200);
+        assert_eq!(record.bytes, Some(2));
+        assert_eq!(record.request_id.as_deref(), Some("req-42"));
+        assert_eq!(record.client_ip.as_deref(), Some("10.0.0.7"));
+        assert_eq!(record.user.as_deref(), Some("alice"));
+        assert_eq!(record.user_agent.as_deref(), Some("curl/8.0"));
+        assert_eq!(record.internal_code, None);
+
+        let req = test::TestRequest::get().uri("/orders").to_request();
+        test::call_service(&app, req).await;
+        let record = receiver.try_recv().unwrap();
+        assert_eq!(record.status, 401);
+        assert_eq!(record.internal_code, Some(401));
+        assert_eq!(record.internal_name.as_deref(), Some("Unauthorized"));
+        assert_eq!(record.user, None);
+    }
+
+    #[actix_web::test]
+    async fn test_file_sink() {
+        let path = std::env::temp_dir().join(format!("simbld-access-{}.log", uuid::Uuid::new_v4()));
+        let log = AccessLog::new(AccessLogFormat::Common, FileSink::open(&path).unwrap());
+        log.emit(&record());
+        log.emit(&AccessLogRecord { status: 500, ..record() });
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].contains("\" 500 2326"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,17 +1,64 @@
 //! # Authentication Middleware
 //!
 //! Provides middleware for authenticating requests by validating tokens.
-//! The middleware intercepts incoming requests, checks for a valid token,
-//! and either allows the request to proceed or returns an unauthorized response.
+//! The middleware intercepts incoming requests, extracts a token from the configured
+//! `TokenSource`s, asks its `TokenValidator` whether the token is acceptable,
+//! and either allows the request to proceed or returns a catalog error response.
+//!
+//! Authorization schemes carrying credentials rather than tokens (Basic, Digest, ...)
+//! are handled by `Authenticator`s, selected by the scheme of the `Authorization` header.
+//!
+//! Validation failures map to the catalog:
+//! - `TokenError::Missing` → `Unauthorized` (401)
+//! - `TokenError::Invalid` → `InvalidToken` (498)
+//! - `TokenError::Expired` → `LoginTimeout` (440)
+//! - `TokenError::InvalidCredentials` / `TokenError::StaleNonce` → `Unauthorized` (401)
+//! - `TokenError::Forbidden` → `Forbidden` (403)
+//!
+//! In proxy mode, every authentication failure becomes `ProxyAuthenticationRequired` (407).
+//!
+//! The validated `Principal` is stored in the request extensions, where handlers can
+//! receive it as an extractor.
+//!
+//! Signed JSON Web Tokens are handled by `jwt_helper::JwtValidator`, Basic and Digest
+//! credentials by `http_auth_helper`.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::{web, App, HttpResponse};
+//! use simbld_http::helpers::auth_middleware::{
+//!     AuthMiddleware, Principal, StaticTokenValidator, TokenSource,
+//! };
+//!
+//! let validator = StaticTokenValidator::new()
+//!     .with_token("s3cr3t", Principal::new("alice"))
+//!     .with_expired_token("old-token");
+//!
+//! let app = App::new()
+//!     .wrap(AuthMiddleware::new(validator).with_token_source(TokenSource::Cookie("session".into())))
+//!     .route(
+//!         "/me",
+//!         web::get().to(|principal: Principal| async move { HttpResponse::Ok().body(principal.id) }),
+//!     );
+//! ```
 
+use crate::helpers::tracing_helper::trace_event;
+use crate::responses::{ResponsesClientCodes, ResponsesTypes};
 use actix_web::{
     body::{BoxBody, EitherBody, MessageBody},
-    dev::{Service, ServiceRequest, ServiceResponse, Transform},
-    http::StatusCode,
-    web, Error, HttpResponse,
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header,
+    web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError,
 };
-use futures_util::future::{ok, LocalBoxFuture, Ready};
+use futures_util::future::{ok, ready, LocalBoxFuture, Ready};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 use std::task::{Context, Poll};
+use thiserror::Error;
+
+const AUTHENTICATION_TARGET: &str = "simbld_http::authentication";
 
 /// Query parameters for extracting the authentication token.
 ///
@@ -23,12 +70,315 @@ pub struct TokenParams {
     pub key: Option<String>,
 }
 
+/// Identity of an authenticated caller.
+///
+/// Authentication layers store it in the request extensions, where later middleware
+/// (e.g. rate limiting with `PrincipalKey`) and handlers can read it.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    /// Unique identifier of the caller (user id, client id, ...).
+    pub id: String,
+    /// Attributes asserted by the credential (e.g. JWT claims), empty when there are none.
+    pub claims: Map<String, Value>,
+}
+
+impl Principal {
+    /// Creates a principal with the given identifier.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self { id: id.into(), claims: Map::new() }
+    }
+
+    /// Attaches the attributes asserted by the credential.
+    pub fn with_claims(mut self, claims: Map<String, Value>) -> Self {
+        self.claims = claims;
+        self
+    }
+
+    /// Adds or replaces a single attribute (e.g. `roles` or `scope`).
+    pub fn with_claim(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.claims.insert(name.into(), value.into());
+        self
+    }
+}
+
+/// Extracts the authenticated principal in handlers.
+///
+/// Fails with `401 Unauthorized` when no authentication layer stored a principal.
+///
+impl FromRequest for Principal {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions().get::<Principal>().cloned().ok_or_else(|| TokenError::Missing.into()),
+        )
+    }
+}
+
+/// Reasons for refusing a token.
+#[derive(Debug, Clone, Copy, PartialEq, Error)]
+pub enum TokenError {
+    #[error("Missing Token")]
+    Missing,
+    #[error("Invalid Token")]
+    Invalid,
+    #[error("Token Expired")]
+    Expired,
+    #[error("Invalid Credentials")]
+    InvalidCredentials,
+    #[error("Stale Nonce")]
+    StaleNonce,
+    #[error("Forbidden")]
+    Forbidden,
+}
+
+impl TokenError {
+    /// Returns the catalog response describing the failure.
+    pub fn response_type(&self) -> ResponsesTypes {
+        ResponsesTypes::ClientError(match self {
+            TokenError::Missing => ResponsesClientCodes::Unauthorized,
+            TokenError::Invalid => ResponsesClientCodes::InvalidToken,
+            TokenError::Expired => ResponsesClientCodes::LoginTimeout,
+            TokenError::InvalidCredentials | TokenError::StaleNonce => {
+                ResponsesClientCodes::Unauthorized
+            }
+            TokenError::Forbidden => ResponsesClientCodes::Forbidden,
+        })
+    }
+
+    /// Returns the `Bearer` challenge matching the failure, if any.
+    pub fn bearer_challenge(&self) -> Option<String> {
+        match self {
+            TokenError::Missing => Some("Bearer".to_string()),
+            TokenError::Invalid | TokenError::Expired => {
+                Some("Bearer error=\"invalid_token\"".to_string())
+            }
+            TokenError::InvalidCredentials | TokenError::StaleNonce | TokenError::Forbidden => None,
+        }
+    }
+
+    /// Renders the catalog body, with `X-Auth-Error` naming the failure and one
+    /// `WWW-Authenticate` header per challenge.
+    ///
+    /// With `proxy`, authentication failures are reported as `ProxyAuthenticationRequired`
+    /// and the challenges are sent in `Proxy-Authenticate`.
+    pub fn challenge_response(&self, challenges: &[String], proxy: bool) -> HttpResponse {
+        let proxied = proxy && *self != TokenError::Forbidden;
+        let response = if proxied {
+            ResponsesTypes::ClientError(ResponsesClientCodes::ProxyAuthenticationRequired)
+        } else {
+            self.response_type()
+        };
+        let challenge_header =
+            if proxied { header::PROXY_AUTHENTICATE } else { header::WWW_AUTHENTICATE };
+
+        let mut builder = response.response_builder();
+        builder
+            .insert_header(("X-HTTP-Status-Code", response.get_code().to_string()))
+            .insert_header(("X-Auth-Error", self.to_string()));
+        if *self != TokenError::Forbidden {
+            for challenge in challenges {
+                builder.append_header((challenge_header.clone(), challenge.as_str()));
+            }
+        }
+
+        builder.content_type("application/json").body(response.as_json().to_string())
+    }
+}
+
+impl ResponseError for TokenError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        actix_web::http::StatusCode::from_u16(self.response_type().get_code())
+            .unwrap_or(actix_web::http::StatusCode::UNAUTHORIZED)
+    }
+
+    /// Renders the catalog body with the `Bearer` challenge.
+    fn error_response(&self) -> HttpResponse {
+        let challenges: Vec<String> = self.bearer_challenge().into_iter().collect();
+        self.challenge_response(&challenges, false)
+    }
+}
+
+/// Validates tokens and resolves them into a `Principal`.
+///
+/// Validation is asynchronous so validators can consult remote key sets or session stores.
+/// Any `Fn(&str) -> Result<Principal, TokenError>` closure is a validator as well.
+///
+pub trait TokenValidator {
+    fn validate(&self, token: &str) -> LocalBoxFuture<'static, Result<Principal, TokenError>>;
+}
+
+impl<F> TokenValidator for F
+where
+    F: Fn(&str) -> Result<Principal, TokenError>,
+{
+    fn validate(&self, token: &str) -> LocalBoxFuture<'static, Result<Principal, TokenError>> {
+        Box::pin(ready(self(token)))
+    }
+}
+
+/// Validator backed by a fixed set of tokens, for tests, demos and service-to-service keys.
+#[derive(Debug, Clone, Default)]
+pub struct StaticTokenValidator {
+    tokens: HashMap<String, Principal>,
+    expired: HashSet<String>,
+}
+
+impl StaticTokenValidator {
+    /// Creates a validator accepting no token.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accepts `token` and authenticates it as `principal`.
+    pub fn with_token(mut self, token: impl Into<String>, principal: Principal) -> Self {
+        self.tokens.insert(token.into(), principal);
+        self
+    }
+
+    /// Reports `token` as expired.
+    pub fn with_expired_token(mut self, token: impl Into<String>) -> Self {
+        self.expired.insert(token.into());
+        self
+    }
+}
+
+impl TokenValidator for StaticTokenValidator {
+    fn validate(&self, token: &str) -> LocalBoxFuture<'static, Result<Principal, TokenError>> {
+        let result = match self.tokens.get(token) {
+            Some(principal) => Ok(principal.clone()),
+            None if self.expired.contains(token) => Err(TokenError::Expired),
+            None => Err(TokenError::Invalid),
+        };
+        Box::pin(ready(result))
+    }
+}
+
+/// Where the middleware looks for the token.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenSource {
+    /// `Authorization: Bearer <token>`.
+    Bearer,
+    /// A query string parameter (e.g. `?key=<token>`).
+    Query(String),
+    /// A cookie.
+    Cookie(String),
+    /// A custom header (e.g. `X-API-Key`).
+    Header(String),
+}
+
+impl TokenSource {
+    /// Returns the token carried by the request, if any.
+    pub fn extract(&self, req: &ServiceRequest) -> Option<String> {
+        let token = match self {
+            TokenSource::Bearer => {
+                let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+                let (scheme, token) = value.trim().split_once(' ')?;
+                if !scheme.eq_ignore_ascii_case("bearer") {
+                    return None;
+                }
+                token.trim().to_string()
+            }
+            TokenSource::Query(name) => {
+                web::Query::<HashMap<String, String>>::from_query(req.query_string())
+                    .ok()?
+                    .remove(name)?
+            }
+            TokenSource::Cookie(name) => req.cookie(name)?.value().to_string(),
+            TokenSource::Header(name) => {
+                req.headers().get(name.as_str())?.to_str().ok()?.trim().to_string()
+            }
+        };
+        (!token.is_empty()).then_some(token)
+    }
+}
+
+/// Authenticates requests using an authorization scheme (e.g. `Basic`, `Digest`).
+///
+/// The middleware hands over the credentials following the scheme name in the
+/// `Authorization` header (`Proxy-Authorization` in proxy mode), and advertises the
+/// challenges of every authenticator when authentication fails.
+///
+pub trait Authenticator {
+    /// Returns the scheme handled, matched case-insensitively.
+    fn scheme(&self) -> &str;
+
+    /// Validates the credentials and resolves them into a `Principal`.
+    fn authenticate(
+        &self,
+        credentials: &str,
+        req: &ServiceRequest,
+    ) -> LocalBoxFuture<'static, Result<Principal, TokenError>>;
+
+    /// Returns the challenges to send after `error` (e.g. `Basic realm="api"`).
+    fn challenges(&self, error: TokenError) -> Vec<String>;
+}
+
 /// Middleware that authenticates requests by validating tokens.
 ///
 /// Implements Actix Web's `Transform` trait to intercept requests and verify
 /// authentication tokens before they reach route handlers.
 ///
-pub struct AuthMiddleware;
+/// Requests whose authorization scheme matches a configured `Authenticator` are handled
+/// by it. Otherwise tokens are looked up in the configured sources in order; the default
+/// is `Authorization: Bearer` only.
+///
+#[derive(Clone)]
+pub struct AuthMiddleware {
+    validator: Option<Rc<dyn TokenValidator>>,
+    sources: Vec<TokenSource>,
+    authenticators: Vec<Rc<dyn Authenticator>>,
+    proxy: bool,
+}
+
+impl AuthMiddleware {
+    /// Creates a middleware validating bearer tokens with `validator`.
+    pub fn new(validator: impl TokenValidator + 'static) -> Self {
+        Self {
+            validator: Some(Rc::new(validator)),
+            sources: vec![TokenSource::Bearer],
+            authenticators: Vec::new(),
+            proxy: false,
+        }
+    }
+
+    /// Creates a middleware accepting only the scheme of `authenticator`, without tokens.
+    pub fn from_authenticator(authenticator: impl Authenticator + 'static) -> Self {
+        Self {
+            validator: None,
+            sources: Vec::new(),
+            authenticators: vec![Rc::new(authenticator)],
+            proxy: false,
+        }
+    }
+
+    /// Adds a place to look for the token, after the already configured ones.
+    pub fn with_token_source(mut self, source: TokenSource) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    /// Replaces the places to look for the token.
+    pub fn with_token_sources(mut self, sources: impl IntoIterator<Item = TokenSource>) -> Self {
+        self.sources = sources.into_iter().collect();
+        self
+    }
+
+    /// Accepts an additional authorization scheme.
+    pub fn with_authenticator(mut self, authenticator: impl Authenticator + 'static) -> Self {
+        self.authenticators.push(Rc::new(authenticator));
+        self
+    }
+
+    /// Authenticates clients of a proxy: credentials are read from `Proxy-Authorization`,
+    /// and failures answer `407 Proxy Authentication Required` with `Proxy-Authenticate`.
+    pub fn as_proxy(mut self) -> Self {
+        self.proxy = true;
+        self
+    }
+}
 
 impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware
 where
@@ -42,7 +392,7 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ok(AuthMiddlewareService { service })
+        ok(AuthMiddlewareService { service: Rc::new(service), config: Rc::new(self.clone()) })
     }
 }
 
@@ -52,7 +402,8 @@ where
 /// tokens from incoming requests.
 ///
 pub struct AuthMiddlewareService<S> {
-    service: S,
+    service: Rc<S>,
+    config: Rc<AuthMiddleware>,
 }
 
 impl<S, B> Service<ServiceRequest> for AuthMiddlewareService<S>
@@ -66,53 +417,96 @@ where
 
     /// Checks if the service is ready to process a request.
     ///
-    fn poll_ready(&self, _ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
     }
 
-    /// Processes an incoming request by extracting and validating the token.
+    /// Processes an incoming request by extracting and validating the credentials.
     ///
-    /// If a valid token is found, the request is passed to the inner service.
-    /// Otherwise, an appropriate error response is returned.
+    /// If they are valid, the principal is stored in the request extensions and
+    /// the request is passed to the inner service.
+    /// Otherwise, the catalog error response is returned without reaching the route handler.
     ///
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        // We check the request parameters, we parse the QueryString: ?key=...
-        let query = web::Query::<TokenParams>::from_query(req.query_string()).ok();
-        let token = query.and_then(|q| q.key.clone()); // Clone the token to avoid invalid references
-
-        let response = match token.as_deref() {
-            Some("validated") => HttpResponse::build(StatusCode::from_u16(200).unwrap())
-                .insert_header(("X-HTTP-Status-Code", "200"))
-                .body("Authentication Successful"),
-            Some("expired") => HttpResponse::build(StatusCode::from_u16(401).unwrap())
-                .insert_header(("X-HTTP-Status-Code", "401"))
-                .insert_header(("X-Auth-Error", "Token Expired"))
-                .body("Your authentication token has expired, please log in again"),
-            Some(_) => HttpResponse::build(StatusCode::from_u16(401).unwrap())
-                .insert_header(("X-HTTP-Status-Code", "401"))
-                .insert_header(("X-Auth-Error", "Invalid Token"))
-                .body("Invalid Token"),
-            None => HttpResponse::build(StatusCode::from_u16(400).unwrap())
-                .insert_header(("X-HTTP-Status-Code", "400"))
-                .insert_header(("X-Auth-Error", "Missing Token"))
-                .body("Missing auth token"),
-        };
+        let service = self.service.clone();
+        let config = self.config.clone();
 
-        // Here, if it is 200 => we let the request pass through to the route handler
-        if response.status() == StatusCode::from_u16(200).unwrap() {
-            let fut = self.service.call(req);
-            return Box::pin(async move { fut.await.map(|res| res.map_into_left_body()) });
-        }
+        let authorization =
+            if config.proxy { header::PROXY_AUTHORIZATION } else { header::AUTHORIZATION };
+        let scheme =
+            req.headers().get(authorization).and_then(|value| value.to_str().ok()).and_then(
+                |value| {
+                    let (scheme, credentials) = value.trim().split_once(' ')?;
+                    let authenticator = config.authenticators.iter().find(|authenticator| {
+                        authenticator.scheme().eq_ignore_ascii_case(scheme)
+                    })?;
+                    Some(authenticator.authenticate(credentials.trim(), &req))
+                },
+            );
+        let pending = match (scheme, &config.validator) {
+            (Some(pending), _) => pending,
+            (None, Some(validator)) => {
+                match config.sources.iter().find_map(|source| source.extract(&req)) {
+                    Some(token) => validator.validate(&token),
+                    None => Box::pin(ready(Err(TokenError::Missing))),
+                }
+            }
+            (None, None) => Box::pin(ready(Err(TokenError::Missing))),
+        };
 
-        // Otherwise, we return the response immediately, without reaching the route handler
-        Box::pin(async move { Ok(req.into_response(response.map_into_right_body())) })
+        Box::pin(async move {
+            match pending.await {
+                Ok(principal) => {
+                    trace_event!(
+                        debug,
+                        target: AUTHENTICATION_TARGET,
+                        { principal = %principal.id, path = req.path(), decision = "accepted", },
+                        "accepted principal={} path={}",
+                        principal.id,
+                        req.path()
+                    );
+                    req.extensions_mut().insert(principal);
+                    service.call(req).await.map(|res| res.map_into_left_body())
+                }
+                Err(err) => {
+                    trace_event!(
+                        warn,
+                        target: AUTHENTICATION_TARGET,
+                        { error = %err, path = req.path(), decision = "rejected", },
+                        "rejected credentials path={} reason=\"{}\"",
+                        req.path(),
+                        err
+                    );
+                    let mut challenges: Vec<String> = config
+                        .authenticators
+                        .iter()
+                        .flat_map(|authenticator| authenticator.challenges(err))
+                        .collect();
+                    if config.validator.is_some() {
+                        challenges.extend(err.bearer_challenge());
+                    }
+                    let response = err.challenge_response(&challenges, config.proxy);
+                    Ok(req.into_response(response).map_into_right_body())
+                }
+            }
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use actix_web::{test, App};
+    use actix_web::{cookie::Cookie, http::StatusCode, test, App};
+
+    fn validator() -> StaticTokenValidator {
+        StaticTokenValidator::new()
+            .with_token("validated", Principal::new("alice"))
+            .with_expired_token("expired")
+    }
+
+    async fn whoami(principal: Principal) -> HttpResponse {
+        HttpResponse::Ok().body(principal.id)
+    }
 
     /// Tests that the AuthMiddleware correctly validates different token scenarios.
     ///
@@ -124,12 +518,14 @@ mod tests {
     ///
     #[actix_web::test]
     async fn test_auth_middleware() {
-        let app = test::init_service(App::new().wrap(AuthMiddleware).route(
-            "/protected",
-            web::get().to(|| async {
-                HttpResponse::build(StatusCode::from_u16(200).unwrap()).body("Access Granted")
-            }),
-        ))
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    AuthMiddleware::new(validator())
+                        .with_token_source(TokenSource::Query("key".into())),
+                )
+                .route("/protected", web::get().to(whoami)),
+        )
         .await;
 
         // Test case 1: Valid token
@@ -137,38 +533,103 @@ mod tests {
         let resp_valid = test::call_service(&app, req_valid).await;
         assert_eq!(
             resp_valid.status(),
-            StatusCode::from_u16(200).unwrap(),
+            StatusCode::OK,
             "Expected status code 200 for a validated token."
         );
+        assert_eq!(test::read_body(resp_valid).await, "alice");
 
         // Test case 2: Expired token
         let req_expired = test::TestRequest::get().uri("/protected?key=expired").to_request();
         let resp_expired = test::call_service(&app, req_expired).await;
         assert_eq!(
             resp_expired.status(),
-            StatusCode::from_u16(401).unwrap(),
+            StatusCode::UNAUTHORIZED,
             "Expected status code 401 for an expired token."
         );
         assert_eq!(resp_expired.headers().get("X-Auth-Error").unwrap(), "Token Expired");
+        assert_eq!(
+            resp_expired.response().extensions().get::<ResponsesTypes>(),
+            Some(&ResponsesTypes::ClientError(ResponsesClientCodes::LoginTimeout))
+        );
 
         // Test case 3: Invalid token
         let req_invalid = test::TestRequest::get().uri("/protected?key=invalid").to_request();
         let resp_invalid = test::call_service(&app, req_invalid).await;
         assert_eq!(
             resp_invalid.status(),
-            StatusCode::from_u16(401).unwrap(),
+            StatusCode::UNAUTHORIZED,
             "Expected status code 401 for an invalid token."
         );
         assert_eq!(resp_invalid.headers().get("X-Auth-Error").unwrap(), "Invalid Token");
+        let body: serde_json::Value = test::read_body_json(resp_invalid).await;
+        assert_eq!(body["details"]["internal http code"]["code"], 498);
 
         // Test case 4: Missing token
         let req_missing = test::TestRequest::get().uri("/protected").to_request();
         let resp_missing = test::call_service(&app, req_missing).await;
         assert_eq!(
             resp_missing.status(),
-            StatusCode::from_u16(400).unwrap(),
-            "Expected status code 400 for a missing token."
+            StatusCode::UNAUTHORIZED,
+            "Expected status code 401 for a missing token."
         );
         assert_eq!(resp_missing.headers().get("X-Auth-Error").unwrap(), "Missing Token");
+        assert_eq!(resp_missing.headers().get("WWW-Authenticate").unwrap(), "Bearer");
+    }
+
+    #[actix_web::test]
+    async fn test_token_sources() {
+        let sources = [
+            TokenSource::Bearer,
+            TokenSource::Cookie("session".into()),
+            TokenSource::Header("X-API-Key".into()),
+        ];
+        let app = test::init_service(
+            App::new()
+                .wrap(AuthMiddleware::new(validator()).with_token_sources(sources))
+                .route("/protected", web::get().to(whoami)),
+        )
+        .await;
+
+        let requests = [
+            test::TestRequest::get().insert_header(("Authorization", "bearer validated")),
+            test::TestRequest::get().cookie(Cookie::new("session", "validated")),
+            test::TestRequest::get().insert_header(("X-API-Key", "validated")),
+        ];
+        for req in requests {
+            let resp = test::call_service(&app, req.uri("/protected").to_request()).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        // Query tokens are not accepted unless configured
+        let req = test::TestRequest::get().uri("/protected?key=validated").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Basic credentials are not bearer tokens
+        let req = test::TestRequest::get()
+            .uri("/protected")
+            .insert_header(("Authorization", "Basic dmFsaWRhdGVk"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("X-Auth-Error").unwrap(), "Missing Token");
+    }
+
+    #[actix_web::test]
+    async fn test_closure_validator_forbidden() {
+        let banned = |token: &str| match token {
+            "banned" => Err(TokenError::Forbidden),
+            _ => Ok(Principal::new(token)),
+        };
+        let app = test::init_service(
+            App::new().wrap(AuthMiddleware::new(banned)).route("/protected", web::get().to(whoami)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/protected")
+            .insert_header(("Authorization", "Bearer banned"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
     }
 }
@@ -0,0 +1,529 @@
+//! # Authorization Guards
+//!
+//! Provides `Authorize`, a middleware checking the roles or scopes of the `Principal`
+//! stored by `AuthMiddleware` before requests reach their handlers.
+//!
+//! Roles are read from the `roles` claim and scopes from the `scope` (space-separated,
+//! as in OAuth 2.0) and `scp` claims; both accept a string or an array of strings.
+//!
+//! Decisions map to the catalog:
+//! - no principal → `Unauthorized` (401), as `AuthMiddleware` would answer
+//! - a role outside the known roles → `InvalidRole` (937)
+//! - a rule not satisfied → `Forbidden` (403), or `OperationNotAllowed` (904) when configured
+//!
+//! Every decision is logged under the `simbld_http::authorization` target for audit:
+//! grants at `info` level, refusals at `warn` level.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::{http::Method, web, App, HttpResponse};
+//! use simbld_http::helpers::authorization_helper::{AccessRule, Authorize};
+//!
+//! let app = App::new()
+//!     .service(
+//!         web::scope("/admin")
+//!             .wrap(Authorize::new(AccessRule::any_role(["admin", "ops"])))
+//!             .route("/users", web::get().to(HttpResponse::Ok)),
+//!     )
+//!     .service(
+//!         web::scope("/orders")
+//!             .wrap(
+//!                 Authorize::table()
+//!                     .method_rule(Method::GET, "/orders", AccessRule::any_scope(["orders:read"]))
+//!                     .rule("/orders", AccessRule::all_scopes(["orders:read", "orders:write"]))
+//!                     .with_known_roles(["admin", "ops", "customer"]),
+//!             )
+//!             .route("", web::get().to(HttpResponse::Ok)),
+//!     );
+//! ```
+
+use crate::helpers::auth_middleware::{Principal, TokenError};
+use crate::helpers::tracing_helper::trace_event;
+use crate::responses::{ResponsesClientCodes, ResponsesLocalApiCodes, ResponsesTypes};
+use actix_web::{
+    body::{BoxBody, EitherBody, MessageBody},
+    dev::{ResourceDef, Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    Error, HttpMessage, HttpResponse, ResponseError,
+};
+use futures_util::future::{ok, LocalBoxFuture, Ready};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use thiserror::Error;
+
+const AUDIT_TARGET: &str = "simbld_http::authorization";
+
+/// Reasons for refusing access to an authenticated principal.
+#[derive(Debug, Clone, Copy, PartialEq, Error)]
+pub enum AuthorizationError {
+    #[error("Forbidden")]
+    Forbidden,
+    #[error("Operation Not Allowed")]
+    OperationNotAllowed,
+    #[error("Invalid Role")]
+    InvalidRole,
+}
+
+impl AuthorizationError {
+    /// Returns the catalog response describing the refusal.
+    pub fn response_type(&self) -> ResponsesTypes {
+        match self {
+            AuthorizationError::Forbidden => {
+                ResponsesTypes::ClientError(ResponsesClientCodes::Forbidden)
+            }
+            AuthorizationError::OperationNotAllowed => {
+                ResponsesTypes::LocalApiError(ResponsesLocalApiCodes::OperationNotAllowed)
+            }
+            AuthorizationError::InvalidRole => {
+                ResponsesTypes::LocalApiError(ResponsesLocalApiCodes::InvalidRole)
+            }
+        }
+    }
+}
+
+impl ResponseError for AuthorizationError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        actix_web::http::StatusCode::from_u16(self.response_type().get_code())
+            .unwrap_or(actix_web::http::StatusCode::FORBIDDEN)
+    }
+
+    /// Renders the catalog body, with `X-Authorization-Error` naming the refusal.
+    fn error_response(&self) -> HttpResponse {
+        let response = self.response_type();
+        response
+            .response_builder()
+            .insert_header(("X-HTTP-Status-Code", response.get_code().to_string()))
+            .insert_header(("X-Authorization-Error", self.to_string()))
+            .content_type("application/json")
+            .body(response.as_json().to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Subject {
+    Role,
+    Scope,
+}
+
+/// Roles or scopes a principal must hold, with any/all semantics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessRule {
+    subject: Subject,
+    all: bool,
+    values: Vec<String>,
+}
+
+impl AccessRule {
+    fn new<I, V>(subject: Subject, all: bool, values: I) -> Self
+    where
+        I: IntoIterator<Item = V>,
+        V: Into<String>,
+    {
+        Self { subject, all, values: values.into_iter().map(Into::into).collect() }
+    }
+
+    /// Requires at least one of `roles`.
+    pub fn any_role<I: IntoIterator<Item = V>, V: Into<String>>(roles: I) -> Self {
+        Self::new(Subject::Role, false, roles)
+    }
+
+    /// Requires every one of `roles`.
+    pub fn all_roles<I: IntoIterator<Item = V>, V: Into<String>>(roles: I) -> Self {
+        Self::new(Subject::Role, true, roles)
+    }
+
+    /// Requires at least one of `scopes`.
+    pub fn any_scope<I: IntoIterator<Item = V>, V: Into<String>>(scopes: I) -> Self {
+        Self::new(Subject::Scope, false, scopes)
+    }
+
+    /// Requires every one of `scopes`.
+    pub fn all_scopes<I: IntoIterator<Item = V>, V: Into<String>>(scopes: I) -> Self {
+        Self::new(Subject::Scope, true, scopes)
+    }
+
+    /// Checks the rule against the roles or scopes held.
+    pub fn is_satisfied(&self, held: &HashSet<String>) -> bool {
+        if self.all {
+            self.values.iter().all(|value| held.contains(value))
+        } else {
+            self.values.iter().any(|value| held.contains(value))
+        }
+    }
+}
+
+impl fmt::Display for AccessRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let quantifier = if self.all { "all" } else { "any" };
+        let subject = match self.subject {
+            Subject::Role => "roles",
+            Subject::Scope => "scopes",
+        };
+        write!(f, "{} {} of [{}]", quantifier, subject, self.values.join(", "))
+    }
+}
+
+/// Collects the strings of the given claims, splitting string values on whitespace.
+fn claim_values(principal: &Principal, claims: &[String]) -> HashSet<String> {
+    claims
+        .iter()
+        .filter_map(|claim| principal.claims.get(claim))
+        .flat_map(|value| match value {
+            Value::String(values) => values.split_whitespace().map(str::to_string).collect(),
+            Value::Array(values) => {
+                values.iter().filter_map(|value| value.as_str().map(str::to_string)).collect()
+            }
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+#[derive(Clone)]
+struct RouteRule {
+    method: Option<Method>,
+    path: ResourceDef,
+    rule: AccessRule,
+}
+
+/// Middleware authorizing requests from the roles and scopes of the principal.
+///
+/// Built either around a single rule applying to everything it wraps (`new`), or as a
+/// route table (`table`) whose first entry matching the method and path applies.
+/// Requests matching no entry are let through, unless a fallback rule is set.
+///
+#[derive(Clone)]
+pub struct Authorize {
+    routes: Vec<RouteRule>,
+    fallback: Option<AccessRule>,
+    known_roles: Option<HashSet<String>>,
+    denial: AuthorizationError,
+    role_claims: Vec<String>,
+    scope_claims: Vec<String>,
+}
+
+impl Authorize {
+    /// Creates a guard applying `rule` to every request.
+    pub fn new(rule: AccessRule) -> Self {
+        Self { fallback: Some(rule), ..Self::table() }
+    }
+
+    /// Creates an empty route table.
+    pub fn table() -> Self {
+        Self {
+            routes: Vec::new(),
+            fallback: None,
+            known_roles: None,
+            denial: AuthorizationError::Forbidden,
+            role_claims: vec!["roles".to_string()],
+            scope_claims: vec!["scope".to_string(), "scp".to_string()],
+        }
+    }
+
+    /// Applies `rule` to requests whose path matches `pattern` or lies below it
+    /// (e.g. `/orders/{id}`), whatever their method.
+    pub fn rule(mut self, pattern: &str, rule: AccessRule) -> Self {
+        self.routes.push(RouteRule { method: None, path: ResourceDef::prefix(pattern), rule });
+        self
+    }
+
+    /// Applies `rule` to `method` requests whose path matches `pattern` or lies below it.
+    pub fn method_rule(mut self, method: Method, pattern: &str, rule: AccessRule) -> Self {
+        self.routes.push(RouteRule {
+            method: Some(method),
+            path: ResourceDef::prefix(pattern),
+            rule,
+        });
+        self
+    }
+
+    /// Applies `rule` to requests matching no route.
+    pub fn otherwise(mut self, rule: AccessRule) -> Self {
+        self.fallback = Some(rule);
+        self
+    }
+
+    /// Refuses principals holding a role outside `roles` with `InvalidRole`.
+    pub fn with_known_roles<I: IntoIterator<Item = V>, V: Into<String>>(
+        mut self,
+        roles: I,
+    ) -> Self {
+        self.known_roles = Some(roles.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Reports unsatisfied rules as `OperationNotAllowed` (904) instead of `Forbidden` (403).
+    pub fn deny_as_operation_not_allowed(mut self) -> Self {
+        self.denial = AuthorizationError::OperationNotAllowed;
+        self
+    }
+
+    /// Reads roles from `claim` instead of `roles`.
+    pub fn with_roles_claim(mut self, claim: impl Into<String>) -> Self {
+        self.role_claims = vec![claim.into()];
+        self
+    }
+
+    /// Reads scopes from `claim` instead of `scope` and `scp`.
+    pub fn with_scopes_claim(mut self, claim: impl Into<String>) -> Self {
+        self.scope_claims = vec![claim.into()];
+        self
+    }
+
+    /// Decides whether `principal` may send a `method` request to `path`.
+    pub fn authorize(
+        &self,
+        principal: &Principal,
+        method: &Method,
+        path: &str,
+    ) -> Result<(), AuthorizationError> {
+        let roles = claim_values(principal, &self.role_claims);
+        if let Some(known) = &self.known_roles {
+            if let Some(unknown) = roles.iter().find(|role| !known.contains(*role)) {
+                trace_event!(
+                    warn,
+                    target: AUDIT_TARGET,
+                    { principal = %principal.id, method = %method, path, decision = "denied", },
+                    "denied principal={} method={} path={} reason=\"unknown role {}\"",
+                    principal.id,
+                    method,
+                    path,
+                    unknown
+                );
+                return Err(AuthorizationError::InvalidRole);
+            }
+        }
+
+        let rule = self
+            .routes
+            .iter()
+            .find(|route| {
+                route.method.as_ref().is_none_or(|expected| expected == method)
+                    && route.path.is_match(path)
+            })
+            .map(|route| &route.rule)
+            .or(self.fallback.as_ref());
+        let Some(rule) = rule else {
+            return Ok(());
+        };
+
+        let held = match rule.subject {
+            Subject::Role => roles,
+            Subject::Scope => claim_values(principal, &self.scope_claims),
+        };
+        if rule.is_satisfied(&held) {
+            trace_event!(
+                info,
+                target: AUDIT_TARGET,
+                { principal = %principal.id, method = %method, path, decision = "granted", },
+                "granted principal={} method={} path={} rule=\"{}\"",
+                principal.id,
+                method,
+                path,
+                rule
+            );
+            Ok(())
+        } else {
+            trace_event!(
+                warn,
+                target: AUDIT_TARGET,
+                { principal = %principal.id, method = %method, path, decision = "denied", },
+                "denied principal={} method={} path={} rule=\"{}\"",
+                principal.id,
+                method,
+                path,
+                rule
+            );
+            Err(self.denial)
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Authorize
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Transform = AuthorizeService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuthorizeService { service: Rc::new(service), config: Rc::new(self.clone()) })
+    }
+}
+
+/// Service created by `Authorize` to process requests.
+pub struct AuthorizeService<S> {
+    service: Rc<S>,
+    config: Rc<Authorize>,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthorizeService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    /// Passes authorized requests to the inner service, and answers the others with the
+    /// catalog error response.
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let decision = match req.extensions().get::<Principal>() {
+            Some(principal) => self
+                .config
+                .authorize(principal, req.method(), req.path())
+                .map_err(|err| err.error_response()),
+            None => Err(TokenError::Missing.error_response()),
+        };
+
+        match decision {
+            Ok(()) => {
+                let service = self.service.clone();
+                Box::pin(async move { service.call(req).await.map(|res| res.map_into_left_body()) })
+            }
+            Err(response) => {
+                Box::pin(async move { Ok(req.into_response(response).map_into_right_body()) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::auth_middleware::{AuthMiddleware, StaticTokenValidator};
+    use actix_web::{http::StatusCode, test, web, App};
+    use serde_json::json;
+
+    fn validator() -> StaticTokenValidator {
+        StaticTokenValidator::new()
+            .with_token("admin", Principal::new("alice").with_claim("roles", json!(["admin"])))
+            .with_token(
+                "reader",
+                Principal::new("bob")
+                    .with_claim("roles", "customer")
+                    .with_claim("scope", "orders:read profile"),
+            )
+            .with_token(
+                "writer",
+                Principal::new("carol")
+                    .with_claim("roles", "customer")
+                    .with_claim("scp", json!(["orders:read", "orders:write"])),
+            )
+            .with_token("intruder", Principal::new("mallory").with_claim("roles", "superuser"))
+    }
+
+    fn request(method: Method, uri: &str, token: &str) -> test::TestRequest {
+        test::TestRequest::default()
+            .method(method)
+            .uri(uri)
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+    }
+
+    #[actix_web::test]
+    async fn test_role_guard() {
+        let app = test::init_service(
+            App::new().wrap(AuthMiddleware::new(validator())).service(
+                web::scope("/admin")
+                    .wrap(Authorize::new(AccessRule::any_role(["admin", "ops"])))
+                    .route("/users", web::get().to(HttpResponse::Ok)),
+            ),
+        )
+        .await;
+
+        let resp =
+            test::call_service(&app, request(Method::GET, "/admin/users", "admin").to_request())
+                .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp =
+            test::call_service(&app, request(Method::GET, "/admin/users", "reader").to_request())
+                .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(resp.headers().get("X-Authorization-Error").unwrap(), "Forbidden");
+    }
+
+    #[actix_web::test]
+    async fn test_scope_table() {
+        let table = Authorize::table()
+            .method_rule(Method::GET, "/orders", AccessRule::any_scope(["orders:read"]))
+            .rule("/orders", AccessRule::all_scopes(["orders:read", "orders:write"]))
+            .with_known_roles(["admin", "customer"])
+            .deny_as_operation_not_allowed();
+        let app = test::init_service(
+            App::new()
+                .wrap(table)
+                .wrap(AuthMiddleware::new(validator()))
+                .route("/orders/{id}", web::get().to(HttpResponse::Ok))
+                .route("/orders/{id}", web::delete().to(HttpResponse::Ok))
+                .route("/health", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        assert_eq!(
+            test::call_service(&app, request(Method::GET, "/orders/1", "reader").to_request())
+                .await
+                .status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            test::call_service(&app, request(Method::DELETE, "/orders/1", "writer").to_request())
+                .await
+                .status(),
+            StatusCode::OK
+        );
+
+        let resp =
+            test::call_service(&app, request(Method::DELETE, "/orders/1", "reader").to_request())
+                .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            resp.response().extensions().get::<ResponsesTypes>(),
+            Some(&ResponsesTypes::LocalApiError(ResponsesLocalApiCodes::OperationNotAllowed))
+        );
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["details"]["internal http code"]["code"], 904);
+
+        // Routes outside the table only need a principal
+        assert_eq!(
+            test::call_service(&app, request(Method::GET, "/health", "admin").to_request())
+                .await
+                .status(),
+            StatusCode::OK
+        );
+
+        let resp =
+            test::call_service(&app, request(Method::GET, "/health", "intruder").to_request())
+                .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(resp.headers().get("X-Authorization-Error").unwrap(), "Invalid Role");
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["details"]["internal http code"]["code"], 937);
+    }
+
+    #[actix_web::test]
+    async fn test_missing_principal() {
+        let app = test::init_service(
+            App::new()
+                .wrap(Authorize::new(AccessRule::all_roles(["admin"])))
+                .route("/admin", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/admin").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
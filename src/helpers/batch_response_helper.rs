@@ -0,0 +1,323 @@
+//! # Multi-Status Batch Responses
+//!
+//! This module provides the `BatchResponse` builder, which collects the per-item results of a
+//! bulk operation and renders them as a single HTTP response.
+//!
+//! Each item carries its own identifier, catalog code (`ResponsesTypes`) and JSON payload.
+//! When the items disagree, the batch is answered with `207 Multi-Status`; when every item
+//! shares the same code, the batch collapses to that code instead. Items that were already
+//! enumerated by a previous response can be recorded as `208 Already Reported`.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use serde_json::json;
+//! use simbld_http::helpers::batch_response_helper::BatchResponse;
+//! use simbld_http::responses::ResponsesTypes;
+//! use simbld_http::{ResponsesClientCodes, ResponsesSuccessCodes};
+//!
+//! let batch = BatchResponse::new()
+//!     .add("item-1", ResponsesTypes::Success(ResponsesSuccessCodes::Created), json!({"id": 1}))
+//!     .add("item-2", ResponsesTypes::ClientError(ResponsesClientCodes::Conflict), json!(null))
+//!     .already_reported("item-3");
+//!
+//! assert_eq!(batch.status_code(), 207);
+//! assert_eq!(batch.summary()["Success"], 2);
+//! assert_eq!(batch.summary()["Client Error"], 1);
+//! ```
+
+use crate::responses::{ResponsesSuccessCodes, ResponsesTypes};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, Responder};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// Output format used when a `BatchResponse` is rendered by Actix.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BatchFormat {
+    /// JSON document with a summary and one entry per item.
+    #[default]
+    Json,
+    /// WebDAV-style `<D:multistatus>` XML document (RFC 4918).
+    Xml,
+}
+
+/// The result of a single item within a batch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchItem {
+    /// Caller-supplied identifier of the item (rendered as `href` in XML).
+    pub id: String,
+    /// Catalog code describing the outcome of the item.
+    pub response: ResponsesTypes,
+    /// Item-specific payload, `Value::Null` when there is nothing to return.
+    pub payload: Value,
+}
+
+impl BatchItem {
+    /// Converts the item into its JSON representation.
+    pub fn as_json(&self) -> Value {
+        let http_code = self.response.as_tuple();
+        json!({
+            "id": self.id,
+            "code": http_code.standard_code,
+            "name": http_code.standard_name,
+            "description": http_code.unified_description,
+            "internal_code": http_code.internal_code,
+            "internal_name": http_code.internal_name,
+            "family": self.response.get_family(),
+            "data": self.payload,
+        })
+    }
+}
+
+/// Builder collecting per-item results of a bulk operation.
+#[derive(Debug, Clone)]
+pub struct BatchResponse {
+    items: Vec<BatchItem>,
+    collapse: bool,
+    format: BatchFormat,
+}
+
+impl BatchResponse {
+    /// Creates an empty batch. Uniform batches collapse to a single code by default.
+    pub fn new() -> Self {
+        Self { items: Vec::new(), collapse: true, format: BatchFormat::Json }
+    }
+
+    /// Records the result of one item.
+    pub fn add(mut self, id: impl Into<String>, response: ResponsesTypes, payload: Value) -> Self {
+        self.push(id, response, payload);
+        self
+    }
+
+    /// Records the result of one item without consuming the builder.
+    pub fn push(&mut self, id: impl Into<String>, response: ResponsesTypes, payload: Value) {
+        self.items.push(BatchItem { id: id.into(), response, payload });
+    }
+
+    /// Records an item that was already enumerated in a previous multi-status response (208).
+    pub fn already_reported(self, id: impl Into<String>) -> Self {
+        self.add(id, ResponsesTypes::Success(ResponsesSuccessCodes::AlreadyReported), Value::Null)
+    }
+
+    /// Enables or disables collapsing a uniform batch to its shared code.
+    pub fn collapse_uniform(mut self, collapse: bool) -> Self {
+        self.collapse = collapse;
+        self
+    }
+
+    /// Selects the format used when the batch is returned from a handler.
+    pub fn format(mut self, format: BatchFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Returns the recorded items in insertion order.
+    pub fn items(&self) -> &[BatchItem] {
+        &self.items
+    }
+
+    /// Returns the code shared by every item, if the batch is non-empty and uniform.
+    pub fn uniform_response(&self) -> Option<ResponsesTypes> {
+        let first = self.items.first()?.response;
+        self.items.iter().all(|item| item.response == first).then_some(first)
+    }
+
+    /// Returns the response type of the batch as a whole.
+    ///
+    /// This is the shared code of a uniform batch when collapsing is enabled,
+    /// and `MultiStatus` (207) otherwise.
+    pub fn response_type(&self) -> ResponsesTypes {
+        match self.uniform_response() {
+            Some(shared) if self.collapse => shared,
+            _ => ResponsesTypes::Success(ResponsesSuccessCodes::MultiStatus),
+        }
+    }
+
+    /// Returns the standard HTTP status code of the batch as a whole.
+    pub fn status_code(&self) -> u16 {
+        self.response_type().get_code()
+    }
+
+    /// Counts the items per status family (see `ResponsesTypes::get_family`).
+    pub fn summary(&self) -> BTreeMap<&'static str, usize> {
+        let mut summary = BTreeMap::new();
+        for item in &self.items {
+            *summary.entry(item.response.get_family()).or_insert(0) += 1;
+        }
+        summary
+    }
+
+    /// Converts the batch into a JSON document.
+    pub fn as_json(&self) -> Value {
+        let response_type = self.response_type();
+        json!({
+            "code": response_type.get_code(),
+            "description": response_type.get_description(),
+            "summary": {
+                "total": self.items.len(),
+                "families": self.summary(),
+            },
+            "items": self.items.iter().map(BatchItem::as_json).collect::<Vec<_>>(),
+        })
+    }
+
+    /// Converts the batch into a WebDAV `multistatus` XML document.
+    ///
+    /// Non-null payloads are embedded as escaped JSON in a `<S:data>` element.
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from(
+            r#"<?xml version="1.0" encoding="utf-8"?><D:multistatus xmlns:D="DAV:" xmlns:S="urn:simbld-http">"#,
+        );
+        for item in &self.items {
+            let http_code = item.response.as_tuple();
+            xml.push_str("<D:response>");
+            xml.push_str(&format!("<D:href>{}</D:href>", escape_xml(&item.id)));
+            xml.push_str(&format!(
+                "<D:status>HTTP/1.1 {} {}</D:status>",
+                http_code.standard_code, http_code.standard_name
+            ));
+            if let Some(internal_code) = http_code.internal_code {
+                xml.push_str(&format!("<S:internal-code>{}</S:internal-code>", internal_code));
+            }
+            if !item.payload.is_null() {
+                xml.push_str(&format!(
+                    "<S:data>{}</S:data>",
+                    escape_xml(&item.payload.to_string())
+                ));
+            }
+            xml.push_str(&format!(
+                "<D:responsedescription>{}</D:responsedescription>",
+                escape_xml(http_code.unified_description)
+            ));
+            xml.push_str("</D:response>");
+        }
+        xml.push_str("</D:multistatus>");
+        xml
+    }
+
+    /// Renders the batch as an `HttpResponse` in the configured format.
+    pub fn into_http_response(self) -> HttpResponse {
+        let status =
+            StatusCode::from_u16(self.status_code()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        match self.format {
+            BatchFormat::Json => HttpResponse::build(status)
+                .content_type("application/json")
+                .body(self.as_json().to_string()),
+            BatchFormat::Xml => HttpResponse::build(status)
+                .content_type("application/xml; charset=utf-8")
+                .body(self.to_xml()),
+        }
+    }
+}
+
+impl Default for BatchResponse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Implements Actix's Responder trait so a batch can be returned directly from a handler.
+impl Responder for BatchResponse {
+    type Body = actix_web::body::BoxBody;
+
+    fn respond_to(self, _: &HttpRequest) -> HttpResponse<Self::Body> {
+        self.into_http_response()
+    }
+}
+
+/// Escapes the five XML special characters.
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::responses::{ResponsesClientCodes, ResponsesLocalApiCodes};
+    use actix_web::{
+        test::{call_service, init_service, TestRequest},
+        web, App,
+    };
+
+    fn mixed_batch() -> BatchResponse {
+        BatchResponse::new()
+            .add("a", ResponsesTypes::Success(ResponsesSuccessCodes::Created), json!({"id": 1}))
+            .add(
+                "b",
+                ResponsesTypes::LocalApiError(ResponsesLocalApiCodes::InvalidAmount),
+                json!({"field": "amount"}),
+            )
+            .already_reported("c")
+    }
+
+    #[test]
+    fn test_mixed_batch_is_multi_status() {
+        let batch = mixed_batch();
+        assert_eq!(batch.status_code(), 207);
+        assert_eq!(batch.uniform_response(), None);
+        assert_eq!(batch.items()[2].response.get_code(), 208);
+    }
+
+    #[test]
+    fn test_uniform_batch_collapses() {
+        let conflict = ResponsesTypes::ClientError(ResponsesClientCodes::Conflict);
+        let batch =
+            BatchResponse::new().add("a", conflict, Value::Null).add("b", conflict, Value::Null);
+        assert_eq!(batch.status_code(), 409);
+        assert_eq!(batch.clone().collapse_uniform(false).status_code(), 207);
+    }
+
+    #[test]
+    fn test_empty_batch_is_multi_status() {
+        assert_eq!(BatchResponse::new().status_code(), 207);
+    }
+
+    #[test]
+    fn test_summary_counts_per_family() {
+        let summary = mixed_batch().summary();
+        assert_eq!(summary.get("Success"), Some(&2));
+        assert_eq!(summary.get("Local API Error"), Some(&1));
+        assert_eq!(summary.get("Client Error"), None);
+    }
+
+    #[test]
+    fn test_as_json() {
+        let json_value = mixed_batch().as_json();
+        assert_eq!(json_value["code"], 207);
+        assert_eq!(json_value["summary"]["total"], 3);
+        assert_eq!(json_value["items"][1]["code"], 400);
+        assert_eq!(json_value["items"][1]["internal_code"], 912);
+        assert_eq!(json_value["items"][1]["data"]["field"], "amount");
+    }
+
+    #[test]
+    fn test_to_xml() {
+        let xml = mixed_batch().to_xml();
+        assert!(xml.starts_with("<?xml"));
+        assert!(xml.contains("<D:href>a</D:href><D:status>HTTP/1.1 201 Created</D:status>"));
+        assert!(xml.contains("<S:internal-code>912</S:internal-code>"));
+        assert!(xml.contains("<S:data>{&quot;id&quot;:1}</S:data>"));
+        assert!(xml.contains("<D:status>HTTP/1.1 208 Already Reported</D:status>"));
+    }
+
+    #[actix_web::test]
+    async fn test_batch_responder() {
+        let app =
+            init_service(App::new().route(
+                "/bulk",
+                web::post().to(|| async { mixed_batch().format(BatchFormat::Xml) }),
+            ))
+            .await;
+
+        let req = TestRequest::post().uri("/bulk").to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::MULTI_STATUS);
+        assert_eq!(resp.headers().get("content-type").unwrap(), "application/xml; charset=utf-8");
+    }
+}
@@ -0,0 +1,640 @@
+//! # Circuit Breaker
+//!
+//! Provides `CircuitBreaker`, which stops calling an upstream that keeps failing, usable
+//! both as a middleware and as a wrapper around async upstream calls, and
+//! `CircuitBreakers`, which keeps one breaker per upstream.
+//!
+//! A breaker is **closed** while the upstream behaves: calls go through and their outcomes
+//! are recorded over a sliding window of the last calls. Once enough calls were recorded,
+//! a failure rate or slow-call rate reaching its threshold **opens** it: calls are refused
+//! with `ServiceUnavailable` (503) and a `Retry-After` computed from the remaining open
+//! time. After that time, it turns **half-open** and lets a few trial calls through: it
+//! closes again if they all succeed, and reopens on the first failure.
+//!
+//! Outcomes are classified with the catalog families: `Server Error` (5xx) and
+//! `Service Error` (6xx) codes are failures, any other code (4xx included) a success.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use simbld_http::helpers::circuit_breaker_helper::{CircuitBreakers, Outcome};
+//! use std::time::Duration;
+//!
+//! # async fn example() {
+//! let breakers = CircuitBreakers::new().with_open_duration(Duration::from_secs(30));
+//! let payments = breakers.get("payments");
+//!
+//! let result = payments
+//!     .call_with(async { 502u16 }, |status| Outcome::from_code(*status))
+//!     .await;
+//! # }
+//! ```
+
+use crate::helpers::rate_limit_helper::{Clock, SystemClock};
+use crate::responses::{ResponsesServerCodes, ResponsesTypes};
+use crate::utils::populate_metadata::status_family;
+use actix_web::{
+    body::{BoxBody, EitherBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header,
+    Error, HttpResponse, ResponseError,
+};
+use futures_util::future::{ok, LocalBoxFuture, Ready};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use thiserror::Error;
+
+/// State of a circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through.
+    Closed,
+    /// Calls are refused.
+    Open,
+    /// A few trial calls go through.
+    HalfOpen,
+}
+
+/// Outcome of an upstream call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+impl Outcome {
+    /// Classifies a catalog code: `Server Error` and `Service Error` codes are failures.
+    pub fn from_code(code: u16) -> Self {
+        match status_family(code) {
+            "Server Error" | "Service Error" => Outcome::Failure,
+            _ => Outcome::Success,
+        }
+    }
+
+    /// Classifies a catalog response from its internal code.
+    pub fn from_response(response: &ResponsesTypes) -> Self {
+        let code = response.as_tuple();
+        Self::from_code(code.internal_code.unwrap_or(code.standard_code))
+    }
+}
+
+/// Refusal of a call by an open circuit.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("Circuit {name} Open")]
+pub struct CircuitOpen {
+    /// Name of the upstream.
+    pub name: String,
+    /// Time until calls may be tried again.
+    pub retry_after: Duration,
+}
+
+impl ResponseError for CircuitOpen {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        actix_web::http::StatusCode::SERVICE_UNAVAILABLE
+    }
+
+    /// Renders the catalog body, with `Retry-After` in whole seconds (rounded up).
+    fn error_response(&self) -> HttpResponse {
+        let response = ResponsesTypes::ServerError(ResponsesServerCodes::ServiceUnavailable);
+        let retry_after =
+            self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0);
+        response
+            .response_builder()
+            .insert_header(("X-HTTP-Status-Code", response.get_code().to_string()))
+            .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+            .insert_header(("X-Circuit-Breaker", self.name.clone()))
+            .content_type("application/json")
+            .body(response.as_json().to_string())
+    }
+}
+
+/// Error of a call made through a circuit breaker.
+#[derive(Debug, Error)]
+pub enum CircuitError<E> {
+    /// The circuit refused the call.
+    #[error(transparent)]
+    Open(CircuitOpen),
+    /// The upstream call failed.
+    #[error("{0}")]
+    Upstream(E),
+}
+
+/// Thresholds and timings of a circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CircuitBreakerSettings {
+    /// Failure rate, between 0 and 1, opening the circuit.
+    pub failure_rate_threshold: f64,
+    /// Slow-call rate, between 0 and 1, opening the circuit.
+    pub slow_call_rate_threshold: f64,
+    /// Duration from which a call counts as slow.
+    pub slow_call_duration: Duration,
+    /// Number of last calls the rates are computed over.
+    pub window_size: usize,
+    /// Number of calls recorded before the rates are considered.
+    pub minimum_calls: usize,
+    /// Time the circuit stays open before trial calls.
+    pub open_duration: Duration,
+    /// Number of successful trial calls closing the circuit, at least one.
+    pub half_open_calls: usize,
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            failure_rate_threshold: 0.5,
+            slow_call_rate_threshold: 1.0,
+            slow_call_duration: Duration::from_secs(5),
+            window_size: 20,
+            minimum_calls: 10,
+            open_duration: Duration::from_secs(30),
+            half_open_calls: 3,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Phase {
+    Closed { calls: VecDeque<(Outcome, bool)> },
+    Open { until: Duration },
+    HalfOpen { started: usize, succeeded: usize },
+}
+
+#[derive(Debug)]
+struct Circuit {
+    phase: Phase,
+    /// Incremented on every transition, so that calls started before it are ignored.
+    generation: u64,
+}
+
+/// Circuit breaker of one upstream.
+///
+/// Clones share the same circuit.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    name: Arc<str>,
+    settings: CircuitBreakerSettings,
+    clock: Arc<dyn Clock>,
+    circuit: Arc<Mutex<Circuit>>,
+}
+
+impl fmt::Debug for CircuitBreaker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("name", &self.name)
+            .field("settings", &self.settings)
+            .field("state", &self.state())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Authorization to make one call, whose outcome must be recorded.
+///
+/// A permit dropped without outcome (e.g. a cancelled call) frees its trial slot.
+pub struct CallPermit {
+    breaker: CircuitBreaker,
+    generation: u64,
+    started: Duration,
+    recorded: bool,
+}
+
+impl CallPermit {
+    /// Records the outcome of the call, timed from the permit creation.
+    pub fn record(mut self, outcome: Outcome) {
+        self.recorded = true;
+        let elapsed = self.breaker.clock.now().saturating_sub(self.started);
+        self.breaker.record(self.generation, outcome, elapsed);
+    }
+}
+
+impl Drop for CallPermit {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.breaker.release(self.generation);
+        }
+    }
+}
+
+impl CircuitBreaker {
+    /// Creates a closed breaker with the default settings.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self::with_settings(name, CircuitBreakerSettings::default())
+    }
+
+    /// Creates a closed breaker with the given settings.
+    pub fn with_settings(name: impl Into<String>, settings: CircuitBreakerSettings) -> Self {
+        Self {
+            name: Arc::from(name.into()),
+            settings,
+            clock: Arc::new(SystemClock),
+            circuit: Arc::new(Mutex::new(Circuit {
+                phase: Phase::Closed { calls: VecDeque::new() },
+                generation: 0,
+            })),
+        }
+    }
+
+    /// Uses `clock` to time calls and open periods.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Returns the name of the upstream.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the settings of the breaker.
+    pub fn settings(&self) -> &CircuitBreakerSettings {
+        &self.settings
+    }
+
+    /// Returns the current state, an open circuit whose time elapsed being half-open.
+    pub fn state(&self) -> CircuitState {
+        let circuit = self.circuit.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match circuit.phase {
+            Phase::Closed { .. } => CircuitState::Closed,
+            Phase::Open { until } if self.clock.now() < until => CircuitState::Open,
+            Phase::Open { .. } | Phase::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Asks for the permission to make a call.
+    pub fn acquire(&self) -> Result<CallPermit, CircuitOpen> {
+        let now = self.clock.now();
+        let mut circuit = self.circuit.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Phase::Open { until } = circuit.phase {
+            if now < until {
+                return Err(self.open(until - now));
+            }
+            circuit.phase = Phase::HalfOpen { started: 0, succeeded: 0 };
+            circuit.generation += 1;
+        }
+        if let Phase::HalfOpen { started, .. } = &mut circuit.phase {
+            if *started >= self.settings.half_open_calls.max(1) {
+                // Trial calls are expected to end within the slow-call duration
+                return Err(self.open(self.settings.slow_call_duration));
+            }
+            *started += 1;
+        }
+        Ok(CallPermit {
+            breaker: self.clone(),
+            generation: circuit.generation,
+            started: now,
+            recorded: false,
+        })
+    }
+
+    /// Makes a call, any error being a failure.
+    pub async fn call<F, T, E>(&self, fut: F) -> Result<T, CircuitError<E>>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let permit = self.acquire().map_err(CircuitError::Open)?;
+        let result = fut.await;
+        permit.record(if result.is_ok() { Outcome::Success } else { Outcome::Failure });
+        result.map_err(CircuitError::Upstream)
+    }
+
+    /// Makes a call whose result is classified by `classify`.
+    pub async fn call_with<F, T>(
+        &self,
+        fut: F,
+        classify: impl FnOnce(&T) -> Outcome,
+    ) -> Result<T, CircuitOpen>
+    where
+        F: Future<Output = T>,
+    {
+        let permit = self.acquire()?;
+        let result = fut.await;
+        permit.record(classify(&result));
+        Ok(result)
+    }
+
+    fn open(&self, retry_after: Duration) -> CircuitOpen {
+        CircuitOpen { name: self.name.to_string(), retry_after }
+    }
+
+    fn record(&self, generation: u64, outcome: Outcome, elapsed: Duration) {
+        let now = self.clock.now();
+        let settings = &self.settings;
+        let mut circuit = self.circuit.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if circuit.generation != generation {
+            return;
+        }
+        let trip = match &mut circuit.phase {
+            Phase::Closed { calls } => {
+                calls.push_back((outcome, elapsed >= settings.slow_call_duration));
+                while calls.len() > settings.window_size.max(1) {
+                    calls.pop_front();
+                }
+                let total = calls.len() as f64;
+                let failures = calls.iter().filter(|(outcome, _)| *outcome == Outcome::Failure);
+                let slow = calls.iter().filter(|(_, slow)| *slow);
+                calls.len() >= settings.minimum_calls
+                    && (failures.count() as f64 / total >= settings.failure_rate_threshold
+                        || slow.count() as f64 / total >= settings.slow_call_rate_threshold)
+            }
+            Phase::Open { .. } => false,
+            Phase::HalfOpen { succeeded, .. } => {
+                if outcome == Outcome::Failure {
+                    true
+                } else {
+                    *succeeded += 1;
+                    if *succeeded >= settings.half_open_calls.max(1) {
+                        circuit.phase = Phase::Closed { calls: VecDeque::new() };
+                        circuit.generation += 1;
+                    }
+                    false
+                }
+            }
+        };
+        if trip {
+            circuit.phase = Phase::Open { until: now + settings.open_duration };
+            circuit.generation += 1;
+        }
+    }
+
+    fn release(&self, generation: u64) {
+        let mut circuit = self.circuit.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if circuit.generation != generation {
+            return;
+        }
+        if let Phase::HalfOpen { started, .. } = &mut circuit.phase {
+            *started = started.saturating_sub(1);
+        }
+    }
+}
+
+/// Circuit breakers of several upstreams, created on first use with shared settings.
+///
+/// Clones share the same breakers.
+#[derive(Clone)]
+pub struct CircuitBreakers {
+    settings: CircuitBreakerSettings,
+    clock: Arc<dyn Clock>,
+    breakers: Arc<Mutex<HashMap<String, CircuitBreaker>>>,
+}
+
+impl Default for CircuitBreakers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CircuitBreakers {
+    /// Creates an empty registry with the default settings.
+    pub fn new() -> Self {
+        Self {
+            settings: CircuitBreakerSettings::default(),
+            clock: Arc::new(SystemClock),
+            breakers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Sets the settings of the breakers created from now on.
+    pub fn with_settings(mut self, settings: CircuitBreakerSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Sets the failure rate opening circuits.
+    pub fn with_failure_rate_threshold(mut self, rate: f64) -> Self {
+        self.settings.failure_rate_threshold = rate;
+        self
+    }
+
+    /// Sets the slow-call rate opening circuits, and the duration making a call slow.
+    pub fn with_slow_call_threshold(mut self, rate: f64, duration: Duration) -> Self {
+        self.settings.slow_call_rate_threshold = rate;
+        self.settings.slow_call_duration = duration;
+        self
+    }
+
+    /// Sets the time circuits stay open before trial calls.
+    pub fn with_open_duration(mut self, duration: Duration) -> Self {
+        self.settings.open_duration = duration;
+        self
+    }
+
+    /// Uses `clock` for the breakers created from now on.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Returns the breaker of `upstream`, creating it if needed.
+    pub fn get(&self, upstream: &str) -> CircuitBreaker {
+        let mut breakers = self.breakers.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        breakers
+            .entry(upstream.to_string())
+            .or_insert_with(|| CircuitBreaker {
+                clock: self.clock.clone(),
+                ..CircuitBreaker::with_settings(upstream, self.settings)
+            })
+            .clone()
+    }
+
+    /// Returns the state of every known upstream.
+    pub fn states(&self) -> HashMap<String, CircuitState> {
+        let breakers = self.breakers.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        breakers.iter().map(|(name, breaker)| (name.clone(), breaker.state())).collect()
+    }
+}
+
+/// Guards the wrapped services as one upstream: their 5xx and 6xx responses (from the
+/// internal code of catalog responses) and errors count as failures.
+impl<S, B> Transform<S, ServiceRequest> for CircuitBreaker
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Transform = CircuitBreakerService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CircuitBreakerService { service: Rc::new(service), breaker: self.clone() })
+    }
+}
+
+/// Service created by `CircuitBreaker` to process requests.
+pub struct CircuitBreakerService<S> {
+    service: Rc<S>,
+    breaker: CircuitBreaker,
+}
+
+impl<S, B> Service<ServiceRequest> for CircuitBreakerService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    /// Short-circuits requests while the circuit is open, and records the outcome of the
+    /// others.
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let permit = match self.breaker.acquire() {
+            Ok(permit) => permit,
+            Err(open) => {
+                return Box::pin(async move {
+                    Ok(req.into_response(open.error_response()).map_into_right_body())
+                })
+            }
+        };
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let result = fut.await;
+            let outcome = match &result {
+                Ok(res) => match res.response().extensions().get::<ResponsesTypes>() {
+                    Some(response) => Outcome::from_response(response),
+                    None => Outcome::from_code(res.status().as_u16()),
+                },
+                Err(_) => Outcome::Failure,
+            };
+            permit.record(outcome);
+            result.map(|res| res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::rate_limit_helper::ManualClock;
+    use crate::responses::{ResponsesClientCodes, ResponsesServiceCodes};
+    use actix_web::{http::StatusCode, test, web, App};
+    use std::cell::Cell;
+
+    fn settings() -> CircuitBreakerSettings {
+        CircuitBreakerSettings {
+            window_size: 4,
+            minimum_calls: 4,
+            half_open_calls: 2,
+            open_duration: Duration::from_secs(10),
+            slow_call_duration: Duration::from_secs(2),
+            ..CircuitBreakerSettings::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_state_transitions() {
+        let clock = ManualClock::new();
+        let breaker =
+            CircuitBreaker::with_settings("payments", settings()).with_clock(clock.clone());
+
+        // Client errors do not trip the circuit, server and service errors do
+        for code in [404, 409, 422, 400] {
+            breaker.call_with(async { code }, |code| Outcome::from_code(*code)).await.unwrap();
+        }
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        for code in [502, 600] {
+            breaker.call_with(async { code }, |code| Outcome::from_code(*code)).await.unwrap();
+        }
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        clock.advance(Duration::from_millis(2500));
+        let open = breaker.call(async { Ok::<_, ()>(()) }).await.unwrap_err();
+        let CircuitError::Open(open) = open else { panic!("expected an open circuit") };
+        assert_eq!(open.retry_after, Duration::from_millis(7500));
+        let resp = open.error_response();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers().get("Retry-After").unwrap(), "8");
+
+        // Half-open: a failed trial reopens the circuit
+        clock.advance(Duration::from_secs(8));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(matches!(
+            breaker.call(async { Err::<(), _>("down") }).await,
+            Err(CircuitError::Upstream("down"))
+        ));
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        // Half-open: successful trials close it, extra concurrent trials are refused
+        clock.advance(Duration::from_secs(10));
+        let first = breaker.acquire().unwrap();
+        let second = breaker.acquire().unwrap();
+        assert!(breaker.acquire().is_err());
+        drop(second);
+        let second = breaker.acquire().unwrap();
+        first.record(Outcome::Success);
+        second.record(Outcome::Success);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        // Without trial calls configured, one trial is still allowed
+        let settings = CircuitBreakerSettings { half_open_calls: 0, ..settings() };
+        let breaker = CircuitBreaker::with_settings("ledger", settings).with_clock(clock.clone());
+        for _ in 0..4 {
+            breaker.acquire().unwrap().record(Outcome::Failure);
+        }
+        clock.advance(Duration::from_secs(10));
+        let trial = breaker.acquire().unwrap();
+        assert!(breaker.acquire().is_err());
+        trial.record(Outcome::Success);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[actix_web::test]
+    async fn test_slow_calls_and_registry() {
+        let clock = ManualClock::new();
+        let breakers = CircuitBreakers::new()
+            .with_settings(settings())
+            .with_slow_call_threshold(0.5, Duration::from_secs(2))
+            .with_clock(clock.clone());
+        let search = breakers.get("search");
+
+        for _ in 0..4 {
+            let permit = search.acquire().unwrap();
+            clock.advance(Duration::from_secs(3));
+            permit.record(Outcome::Success);
+        }
+        assert_eq!(breakers.get("search").state(), CircuitState::Open);
+        assert_eq!(breakers.get("billing").state(), CircuitState::Closed);
+        assert_eq!(breakers.states().len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_middleware() {
+        let failing = Rc::new(Cell::new(true));
+        let flag = failing.clone();
+        let app = test::init_service(
+            App::new().wrap(CircuitBreaker::with_settings("inventory", settings())).route(
+                "/",
+                web::get().to(move || {
+                    let response = if flag.get() {
+                        ResponsesTypes::ServiceError(ResponsesServiceCodes::ReadingError)
+                    } else {
+                        ResponsesTypes::ClientError(ResponsesClientCodes::NotFound)
+                    };
+                    async move { response.into_http_response() }
+                }),
+            ),
+        )
+        .await;
+
+        for _ in 0..4 {
+            let resp =
+                test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+            assert_ne!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        }
+        failing.set(false);
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers().get("Retry-After").unwrap(), "10");
+        assert_eq!(resp.headers().get("X-Circuit-Breaker").unwrap(), "inventory");
+    }
+}
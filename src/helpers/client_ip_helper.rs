@@ -0,0 +1,474 @@
+//! # Client IP Resolution
+//!
+//! Provides `ClientIpResolver`, a middleware resolving the address of the client behind
+//! trusted reverse proxies, and storing it as a `ClientIp` in the request extensions.
+//!
+//! Forwarding headers are only believed when the peer is a configured trusted proxy, and
+//! only the header those proxies maintain is read (`ForwardedHeader`): `X-Forwarded-For`
+//! by default, or `Forwarded` (RFC 7239). The other one is passed through unchanged by the
+//! proxies, so it is left to the client and ignored. The chain is then walked right to
+//! left: every hop appended by a trusted proxy is skipped, and the first untrusted address
+//! is the client. Clients can prepend any address to the header, but not hide behind it.
+//! Chains longer than the configured limit are refused with `TooManyForwardedIPAddresses`
+//! (445).
+//!
+//! Every IP-based feature of the crate (rate-limit keys, IP policies, maintenance
+//! allow-lists, access logs) reads the address through `client_ip`, which falls back to the
+//! peer address when the resolver is not installed. The resolver must therefore wrap those
+//! middlewares, i.e. be registered after them.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::{web, App, HttpResponse};
+//! use simbld_http::helpers::client_ip_helper::{ClientIp, ClientIpResolver, ForwardedHeader};
+//! use simbld_http::helpers::ip_policy_helper::IpPolicy;
+//!
+//! let resolver = ClientIpResolver::new()
+//!     .with_trusted_proxies(["10.0.0.0/8", "fd00::/8"])
+//!     .unwrap()
+//!     .with_forwarded_header(ForwardedHeader::XForwardedFor)
+//!     .with_max_forwarded(5);
+//!
+//! let app = App::new()
+//!     .wrap(IpPolicy::new().deny(["203.0.113.0/24"]).unwrap())
+//!     .wrap(resolver)
+//!     .route(
+//!         "/",
+//!         web::get().to(|client: ClientIp| async move {
+//!             HttpResponse::Ok().body(client.to_string())
+//!         }),
+//!     );
+//! ```
+
+use crate::helpers::ip_policy_helper::{IpListError, IpNet, IpRangeSet};
+use crate::responses::{ResponsesClientCodes, ResponsesTypes};
+use actix_web::{
+    body::{BoxBody, EitherBody, MessageBody},
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{self, HeaderMap, HeaderName},
+    Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use futures_util::future::{ok, ready, LocalBoxFuture, Ready};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use thiserror::Error;
+
+/// Default maximum number of addresses in a forwarding chain.
+pub const DEFAULT_MAX_FORWARDED: usize = 10;
+
+/// Reasons for refusing the forwarding headers of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Error)]
+pub enum ClientIpError {
+    #[error("Too Many Forwarded IP Addresses")]
+    TooManyForwardedIPAddresses,
+}
+
+impl ClientIpError {
+    /// Returns the catalog response describing the refusal.
+    pub fn response_type(&self) -> ResponsesTypes {
+        match self {
+            ClientIpError::TooManyForwardedIPAddresses => {
+                ResponsesTypes::ClientError(ResponsesClientCodes::TooManyForwardedIPAddresses)
+            }
+        }
+    }
+}
+
+impl ResponseError for ClientIpError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        actix_web::http::StatusCode::from_u16(self.response_type().get_code())
+            .unwrap_or(actix_web::http::StatusCode::BAD_REQUEST)
+    }
+
+    /// Renders the catalog body, with `X-Client-IP-Error` naming the refusal.
+    fn error_response(&self) -> HttpResponse {
+        let response = self.response_type();
+        response
+            .response_builder()
+            .insert_header(("X-HTTP-Status-Code", response.get_code().to_string()))
+            .insert_header(("X-Client-IP-Error", self.to_string()))
+            .content_type("application/json")
+            .body(response.as_json().to_string())
+    }
+}
+
+/// The resolved address of the client, stored in the request extensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp {
+    /// Address of the client.
+    pub ip: IpAddr,
+    /// Address of the peer of the connection, the last proxy when the request was forwarded.
+    pub peer: Option<IpAddr>,
+}
+
+impl ClientIp {
+    /// Whether the address was taken from forwarding headers.
+    pub fn forwarded(&self) -> bool {
+        self.peer != Some(self.ip)
+    }
+}
+
+impl fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.ip.fmt(f)
+    }
+}
+
+/// Returns the address of the client of `req`: the one resolved by `ClientIpResolver`,
+/// or the peer address when the resolver is not installed.
+pub fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    if let Some(client) = req.extensions().get::<ClientIp>() {
+        return Some(client.ip);
+    }
+    req.peer_addr().map(|addr| addr.ip())
+}
+
+/// Extracts the client address, falling back to the peer address.
+///
+/// Fails with `400 Bad Request` when the address is unknown, as in some test requests.
+impl FromRequest for ClientIp {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let resolved = req.extensions().get::<ClientIp>().copied();
+        let client = resolved.or_else(|| {
+            let peer = req.peer_addr()?.ip();
+            Some(ClientIp { ip: peer, peer: Some(peer) })
+        });
+        ready(client.ok_or_else(|| actix_web::error::ErrorBadRequest("Unknown client address")))
+    }
+}
+
+/// Forwarding headers set by the trusted proxies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ForwardedHeader {
+    /// `X-Forwarded-For`, with `X-Forwarded-Proto` and `X-Forwarded-Host`.
+    #[default]
+    XForwardedFor,
+    /// `Forwarded` (RFC 7239), with its `for`, `proto` and `host` parameters.
+    Forwarded,
+}
+
+impl ForwardedHeader {
+    /// Returns the hops of the forwarding chain, oldest first.
+    pub fn chain(&self, headers: &HeaderMap) -> Vec<String> {
+        match self {
+            ForwardedHeader::XForwardedFor => header_list(headers, header::X_FORWARDED_FOR),
+            ForwardedHeader::Forwarded => header_list(headers, header::FORWARDED)
+                .iter()
+                .map(|element| forwarded_param(element, "for").unwrap_or_default())
+                .collect(),
+        }
+    }
+
+    /// Returns the scheme forwarded by the nearest proxy.
+    pub fn proto(&self, headers: &HeaderMap) -> Option<String> {
+        self.last_value(headers, HeaderName::from_static("x-forwarded-proto"), "proto")
+    }
+
+    /// Returns the host forwarded by the nearest proxy.
+    pub fn host(&self, headers: &HeaderMap) -> Option<String> {
+        self.last_value(headers, HeaderName::from_static("x-forwarded-host"), "host")
+    }
+
+    fn last_value(&self, headers: &HeaderMap, name: HeaderName, param: &str) -> Option<String> {
+        match self {
+            ForwardedHeader::XForwardedFor => header_list(headers, name).pop(),
+            ForwardedHeader::Forwarded => {
+                forwarded_param(&header_list(headers, header::FORWARDED).pop()?, param)
+            }
+        }
+        .filter(|value| !value.is_empty())
+    }
+}
+
+/// Middleware resolving the client address behind trusted proxies.
+#[derive(Debug, Clone)]
+pub struct ClientIpResolver {
+    trusted: Arc<IpRangeSet>,
+    header: ForwardedHeader,
+    max_forwarded: usize,
+}
+
+impl Default for ClientIpResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientIpResolver {
+    /// Creates a resolver trusting no proxy, so the client is always the peer.
+    ///
+    /// Trusted proxies are expected to set `X-Forwarded-For` unless configured otherwise.
+    pub fn new() -> Self {
+        Self {
+            trusted: Arc::new(IpRangeSet::new()),
+            header: ForwardedHeader::default(),
+            max_forwarded: DEFAULT_MAX_FORWARDED,
+        }
+    }
+
+    /// Trusts the forwarding headers set by proxies in the given ranges.
+    pub fn with_trusted_proxies<I, R>(mut self, ranges: I) -> Result<Self, IpListError>
+    where
+        I: IntoIterator<Item = R>,
+        R: AsRef<str>,
+    {
+        let trusted = Arc::make_mut(&mut self.trusted);
+        for range in ranges {
+            trusted.insert(range.as_ref().parse::<IpNet>()?);
+        }
+        Ok(self)
+    }
+
+    /// Sets the header the trusted proxies maintain; the other one is ignored.
+    pub fn with_forwarded_header(mut self, header: ForwardedHeader) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Returns the header the trusted proxies maintain.
+    pub fn forwarded_header(&self) -> ForwardedHeader {
+        self.header
+    }
+
+    /// Sets the maximum number of addresses in a forwarding chain.
+    pub fn with_max_forwarded(mut self, max_forwarded: usize) -> Self {
+        self.max_forwarded = max_forwarded;
+        self
+    }
+
+    /// Whether `addr` belongs to a trusted proxy.
+    pub fn is_trusted(&self, addr: IpAddr) -> bool {
+        self.trusted.contains(addr)
+    }
+
+    /// Resolves the client address from the peer address and the forwarding headers.
+    pub fn resolve(
+        &self,
+        peer: Option<IpAddr>,
+        headers: &HeaderMap,
+    ) -> Result<Option<ClientIp>, ClientIpError> {
+        let Some(peer) = peer else {
+            return Ok(None);
+        };
+        if !self.is_trusted(peer) {
+            return Ok(Some(ClientIp { ip: peer, peer: Some(peer) }));
+        }
+
+        let chain = self.header.chain(headers);
+        if chain.len() > self.max_forwarded {
+            return Err(ClientIpError::TooManyForwardedIPAddresses);
+        }
+
+        let mut client = peer;
+        for hop in chain.iter().rev() {
+            match parse_node(hop) {
+                Some(addr) => {
+                    client = addr;
+                    if !self.is_trusted(addr) {
+                        break;
+                    }
+                }
+                // Obfuscated or malformed hops end the walk at the proxy which appended them
+                None => break,
+            }
+        }
+        Ok(Some(ClientIp { ip: client, peer: Some(peer) }))
+    }
+}
+
+/// Returns the comma-separated elements of every `name` header, in order.
+fn header_list(headers: &HeaderMap, name: HeaderName) -> Vec<String> {
+    headers
+        .get_all(name)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|element| element.trim().to_string())
+        .filter(|element| !element.is_empty())
+        .collect()
+}
+
+/// Returns the parameter `name` of a `Forwarded` element, such as `for=192.0.2.43;proto=https`.
+fn forwarded_param(element: &str, name: &str) -> Option<String> {
+    element
+        .split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(param, _)| param.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+}
+
+/// Parses a hop such as `192.0.2.43`, `"192.0.2.43:4711"` or `"[2001:db8::17]:4711"`.
+///
+/// Returns `None` for `unknown`, obfuscated identifiers (`_hidden`) and malformed values.
+fn parse_node(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim().trim_matches('"');
+    if let Ok(addr) = hop.parse::<IpAddr>() {
+        return Some(addr);
+    }
+    if let Ok(addr) = hop.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    hop.strip_prefix('[')?.split(']').next()?.parse().ok()
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ClientIpResolver
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Transform = ClientIpResolverService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ClientIpResolverService { service: Rc::new(service), resolver: self.clone() })
+    }
+}
+
+pub struct ClientIpResolverService<S> {
+    service: Rc<S>,
+    resolver: ClientIpResolver,
+}
+
+impl<S, B> Service<ServiceRequest> for ClientIpResolverService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    /// Stores the resolved `ClientIp`, or answers oversized chains with the catalog response.
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let peer = req.peer_addr().map(|addr| addr.ip());
+        match self.resolver.resolve(peer, req.headers()) {
+            Ok(client) => {
+                if let Some(client) = client {
+                    req.extensions_mut().insert(client);
+                }
+                let fut = self.service.call(req);
+                Box::pin(async move { fut.await.map(|res| res.map_into_left_body()) })
+            }
+            Err(err) => Box::pin(async move {
+                Ok(req.into_response(err.error_response()).map_into_right_body())
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App};
+
+    fn addr(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(name.clone(), value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[actix_web::test]
+    async fn test_resolve_chain() {
+        let resolver = ClientIpResolver::new().with_trusted_proxies(["10.0.0.0/8"]).unwrap();
+        let xff = |value| headers(&[(header::X_FORWARDED_FOR, value)]);
+
+        // Untrusted peers cannot spoof their address
+        let client = resolver.resolve(Some(addr("198.51.100.9")), &xff("1.2.3.4")).unwrap();
+        assert_eq!(client.unwrap().ip, addr("198.51.100.9"));
+        assert!(!client.unwrap().forwarded());
+
+        // Hops appended by trusted proxies are skipped, prepended ones ignored
+        let chain = xff("6.6.6.6, 203.0.113.5, 10.1.1.1");
+        let client = resolver.resolve(Some(addr("10.0.0.2")), &chain).unwrap().unwrap();
+        assert_eq!(client.ip, addr("203.0.113.5"));
+        assert_eq!(client.peer, Some(addr("10.0.0.2")));
+
+        // A `Forwarded` header injected by the client is ignored behind XFF-only proxies
+        let both = headers(&[
+            (header::FORWARDED, "for=1.2.3.4"),
+            (header::X_FORWARDED_FOR, "203.0.113.5"),
+        ]);
+        let client = resolver.resolve(Some(addr("10.0.0.2")), &both).unwrap().unwrap();
+        assert_eq!(client.ip, addr("203.0.113.5"));
+
+        // Proxies setting `Forwarded`, with ports, quotes and brackets
+        let rfc = resolver.clone().with_forwarded_header(ForwardedHeader::Forwarded);
+        let both = headers(&[
+            (header::FORWARDED, r#"for="[2001:db8::17]:4711";proto=https, for=10.2.2.2"#),
+            (header::X_FORWARDED_FOR, "6.6.6.6"),
+        ]);
+        let client = rfc.resolve(Some(addr("10.0.0.2")), &both).unwrap().unwrap();
+        assert_eq!(client.ip, addr("2001:db8::17"));
+
+        // Scheme and host come from the element of the nearest proxy
+        let nearest = headers(&[(header::FORWARDED, "proto=http, proto=https;host=example.com")]);
+        assert_eq!(ForwardedHeader::Forwarded.proto(&nearest).as_deref(), Some("https"));
+        assert_eq!(ForwardedHeader::Forwarded.host(&nearest).as_deref(), Some("example.com"));
+        assert_eq!(ForwardedHeader::XForwardedFor.proto(&nearest), None);
+
+        // Obfuscated hops stop the walk at the proxy which appended them
+        let hidden = headers(&[(header::FORWARDED, "for=1.2.3.4, for=_hidden, for=10.3.3.3")]);
+        let client = rfc.resolve(Some(addr("10.0.0.2")), &hidden).unwrap().unwrap();
+        assert_eq!(client.ip, addr("10.3.3.3"));
+
+        let long = xff("1.1.1.1, 2.2.2.2, 3.3.3.3");
+        let err = resolver.with_max_forwarded(2).resolve(Some(addr("10.0.0.2")), &long);
+        assert_eq!(err, Err(ClientIpError::TooManyForwardedIPAddresses));
+    }
+
+    #[actix_web::test]
+    async fn test_resolver_middleware() {
+        let resolver = ClientIpResolver::new()
+            .with_trusted_proxies(["127.0.0.1"])
+            .unwrap()
+            .with_max_forwarded(2);
+        let app = test::init_service(
+            App::new()
+                .wrap(resolver)
+                .route("/", web::get().to(|client: ClientIp| async move { client.to_string() })),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .peer_addr("127.0.0.1:4000".parse().unwrap())
+            .insert_header((header::X_FORWARDED_FOR, "203.0.113.5"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(test::read_body(res).await, "203.0.113.5");
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .peer_addr("127.0.0.1:4000".parse().unwrap())
+            .insert_header((header::X_FORWARDED_FOR, "1.1.1.1, 2.2.2.2, 3.3.3.3"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            res.headers().get("X-Client-IP-Error").unwrap(),
+            "Too Many Forwarded IP Addresses"
+        );
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["details"]["internal http code"]["code"], 445);
+    }
+}
@@ -0,0 +1,498 @@
+//! # Conditional GET
+//!
+//! Provides entity validators (`ETag` and `Last-Modified`) and their evaluation against
+//! `If-None-Match` / `If-Modified-Since`, answering unchanged resources with
+//! `NotModified` (304) and no body.
+//!
+//! - `Validators` holds the validators of a representation, computes ETags by hashing a
+//!   body (`strong_etag` / `weak_etag`), and evaluates the conditions of a request.
+//! - `Conditional` wraps any `Responder`, such as `CustomResponse`, with its validators.
+//! - `ConditionalGet` is a middleware doing the same for every `GET` and `HEAD` answered
+//!   with `200 OK`. It keeps the validators set by the handler, and otherwise hashes
+//!   buffered bodies. Streaming bodies are never buffered: the handler supplies their
+//!   validators, and the stream is dropped unread when the client copy is still fresh.
+//!
+//! As required by RFC 9110, `If-None-Match` takes precedence over `If-Modified-Since`,
+//! and entity tags are compared weakly.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::{web, App, HttpResponse};
+//! use simbld_http::helpers::conditional_helper::ConditionalGet;
+//! use simbld_http::responses::CustomResponse;
+//! use std::time::{Duration, UNIX_EPOCH};
+//!
+//! let app = App::new()
+//!     .wrap(ConditionalGet::new())
+//!     .route("/hashed", web::get().to(|| async { HttpResponse::Ok().body("hashed") }))
+//!     .route(
+//!         "/report",
+//!         web::get().to(|| async {
+//!             CustomResponse::new(200, "OK", "{\"total\": 42}", "Report")
+//!                 .conditional()
+//!                 .with_last_modified(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+//!         }),
+//!     );
+//! ```
+
+use crate::responses::{ResponsesRedirectionCodes, ResponsesTypes};
+use actix_web::{
+    body::{self, BodySize, BoxBody, EitherBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{self, EntityTag, HeaderMap, HttpDate},
+        Method, StatusCode,
+    },
+    Error, HttpRequest, HttpResponse, Responder,
+};
+use futures_util::future::{ok, LocalBoxFuture, Ready};
+use sha2::{Digest, Sha256};
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default maximum size of the bodies `ConditionalGet` buffers to hash.
+pub const DEFAULT_MAX_HASHED_SIZE: u64 = 1024 * 1024;
+
+/// Headers a `304` repeats from the response it stands for.
+const NOT_MODIFIED_HEADERS: [header::HeaderName; 6] = [
+    header::CACHE_CONTROL,
+    header::CONTENT_LOCATION,
+    header::DATE,
+    header::ETAG,
+    header::EXPIRES,
+    header::VARY,
+];
+
+/// Returns a strong ETag hashing `body`.
+pub fn strong_etag(body: &[u8]) -> EntityTag {
+    EntityTag::new_strong(body_hash(body))
+}
+
+/// Returns a weak ETag hashing `body`.
+pub fn weak_etag(body: &[u8]) -> EntityTag {
+    EntityTag::new_weak(body_hash(body))
+}
+
+/// Truncates `time` to whole seconds, the precision of HTTP dates, so that it compares
+/// equal to the date a client echoes back.
+pub fn http_date_precision(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => UNIX_EPOCH + Duration::from_secs(elapsed.as_secs()),
+        Err(_) => time,
+    }
+}
+
+/// Hex encoding of the first 128 bits of the SHA-256 of `body`.
+fn body_hash(body: &[u8]) -> String {
+    Sha256::digest(body)[..16].iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Validators of a representation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Validators {
+    pub etag: Option<EntityTag>,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// Creates empty validators.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the `ETag` and `Last-Modified` headers of a response.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let value = |name| headers.get(name).and_then(|value| value.to_str().ok());
+        Self {
+            etag: value(header::ETAG).and_then(|etag| etag.parse().ok()),
+            last_modified: value(header::LAST_MODIFIED)
+                .and_then(|date| date.parse::<HttpDate>().ok())
+                .map(SystemTime::from),
+        }
+    }
+
+    /// Sets the ETag.
+    pub fn with_etag(mut self, etag: EntityTag) -> Self {
+        self.etag = Some(etag);
+        self
+    }
+
+    /// Sets the last modification time.
+    pub fn with_last_modified(mut self, last_modified: SystemTime) -> Self {
+        self.last_modified = Some(last_modified);
+        self
+    }
+
+    /// Whether no validator is set.
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// Whether the client copy described by the request `headers` is still fresh.
+    pub fn is_not_modified(&self, method: &Method, headers: &HeaderMap) -> bool {
+        if method != Method::GET && method != Method::HEAD {
+            return false;
+        }
+        let value = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+        if let Some(if_none_match) = value(header::IF_NONE_MATCH) {
+            // `*` matches any current representation, whether it has an entity tag or not
+            return if_none_match.split(',').map(str::trim).any(|candidate| {
+                candidate == "*"
+                    || self.etag.as_ref().is_some_and(|etag| {
+                        candidate.parse::<EntityTag>().is_ok_and(|tag| tag.weak_eq(etag))
+                    })
+            });
+        }
+
+        match (value(header::IF_MODIFIED_SINCE), self.last_modified) {
+            (Some(since), Some(last_modified)) => since
+                .parse::<HttpDate>()
+                .is_ok_and(|since| http_date_precision(last_modified) <= SystemTime::from(since)),
+            _ => false,
+        }
+    }
+
+    /// Sets the `ETag` and `Last-Modified` headers.
+    pub fn insert_headers(&self, headers: &mut HeaderMap) {
+        if let Some(Ok(etag)) = self.etag.as_ref().map(|etag| etag.to_string().parse()) {
+            headers.insert(header::ETAG, etag);
+        }
+        if let Some(Ok(date)) =
+            self.last_modified.map(|date| HttpDate::from(date).to_string().parse())
+        {
+            headers.insert(header::LAST_MODIFIED, date);
+        }
+    }
+
+    /// Builds the catalog `304 Not Modified`, with the validators and no body.
+    pub fn not_modified_response(&self) -> HttpResponse {
+        let mut response = ResponsesTypes::Redirection(ResponsesRedirectionCodes::NotModified)
+            .response_builder()
+            .finish();
+        self.insert_headers(response.headers_mut());
+        response
+    }
+
+    /// Answers `304` when the request conditions hold, otherwise adds the validators.
+    pub fn evaluate<B: MessageBody + 'static>(
+        &self,
+        req: &HttpRequest,
+        mut response: HttpResponse<B>,
+    ) -> HttpResponse<EitherBody<B, BoxBody>> {
+        if response.status() != StatusCode::OK {
+            return response.map_into_left_body();
+        }
+        self.insert_headers(response.headers_mut());
+        if self.is_not_modified(req.method(), req.headers()) {
+            return not_modified(self, response.headers()).map_into_right_body();
+        }
+        response.map_into_left_body()
+    }
+}
+
+/// Builds the `304` standing for a response carrying `headers`.
+fn not_modified(validators: &Validators, headers: &HeaderMap) -> HttpResponse {
+    let mut response = validators.not_modified_response();
+    for name in NOT_MODIFIED_HEADERS {
+        if response.headers().contains_key(&name) {
+            continue;
+        }
+        for value in headers.get_all(&name) {
+            response.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+    response
+}
+
+/// A `Responder` answered conditionally according to its validators.
+#[derive(Debug, Clone)]
+pub struct Conditional<R> {
+    inner: R,
+    validators: Validators,
+}
+
+impl<R> Conditional<R> {
+    /// Wraps `inner` with the given validators.
+    pub fn new(inner: R, validators: Validators) -> Self {
+        Self { inner, validators }
+    }
+
+    /// Sets the ETag.
+    pub fn with_etag(mut self, etag: EntityTag) -> Self {
+        self.validators.etag = Some(etag);
+        self
+    }
+
+    /// Sets the last modification time.
+    pub fn with_last_modified(mut self, last_modified: SystemTime) -> Self {
+        self.validators.last_modified = Some(last_modified);
+        self
+    }
+
+    /// Returns the validators.
+    pub fn validators(&self) -> &Validators {
+        &self.validators
+    }
+}
+
+impl<R: Responder> Responder for Conditional<R>
+where
+    R::Body: 'static,
+{
+    type Body = EitherBody<R::Body, BoxBody>;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let response = self.inner.respond_to(req);
+        self.validators.evaluate(req, response)
+    }
+}
+
+/// Middleware answering `GET` and `HEAD` requests conditionally.
+#[derive(Debug, Clone, Copy)]
+pub struct ConditionalGet {
+    weak: bool,
+    max_hashed_size: u64,
+}
+
+impl Default for ConditionalGet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConditionalGet {
+    /// Creates the middleware, computing strong ETags for bodies up to 1 MiB.
+    pub fn new() -> Self {
+        Self { weak: false, max_hashed_size: DEFAULT_MAX_HASHED_SIZE }
+    }
+
+    /// Computes weak ETags, for representations which are not byte-for-byte stable.
+    pub fn with_weak_etags(mut self) -> Self {
+        self.weak = true;
+        self
+    }
+
+    /// Sets the maximum size of the bodies buffered to compute their ETag.
+    pub fn with_max_hashed_size(mut self, max_hashed_size: u64) -> Self {
+        self.max_hashed_size = max_hashed_size;
+        self
+    }
+
+    /// Whether `ConditionalGet` computes an ETag for a body of the given size.
+    fn hashes(&self, size: BodySize) -> bool {
+        matches!(size, BodySize::Sized(size) if size <= self.max_hashed_size)
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ConditionalGet
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Transform = ConditionalGetService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ConditionalGetService { service: Rc::new(service), config: *self })
+    }
+}
+
+/// Service created by `ConditionalGet` to process requests.
+pub struct ConditionalGetService<S> {
+    service: Rc<S>,
+    config: ConditionalGet,
+}
+
+impl<S, B> Service<ServiceRequest> for ConditionalGetService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    /// Adds validators to `200` answers, and replaces them with `304` when still fresh.
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let config = self.config;
+        let conditional = req.method() == Method::GET || req.method() == Method::HEAD;
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            if !conditional || res.status() != StatusCode::OK {
+                return Ok(res.map_into_left_body());
+            }
+
+            let validators = Validators::from_headers(res.headers());
+            if validators.etag.is_some() || !config.hashes(res.response().body().size()) {
+                if !validators.is_not_modified(res.request().method(), res.request().headers()) {
+                    return Ok(res.map_into_left_body());
+                }
+                let not_modified = not_modified(&validators, res.headers());
+                return Ok(res.into_response(not_modified).map_into_right_body());
+            }
+
+            // Buffer the body to hash it
+            let (req, res) = res.into_parts();
+            let (mut head, body) = res.into_parts();
+            let body = body::to_bytes(body)
+                .await
+                .map_err(|err| actix_web::error::ErrorInternalServerError(err.into()))?;
+            let etag = if config.weak { weak_etag(&body) } else { strong_etag(&body) };
+            let validators = validators.with_etag(etag);
+            validators.insert_headers(head.headers_mut());
+
+            let res = match validators.is_not_modified(req.method(), req.headers()) {
+                true => not_modified(&validators, head.headers()),
+                false => head.set_body(BoxBody::new(body)),
+            };
+            Ok(ServiceResponse::new(req, res).map_into_right_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::responses::CustomResponse;
+    use actix_web::{test, web, App};
+    use futures_util::stream;
+
+    fn modified_at() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    #[actix_web::test]
+    async fn test_validators_evaluation() {
+        let validators =
+            Validators::new().with_etag(strong_etag(b"body")).with_last_modified(modified_at());
+        let headers = |pairs: &[(header::HeaderName, String)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(name.clone(), value.parse().unwrap());
+            }
+            headers
+        };
+        let etag = strong_etag(b"body");
+        let since = |time: SystemTime| HttpDate::from(time).to_string();
+
+        let fresh = headers(&[(header::IF_NONE_MATCH, format!("\"x\", W/{}", etag))]);
+        assert!(validators.is_not_modified(&Method::GET, &fresh));
+        assert!(!validators.is_not_modified(&Method::POST, &fresh));
+        let any = headers(&[(header::IF_NONE_MATCH, "*".to_string())]);
+        assert!(validators.is_not_modified(&Method::HEAD, &any));
+        let dated = Validators::new().with_last_modified(modified_at());
+        assert!(dated.is_not_modified(&Method::GET, &any));
+        assert!(!dated.is_not_modified(&Method::GET, &fresh));
+
+        let later = headers(&[(header::IF_MODIFIED_SINCE, since(modified_at()))]);
+        assert!(validators.is_not_modified(&Method::GET, &later));
+        let earlier =
+            headers(&[(header::IF_MODIFIED_SINCE, since(modified_at() - Duration::from_secs(1)))]);
+        assert!(!validators.is_not_modified(&Method::GET, &earlier));
+
+        // Sub-second modification times match the date sent in `Last-Modified`
+        let precise =
+            Validators::new().with_last_modified(modified_at() + Duration::from_millis(500));
+        let mut sent = HeaderMap::new();
+        precise.insert_headers(&mut sent);
+        let echoed = headers(&[(header::IF_MODIFIED_SINCE, since(modified_at()))]);
+        assert_eq!(
+            sent.get(header::LAST_MODIFIED).unwrap().to_str().unwrap(),
+            since(modified_at())
+        );
+        assert!(precise.is_not_modified(&Method::GET, &echoed));
+        assert!(!precise.is_not_modified(&Method::GET, &earlier));
+
+        // If-None-Match takes precedence over If-Modified-Since
+        let stale = headers(&[
+            (header::IF_NONE_MATCH, "\"other\"".to_string()),
+            (header::IF_MODIFIED_SINCE, since(modified_at())),
+        ]);
+        assert!(!validators.is_not_modified(&Method::GET, &stale));
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_hashes_bodies() {
+        let app = test::init_service(
+            App::new()
+                .wrap(ConditionalGet::new())
+                .route("/", web::get().to(|| async { HttpResponse::Ok().body("hello") }))
+                .route(
+                    "/stream",
+                    web::get().to(|| async {
+                        let chunks = stream::iter([Ok::<_, Error>(web::Bytes::from("chunk"))]);
+                        HttpResponse::Ok().insert_header((header::ETAG, "\"v1\"")).streaming(chunks)
+                    }),
+                ),
+        )
+        .await;
+
+        let res = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        let etag = res.headers().get(header::ETAG).unwrap().clone();
+        assert_eq!(etag.to_str().unwrap(), strong_etag(b"hello").to_string());
+        assert_eq!(test::read_body(res).await, "hello");
+
+        let req = test::TestRequest::get().uri("/").insert_header((header::IF_NONE_MATCH, etag));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(
+            res.response().extensions().get::<ResponsesTypes>(),
+            Some(&ResponsesTypes::Redirection(ResponsesRedirectionCodes::NotModified))
+        );
+        assert!(test::read_body(res).await.is_empty());
+
+        // Streams keep their handler-supplied validators
+        let req = test::TestRequest::get().uri("/stream");
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(test::read_body(res).await, "chunk");
+        let req = test::TestRequest::get()
+            .uri("/stream")
+            .insert_header((header::IF_NONE_MATCH, "W/\"v1\""));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        let etags: Vec<_> = res.headers().get_all(header::ETAG).collect();
+        assert_eq!(etags, ["\"v1\""]);
+    }
+
+    #[actix_web::test]
+    async fn test_conditional_custom_response() {
+        let app = test::init_service(App::new().route(
+            "/report",
+            web::get().to(|| async {
+                CustomResponse::new(200, "OK", "{\"total\": 42}", "Report")
+                    .conditional()
+                    .with_last_modified(modified_at())
+            }),
+        ))
+        .await;
+
+        let res =
+            test::call_service(&app, test::TestRequest::get().uri("/report").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::ETAG).unwrap().to_str().unwrap(),
+            strong_etag(b"{\"total\": 42}").to_string()
+        );
+        assert!(res.headers().contains_key(header::LAST_MODIFIED));
+
+        let since = HttpDate::from(modified_at() + Duration::from_secs(60)).to_string();
+        let req = test::TestRequest::get()
+            .uri("/report")
+            .insert_header((header::IF_MODIFIED_SINCE, since));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert!(test::read_body(res).await.is_empty());
+    }
+}
@@ -0,0 +1,451 @@
+//! # Cross-Origin Resource Sharing (CORS)
+//!
+//! This module provides the `CorsPolicy` applied by `UnifiedMiddleware`.
+//!
+//! A policy decides which origins may call the API and which `Access-Control-*` headers are
+//! returned to the browser:
+//! - preflight requests (`OPTIONS` with `Access-Control-Request-Method`) are answered directly
+//!   with the allowed methods, headers and `Access-Control-Max-Age`;
+//! - actual requests get `Access-Control-Allow-Origin`, `-Allow-Credentials` and
+//!   `-Expose-Headers` added to the handler's response;
+//! - every answer carries `Vary: Origin` so caches never serve one origin's answer to another.
+//!
+//! Origins are matched with `OriginPattern`s: exact origins, `*`, wildcard subdomains
+//! (`https://*.example.com`) or regular expressions.
+//!
+//! When credentials are allowed, the policy never answers `*`: the `Any` pattern is ignored
+//! and the concrete origin is echoed back, as required by the Fetch standard.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::http::Method;
+//! use simbld_http::helpers::cors_helper::{CorsPolicy, OriginPattern};
+//! use std::time::Duration;
+//!
+//! let policy = CorsPolicy::new()
+//!     .with_origin("https://app.example.com")
+//!     .with_origin("https://*.preview.example.com")
+//!     .with_origin_pattern(OriginPattern::regex(r"^http://localhost:\d+$").unwrap())
+//!     .with_allowed_methods([Method::GET, Method::POST])
+//!     .with_expose_headers(["x-request-id"])
+//!     .with_max_age(Duration::from_secs(600))
+//!     .with_credentials(true);
+//!
+//! assert!(policy.is_origin_allowed("https://pr-42.preview.example.com"));
+//! assert!(!policy.is_origin_allowed("https://evil.com"));
+//! ```
+
+use crate::responses::{ResponsesClientCodes, ResponsesTypes};
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::Method;
+use regex::Regex;
+use std::time::Duration;
+use thiserror::Error;
+
+/// A rule matching the `Origin` header of a request.
+#[derive(Debug, Clone)]
+pub enum OriginPattern {
+    /// Matches every origin (`*`). Ignored when credentials are allowed.
+    Any,
+    /// Matches one origin, compared case-insensitively (e.g. `https://example.com`).
+    Exact(String),
+    /// Matches any subdomain of `suffix`, optionally restricted to one scheme
+    /// (e.g. `https://*.example.com` matches `https://a.b.example.com` but not `https://example.com`).
+    Subdomain { scheme: Option<String>, suffix: String },
+    /// Matches origins against a regular expression.
+    Regex(Regex),
+}
+
+impl OriginPattern {
+    /// Parses `*`, a wildcard subdomain (`https://*.example.com`, `*.example.com`) or an exact origin.
+    pub fn parse(pattern: &str) -> Self {
+        let pattern = normalize_origin(pattern);
+        if pattern == "*" {
+            return OriginPattern::Any;
+        }
+
+        let (scheme, host) = match pattern.split_once("://") {
+            Some((scheme, host)) => (Some(format!("{}://", scheme)), host),
+            None => (None, pattern.as_str()),
+        };
+        match host.strip_prefix('*') {
+            Some(suffix) if suffix.starts_with('.') => {
+                OriginPattern::Subdomain { scheme, suffix: suffix.to_string() }
+            }
+            _ => OriginPattern::Exact(pattern),
+        }
+    }
+
+    /// Creates a pattern from a regular expression. Anchor it (`^...$`) to match whole origins.
+    pub fn regex(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(OriginPattern::Regex)
+    }
+
+    /// Returns `true` when `origin` matches the pattern.
+    pub fn matches(&self, origin: &str) -> bool {
+        match self {
+            OriginPattern::Any => true,
+            OriginPattern::Exact(expected) => normalize_origin(origin) == *expected,
+            OriginPattern::Subdomain { scheme, suffix } => {
+                let origin = normalize_origin(origin);
+                let host = match scheme {
+                    Some(scheme) => match origin.strip_prefix(scheme.as_str()) {
+                        Some(host) => host,
+                        None => return false,
+                    },
+                    None => match origin.split_once("://") {
+                        Some((_, host)) => host,
+                        None => return false,
+                    },
+                };
+                host.strip_suffix(suffix.as_str()).is_some_and(|label| {
+                    !label.is_empty()
+                        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                })
+            }
+            OriginPattern::Regex(regex) => regex.is_match(origin),
+        }
+    }
+}
+
+/// Lowercases an origin and strips the trailing slash some clients send.
+fn normalize_origin(origin: &str) -> String {
+    origin.trim().trim_end_matches('/').to_ascii_lowercase()
+}
+
+/// Reasons for rejecting a cross-origin request.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum CorsError {
+    #[error("Origin not allowed.")]
+    OriginNotAllowed,
+    #[error("Method not allowed by the CORS policy.")]
+    MethodNotAllowed,
+    #[error("Request headers not allowed by the CORS policy.")]
+    HeadersNotAllowed,
+    #[error("Malformed CORS request.")]
+    BadRequest,
+}
+
+impl CorsError {
+    /// Returns the catalog response describing the rejection.
+    pub fn response_type(&self) -> ResponsesTypes {
+        ResponsesTypes::ClientError(match self {
+            CorsError::OriginNotAllowed => ResponsesClientCodes::OriginError,
+            CorsError::MethodNotAllowed => ResponsesClientCodes::MethodNotAllowed,
+            CorsError::HeadersNotAllowed => ResponsesClientCodes::Forbidden,
+            CorsError::BadRequest => ResponsesClientCodes::BadRequest,
+        })
+    }
+}
+
+/// Cross-origin policy of `UnifiedMiddleware`.
+#[derive(Debug, Clone)]
+pub struct CorsPolicy {
+    origins: Vec<OriginPattern>,
+    allowed_methods: Vec<Method>,
+    allowed_headers: Option<Vec<HeaderName>>,
+    expose_headers: Vec<HeaderName>,
+    max_age: Option<Duration>,
+    allow_credentials: bool,
+}
+
+impl CorsPolicy {
+    /// Creates a policy allowing no origin yet, the common methods and any request header.
+    pub fn new() -> Self {
+        Self {
+            origins: Vec::new(),
+            allowed_methods: vec![
+                Method::GET,
+                Method::HEAD,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+                Method::OPTIONS,
+            ],
+            allowed_headers: None,
+            expose_headers: Vec::new(),
+            max_age: None,
+            allow_credentials: false,
+        }
+    }
+
+    /// Allows origins matching `pattern` (see `OriginPattern::parse`).
+    pub fn with_origin(self, pattern: &str) -> Self {
+        self.with_origin_pattern(OriginPattern::parse(pattern))
+    }
+
+    /// Allows origins matching `pattern`.
+    pub fn with_origin_pattern(mut self, pattern: OriginPattern) -> Self {
+        self.origins.push(pattern);
+        self
+    }
+
+    /// Replaces the methods accepted in preflight requests.
+    pub fn with_allowed_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+        self.allowed_methods = methods.into_iter().collect();
+        self
+    }
+
+    /// Restricts the request headers accepted in preflight requests.
+    ///
+    /// By default every requested header is accepted and echoed back.
+    pub fn with_allowed_headers<'a>(mut self, headers: impl IntoIterator<Item = &'a str>) -> Self {
+        self.allowed_headers = Some(parse_header_names(headers));
+        self
+    }
+
+    /// Sets the response headers readable by browser scripts (`Access-Control-Expose-Headers`).
+    pub fn with_expose_headers<'a>(mut self, headers: impl IntoIterator<Item = &'a str>) -> Self {
+        self.expose_headers = parse_header_names(headers);
+        self
+    }
+
+    /// Sets how long browsers may cache preflight results (`Access-Control-Max-Age`).
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Allows credentialed requests (cookies, `Authorization`).
+    ///
+    /// Credentialed policies never answer `*`, so `OriginPattern::Any` stops matching.
+    pub fn with_credentials(mut self, allow: bool) -> Self {
+        self.allow_credentials = allow;
+        self
+    }
+
+    /// Returns `true` when at least one origin pattern is configured.
+    pub fn has_origins(&self) -> bool {
+        !self.origins.is_empty()
+    }
+
+    /// Returns `true` when cross-origin requests from `origin` are allowed.
+    pub fn is_origin_allowed(&self, origin: &str) -> bool {
+        self.allow_origin_value(origin).is_some()
+    }
+
+    /// Returns the `Access-Control-Allow-Origin` value for `origin`, if it is allowed.
+    fn allow_origin_value(&self, origin: &str) -> Option<HeaderValue> {
+        let mut any = false;
+        for pattern in &self.origins {
+            match pattern {
+                OriginPattern::Any if self.allow_credentials => continue,
+                OriginPattern::Any => any = true,
+                pattern if pattern.matches(origin) => return HeaderValue::from_str(origin).ok(),
+                _ => {}
+            }
+        }
+        any.then(|| HeaderValue::from_static("*"))
+    }
+
+    /// Returns `true` when the request is a CORS preflight.
+    pub fn is_preflight(method: &Method, headers: &HeaderMap) -> bool {
+        method == Method::OPTIONS
+            && headers.contains_key(header::ORIGIN)
+            && headers.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    /// Checks a preflight request and returns the headers of its answer.
+    pub fn preflight_headers(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Vec<(HeaderName, HeaderValue)>, CorsError> {
+        let mut response = self.origin_headers(headers)?.unwrap_or_default();
+
+        let method = headers
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| Method::from_bytes(value.trim().as_bytes()).ok())
+            .ok_or(CorsError::BadRequest)?;
+        if !self.allowed_methods.contains(&method) {
+            return Err(CorsError::MethodNotAllowed);
+        }
+
+        let requested = headers
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .map(|value| value.to_str().map_err(|_| CorsError::BadRequest))
+            .transpose()?
+            .map(|value| parse_header_names(value.split(',')))
+            .unwrap_or_default();
+        if let Some(allowed) = &self.allowed_headers {
+            if requested.iter().any(|name| !allowed.contains(name)) {
+                return Err(CorsError::HeadersNotAllowed);
+            }
+        }
+
+        response.push((
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            join_header_value(self.allowed_methods.iter().map(Method::as_str)),
+        ));
+        let allow_headers = self.allowed_headers.as_ref().unwrap_or(&requested);
+        if !allow_headers.is_empty() {
+            response.push((
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                join_header_value(allow_headers.iter().map(HeaderName::as_str)),
+            ));
+        }
+        if let Some(max_age) = self.max_age {
+            response.push((header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age.as_secs())));
+        }
+        response.push((
+            header::VARY,
+            HeaderValue::from_static(
+                "Origin, Access-Control-Request-Method, Access-Control-Request-Headers",
+            ),
+        ));
+
+        Ok(response)
+    }
+
+    /// Checks the origin of an actual request and returns the headers to add to its response.
+    ///
+    /// Requests without an `Origin` header are not cross-origin and only get `Vary: Origin`.
+    pub fn actual_headers(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Vec<(HeaderName, HeaderValue)>, CorsError> {
+        let mut response = self.origin_headers(headers)?.unwrap_or_default();
+        if !response.is_empty() && !self.expose_headers.is_empty() {
+            response.push((
+                header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                join_header_value(self.expose_headers.iter().map(HeaderName::as_str)),
+            ));
+        }
+        response.push((header::VARY, HeaderValue::from_static("Origin")));
+        Ok(response)
+    }
+
+    /// Returns `Access-Control-Allow-Origin` (and `-Credentials`) for the request's origin,
+    /// `None` when the request has no `Origin` header.
+    fn origin_headers(
+        &self,
+        headers: &HeaderMap,
+    ) -> Result<Option<Vec<(HeaderName, HeaderValue)>>, CorsError> {
+        let Some(origin) = headers.get(header::ORIGIN) else {
+            return Ok(None);
+        };
+        let origin = origin.to_str().map_err(|_| CorsError::OriginNotAllowed)?;
+        let allow_origin = self.allow_origin_value(origin).ok_or(CorsError::OriginNotAllowed)?;
+
+        let mut response = vec![(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin)];
+        if self.allow_credentials {
+            response
+                .push((header::ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true")));
+        }
+        Ok(Some(response))
+    }
+}
+
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parses a list of header names, skipping blank and invalid entries.
+fn parse_header_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Vec<HeaderName> {
+    names
+        .into_iter()
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect()
+}
+
+/// Joins header names or methods into a comma-separated header value.
+fn join_header_value<'a>(items: impl Iterator<Item = &'a str>) -> HeaderValue {
+    HeaderValue::from_str(&items.collect::<Vec<_>>().join(", "))
+        .unwrap_or_else(|_| HeaderValue::from_static(""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut req = TestRequest::default();
+        for (name, value) in pairs {
+            req = req.insert_header((*name, *value));
+        }
+        req.to_http_request().headers().clone()
+    }
+
+    fn find<'a>(response: &'a [(HeaderName, HeaderValue)], name: &str) -> Option<&'a str> {
+        response.iter().find(|(n, _)| n == name).and_then(|(_, v)| v.to_str().ok())
+    }
+
+    #[test]
+    fn test_origin_patterns() {
+        assert!(OriginPattern::parse("https://Example.com/").matches("https://example.com"));
+        assert!(!OriginPattern::parse("https://example.com").matches("http://example.com"));
+
+        let subdomain = OriginPattern::parse("https://*.example.com");
+        assert!(subdomain.matches("https://a.b.example.com"));
+        assert!(!subdomain.matches("https://example.com"));
+        assert!(!subdomain.matches("http://a.example.com"));
+        assert!(!subdomain.matches("https://evil.com/.example.com"));
+        assert!(OriginPattern::parse("*.example.com").matches("http://a.example.com"));
+
+        let regex = OriginPattern::regex(r"^http://localhost:\d+$").unwrap();
+        assert!(regex.matches("http://localhost:3000"));
+        assert!(!regex.matches("http://localhost.evil.com"));
+    }
+
+    #[test]
+    fn test_credentials_never_answer_wildcard() {
+        let policy = CorsPolicy::new().with_origin("*");
+        let response = policy.actual_headers(&headers(&[("origin", "https://a.com")])).unwrap();
+        assert_eq!(find(&response, "access-control-allow-origin"), Some("*"));
+
+        let credentialed = policy.clone().with_credentials(true);
+        assert_eq!(
+            credentialed.actual_headers(&headers(&[("origin", "https://a.com")])),
+            Err(CorsError::OriginNotAllowed)
+        );
+
+        let credentialed = credentialed.with_origin("https://a.com");
+        let response =
+            credentialed.actual_headers(&headers(&[("origin", "https://a.com")])).unwrap();
+        assert_eq!(find(&response, "access-control-allow-origin"), Some("https://a.com"));
+        assert_eq!(find(&response, "access-control-allow-credentials"), Some("true"));
+        assert_eq!(find(&response, "vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn test_preflight_headers() {
+        let policy = CorsPolicy::new()
+            .with_origin("https://a.com")
+            .with_allowed_methods([Method::GET, Method::POST])
+            .with_allowed_headers(["content-type", "x-api-key"])
+            .with_max_age(Duration::from_secs(600));
+
+        let response = policy
+            .preflight_headers(&headers(&[
+                ("origin", "https://a.com"),
+                ("access-control-request-method", "POST"),
+                ("access-control-request-headers", "X-API-Key, Content-Type"),
+            ]))
+            .unwrap();
+        assert_eq!(find(&response, "access-control-allow-methods"), Some("GET, POST"));
+        assert_eq!(
+            find(&response, "access-control-allow-headers"),
+            Some("content-type, x-api-key")
+        );
+        assert_eq!(find(&response, "access-control-max-age"), Some("600"));
+
+        let rejected = policy.preflight_headers(&headers(&[
+            ("origin", "https://a.com"),
+            ("access-control-request-method", "DELETE"),
+        ]));
+        assert_eq!(rejected, Err(CorsError::MethodNotAllowed));
+
+        let rejected = policy.preflight_headers(&headers(&[
+            ("origin", "https://a.com"),
+            ("access-control-request-method", "GET"),
+            ("access-control-request-headers", "x-secret"),
+        ]));
+        assert_eq!(rejected, Err(CorsError::HeadersNotAllowed));
+        assert_eq!(CorsError::OriginNotAllowed.response_type().get_code(), 400);
+    }
+}
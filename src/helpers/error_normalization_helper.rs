@@ -0,0 +1,406 @@
+//! # Error Normalization
+//!
+//! Provides `NormalizeErrors`, a middleware rewriting the error responses produced outside
+//! the catalog into one unified format, such as Actix's default `404` for unmatched routes,
+//! `405` for unsupported methods, extractor errors (`400` plain-text bodies) and panics.
+//!
+//! An error response (status 400 or above) is left untouched when it already comes from
+//! the catalog, i.e. carries a `ResponsesTypes` in its extensions, or is a problem document.
+//! Otherwise its status and headers are kept, and its body is replaced by the catalog entry
+//! matching the status, in the configured `ErrorFormat`:
+//! - `Catalog`: the catalog JSON body, as rendered by `ResponsesTypes::as_json`.
+//! - `Envelope`: `{"error": {...}}`, with the code, name, description and family.
+//! - `Problem`: an RFC 9457 `application/problem+json` document.
+//!
+//! The original text of client errors, such as the reason of a rejected JSON payload, is
+//! kept as `detail`. Server error bodies are dropped, since they may reveal internals.
+//! Panics of the wrapped services are caught and answered with a normalized `500`.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::{web, App, HttpResponse};
+//! use simbld_http::helpers::error_normalization_helper::{ErrorFormat, NormalizeErrors};
+//!
+//! let app = App::new()
+//!     .wrap(NormalizeErrors::new(ErrorFormat::Problem))
+//!     .route("/", web::get().to(HttpResponse::Ok));
+//! ```
+
+use crate::helpers::http_interceptor_helper::RequestId;
+use crate::helpers::tracing_helper::trace_event;
+use crate::responses::{ResponsesClientCodes, ResponsesServerCodes, ResponsesTypes};
+use actix_web::{
+    body::{self, BodySize, BoxBody, EitherBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::InternalError,
+    http::{header, StatusCode},
+    Error, HttpMessage, HttpResponse,
+};
+use futures_util::future::{ok, LocalBoxFuture, Ready};
+use futures_util::FutureExt;
+use serde_json::{json, Value};
+use std::panic::AssertUnwindSafe;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+const ERRORS_TARGET: &str = "simbld_http::errors";
+
+/// Content type of problem documents.
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Maximum size of the original body kept as `detail`.
+pub const MAX_DETAIL_SIZE: u64 = 1024;
+
+/// Format of normalized error bodies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorFormat {
+    /// The catalog JSON body.
+    #[default]
+    Catalog,
+    /// `{"error": {...}}` envelope.
+    Envelope,
+    /// RFC 9457 problem document.
+    Problem,
+}
+
+/// What is known about the failed request.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ErrorContext {
+    /// Path of the request.
+    pub path: String,
+    /// Identifier of the request, when `HttpInterceptor` assigned one.
+    pub request_id: Option<String>,
+    /// Original text of the error, kept for client errors.
+    pub detail: Option<String>,
+}
+
+/// Returns the catalog entry whose standard or internal code is `status`, falling back to
+/// the generic entry of its class for codes missing from the catalog.
+pub fn catalog_entry(status: StatusCode) -> ResponsesTypes {
+    ResponsesTypes::from_u16(status.as_u16()).unwrap_or(match status.is_client_error() {
+        true => ResponsesTypes::ClientError(ResponsesClientCodes::BadRequest),
+        false => ResponsesTypes::ServerError(ResponsesServerCodes::InternalServerError),
+    })
+}
+
+impl ErrorFormat {
+    /// Returns the content type of the format.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ErrorFormat::Problem => PROBLEM_JSON,
+            ErrorFormat::Catalog | ErrorFormat::Envelope => "application/json",
+        }
+    }
+
+    /// Renders the body of an error with `status`.
+    pub fn render(&self, status: StatusCode, context: &ErrorContext) -> Value {
+        let entry = catalog_entry(status);
+        let code = entry.as_tuple();
+        match self {
+            ErrorFormat::Catalog => {
+                let mut body = entry.as_json();
+                if let (Some(detail), Some(object)) = (&context.detail, body.as_object_mut()) {
+                    object.insert("detail".to_string(), json!(detail));
+                }
+                body
+            }
+            ErrorFormat::Envelope => json!({
+                "error": {
+                    "status": status.as_u16(),
+                    "name": code.standard_name,
+                    "description": code.unified_description,
+                    "internal_code": code.internal_code,
+                    "internal_name": code.internal_name,
+                    "family": entry.get_family(),
+                    "detail": context.detail,
+                },
+                "request_id": context.request_id,
+            }),
+            ErrorFormat::Problem => {
+                let mut body = json!({
+                    "type": "about:blank",
+                    "title": status.canonical_reason().unwrap_or(code.standard_name),
+                    "status": status.as_u16(),
+                    "detail": context.detail.as_deref().unwrap_or(code.unified_description),
+                    "instance": context.path,
+                });
+                if let Some(object) = body.as_object_mut() {
+                    if code.internal_code.is_some_and(|internal| internal != status.as_u16()) {
+                        object.insert("internal_code".to_string(), json!(code.internal_code));
+                    }
+                    if let Some(request_id) = &context.request_id {
+                        object.insert("request_id".to_string(), json!(request_id));
+                    }
+                }
+                body
+            }
+        }
+    }
+
+    /// Builds the normalized response replacing `original`, keeping its status and headers.
+    pub fn normalize(&self, original: &HttpResponse<()>, context: &ErrorContext) -> HttpResponse {
+        let status = original.status();
+        let mut builder = HttpResponse::build(status);
+        for (name, value) in original.headers() {
+            if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+                builder.append_header((name.clone(), value.clone()));
+            }
+        }
+        builder.extensions_mut().insert(catalog_entry(status));
+        builder.content_type(self.content_type()).body(self.render(status, context).to_string())
+    }
+}
+
+/// Whether an error response needs normalizing.
+fn needs_normalizing(response: &HttpResponse<impl MessageBody>) -> bool {
+    let problem = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(PROBLEM_JSON.as_bytes()));
+    response.status().as_u16() >= 400
+        && response.extensions().get::<ResponsesTypes>().is_none()
+        && !problem
+}
+
+/// Middleware rewriting non-catalog error responses into one format.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NormalizeErrors {
+    format: ErrorFormat,
+}
+
+impl NormalizeErrors {
+    /// Creates the middleware rendering errors in `format`.
+    pub fn new(format: ErrorFormat) -> Self {
+        Self { format }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for NormalizeErrors
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Transform = NormalizeErrorsService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(NormalizeErrorsService { service: Rc::new(service), format: self.format })
+    }
+}
+
+/// Service created by `NormalizeErrors` to process requests.
+pub struct NormalizeErrorsService<S> {
+    service: Rc<S>,
+    format: ErrorFormat,
+}
+
+impl<S, B> Service<ServiceRequest> for NormalizeErrorsService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    /// Replaces non-catalog error responses, errors and panics with normalized responses.
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let format = self.format;
+        let mut context = ErrorContext {
+            path: req.path().to_string(),
+            request_id: req.extensions().get::<RequestId>().map(|id| id.as_str().to_string()),
+            detail: None,
+        };
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let result =
+                AssertUnwindSafe(async move { service.call(req).await }).catch_unwind().await;
+            let res = match result {
+                Ok(Ok(res)) => res,
+                Ok(Err(err)) => {
+                    let response = err.error_response();
+                    if !needs_normalizing(&response) {
+                        return Err(err);
+                    }
+                    if response.status().is_client_error() {
+                        context.detail = Some(err.to_string()).filter(|detail| !detail.is_empty());
+                    }
+                    let normalized = format.normalize(&response.drop_body(), &context);
+                    return Err(InternalError::from_response(err, normalized).into());
+                }
+                Err(_) => {
+                    trace_event!(error, target: ERRORS_TARGET, { path = %context.path, }, "Panic while handling {}", context.path);
+                    let panicked = HttpResponse::InternalServerError().finish().drop_body();
+                    let normalized = format.normalize(&panicked, &context);
+                    return Err(InternalError::from_response("panic", normalized).into());
+                }
+            };
+            if !needs_normalizing(res.response()) {
+                return Ok(res.map_into_left_body());
+            }
+
+            let (req, res) = res.into_parts();
+            if let Some(request_id) = req.extensions().get::<RequestId>() {
+                context.request_id = Some(request_id.as_str().to_string());
+            }
+            let (head, body) = res.into_parts();
+            let keep_detail = head.status().is_client_error()
+                && matches!(body.size(), BodySize::Sized(size) if size > 0 && size <= MAX_DETAIL_SIZE);
+            if keep_detail {
+                if let Ok(bytes) = body::to_bytes(body).await {
+                    context.detail = Some(String::from_utf8_lossy(&bytes).into_owned());
+                }
+            }
+            let normalized = format.normalize(&head, &context);
+            Ok(ServiceResponse::new(req, normalized).map_into_right_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::http_interceptor_helper::HttpInterceptor;
+    use actix_web::{test, web, App};
+
+    #[actix_web::test]
+    async fn test_render_formats() {
+        let context = ErrorContext {
+            path: "/items/7".to_string(),
+            request_id: Some("req-1".to_string()),
+            detail: None,
+        };
+
+        let catalog = ErrorFormat::Catalog.render(StatusCode::NOT_FOUND, &context);
+        assert_eq!(catalog["details"]["standard http code"]["code"], 404);
+
+        let envelope = ErrorFormat::Envelope.render(StatusCode::METHOD_NOT_ALLOWED, &context);
+        assert_eq!(envelope["error"]["status"], 405);
+        assert_eq!(envelope["error"]["family"], "Client Error");
+        assert_eq!(envelope["request_id"], "req-1");
+
+        let problem = ErrorFormat::Problem.render(StatusCode::NOT_FOUND, &context);
+        assert_eq!(problem["title"], "Not Found");
+        assert_eq!(problem["status"], 404);
+        assert_eq!(problem["instance"], "/items/7");
+        assert_eq!(ErrorFormat::Problem.content_type(), PROBLEM_JSON);
+
+        // Codes missing from the catalog fall back to the entry of their class
+        assert_eq!(
+            catalog_entry(StatusCode::from_u16(427).unwrap()),
+            ResponsesTypes::ClientError(ResponsesClientCodes::BadRequest)
+        );
+        assert_eq!(
+            catalog_entry(StatusCode::from_u16(590).unwrap()),
+            ResponsesTypes::ServerError(ResponsesServerCodes::InternalServerError)
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_normalize_framework_errors() {
+        let app = test::init_service(
+            App::new()
+                .wrap(NormalizeErrors::new(ErrorFormat::Problem))
+                .wrap(HttpInterceptor::new())
+                .service(web::resource("/items").route(
+                    web::post().to(|_: web::Json<Value>| async { HttpResponse::Ok().finish() }),
+                ))
+                .route(
+                    "/catalog",
+                    web::get().to(|| async {
+                        ResponsesTypes::ClientError(ResponsesClientCodes::Conflict)
+                            .into_http_response()
+                    }),
+                ),
+        )
+        .await;
+
+        let res =
+            test::call_service(&app, test::TestRequest::get().uri("/missing").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), PROBLEM_JSON);
+        assert!(res.headers().contains_key("x-request-id"));
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["title"], "Not Found");
+        assert!(body["request_id"].is_string());
+
+        let res =
+            test::call_service(&app, test::TestRequest::get().uri("/items").to_request()).await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["status"], 405);
+
+        let req = test::TestRequest::post()
+            .uri("/items")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload("{not json");
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(res).await;
+        assert!(body["detail"].as_str().unwrap().contains("Json deserialize error"));
+
+        // Catalog responses are left untouched
+        let res =
+            test::call_service(&app, test::TestRequest::get().uri("/catalog").to_request()).await;
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "application/json");
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["details"]["standard http code"]["code"], 409);
+    }
+
+    #[actix_web::test]
+    async fn test_normalize_errors_and_panics() {
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(|req, srv| {
+                    let refused = req.path() == "/refused";
+                    let fut = srv.call(req);
+                    async move {
+                        match refused {
+                            true => Err(actix_web::error::ErrorForbidden("not for you")),
+                            false => fut.await,
+                        }
+                    }
+                })
+                .wrap(NormalizeErrors::new(ErrorFormat::Envelope))
+                .route(
+                    "/panic",
+                    web::get().to(|| async {
+                        panic!("boom");
+                        #[allow(unreachable_code)]
+                        HttpResponse::Ok().finish()
+                    }),
+                ),
+        )
+        .await;
+
+        let err =
+            test::try_call_service(&app, test::TestRequest::get().uri("/refused").to_request())
+                .await
+                .unwrap_err();
+        let res = err.error_response();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let body: Value =
+            serde_json::from_slice(&body::to_bytes(res.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body["error"]["name"], "Forbidden");
+        assert_eq!(body["error"]["detail"], "not for you");
+
+        let err = test::try_call_service(&app, test::TestRequest::get().uri("/panic").to_request())
+            .await
+            .unwrap_err();
+        let res = err.error_response();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body: Value =
+            serde_json::from_slice(&body::to_bytes(res.into_body()).await.unwrap()).unwrap();
+        assert_eq!(body["error"]["family"], "Server Error");
+        assert!(body["error"]["detail"].is_null());
+    }
+}
@@ -0,0 +1,777 @@
+//! # HTTP Basic and Digest Authentication
+//!
+//! Provides `Authenticator`s for `AuthMiddleware` handling the `Basic` (RFC 7617) and
+//! `Digest` (RFC 7616) schemes. Both look users up in a `CredentialStore` and compare
+//! secrets in constant time.
+//!
+//! Wrong or malformed credentials are reported as `TokenError::InvalidCredentials`
+//! (catalog `Unauthorized`, or `ProxyAuthenticationRequired` when the middleware runs in
+//! proxy mode), along with the challenges of the configured schemes.
+//!
+//! Digest nonces are self-contained: they carry their creation time and are signed with a
+//! secret of the authenticator, so no per-challenge state is kept. Expired nonces are
+//! answered with `stale=true`, and nonce counts (`nc`) must increase to prevent replays.
+//!
+//! Authenticators are built once per worker, inside the `HttpServer::new` closure. Every
+//! worker must therefore use the same secret, so that a nonce issued by one worker is
+//! accepted by the others, and the same `DigestNonceCounts`, created outside the closure,
+//! so that a nonce count used on one worker cannot be replayed on another.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::{web, App, HttpResponse};
+//! use simbld_http::helpers::auth_middleware::{AuthMiddleware, Principal};
+//! use simbld_http::helpers::http_auth_helper::{
+//!     BasicAuthenticator, DigestAuthenticator, StaticCredentialStore,
+//! };
+//!
+//! let users = StaticCredentialStore::new().with_user("alice", "wonderland");
+//! let digest = DigestAuthenticator::new("devices", users.clone(), "shared nonce secret");
+//!
+//! let app = App::new()
+//!     .wrap(
+//!         AuthMiddleware::from_authenticator(digest)
+//!             .with_authenticator(BasicAuthenticator::new("tools", users)),
+//!     )
+//!     .route(
+//!         "/me",
+//!         web::get().to(|principal: Principal| async move { HttpResponse::Ok().body(principal.id) }),
+//!     );
+//! ```
+
+use crate::helpers::auth_middleware::{Authenticator, Principal, TokenError};
+use crate::helpers::rate_limit_helper::{Clock, SystemClock};
+use actix_web::dev::ServiceRequest;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures_util::future::{ready, LocalBoxFuture};
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Secret of a user, and the principal it authenticates as.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credential {
+    /// Principal stored in the request once authenticated.
+    pub principal: Principal,
+    /// Plain-text password, required to verify Digest responses.
+    pub password: String,
+}
+
+impl Credential {
+    /// Creates a credential authenticating as `principal`.
+    pub fn new(principal: Principal, password: impl Into<String>) -> Self {
+        Self { principal, password: password.into() }
+    }
+}
+
+/// Looks up the credential of a user.
+///
+/// Lookups are asynchronous so stores can consult a database or a directory.
+/// Any `Fn(&str) -> Option<Credential>` closure is a store as well.
+///
+pub trait CredentialStore {
+    fn credential(&self, username: &str) -> LocalBoxFuture<'static, Option<Credential>>;
+}
+
+impl<F> CredentialStore for F
+where
+    F: Fn(&str) -> Option<Credential>,
+{
+    fn credential(&self, username: &str) -> LocalBoxFuture<'static, Option<Credential>> {
+        Box::pin(ready(self(username)))
+    }
+}
+
+/// Store backed by a fixed set of users, for tests, demos and internal tools.
+#[derive(Debug, Clone, Default)]
+pub struct StaticCredentialStore {
+    users: HashMap<String, Credential>,
+}
+
+impl StaticCredentialStore {
+    /// Creates a store without users.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `username`, authenticating as a principal of the same id.
+    pub fn with_user(self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        let username = username.into();
+        let credential = Credential::new(Principal::new(username.clone()), password);
+        self.with_credential(username, credential)
+    }
+
+    /// Adds `username` with a custom credential.
+    pub fn with_credential(mut self, username: impl Into<String>, credential: Credential) -> Self {
+        self.users.insert(username.into(), credential);
+        self
+    }
+}
+
+impl CredentialStore for StaticCredentialStore {
+    fn credential(&self, username: &str) -> LocalBoxFuture<'static, Option<Credential>> {
+        Box::pin(ready(self.users.get(username).cloned()))
+    }
+}
+
+/// Compares two byte strings in a time independent of their contents and lengths.
+///
+/// Both sides are hashed first, so that the comparison always covers 32 bytes.
+pub fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    let (left, right) = (Sha256::digest(left), Sha256::digest(right));
+    left.iter().zip(right.iter()).fold(0u8, |diff, (l, r)| diff | (l ^ r)) == 0
+}
+
+/// Quotes a challenge parameter value.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// `Basic` scheme authenticator (RFC 7617).
+#[derive(Clone)]
+pub struct BasicAuthenticator {
+    realm: String,
+    store: Rc<dyn CredentialStore>,
+}
+
+impl BasicAuthenticator {
+    /// Creates an authenticator for `realm`.
+    pub fn new(realm: impl Into<String>, store: impl CredentialStore + 'static) -> Self {
+        Self { realm: realm.into(), store: Rc::new(store) }
+    }
+}
+
+impl Authenticator for BasicAuthenticator {
+    fn scheme(&self) -> &str {
+        "Basic"
+    }
+
+    fn authenticate(
+        &self,
+        credentials: &str,
+        _req: &ServiceRequest,
+    ) -> LocalBoxFuture<'static, Result<Principal, TokenError>> {
+        let decoded = STANDARD
+            .decode(credentials)
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|decoded| {
+                decoded.split_once(':').map(|(user, pass)| (user.to_string(), pass.to_string()))
+            });
+        let Some((username, password)) = decoded else {
+            return Box::pin(ready(Err(TokenError::InvalidCredentials)));
+        };
+
+        let lookup = self.store.credential(&username);
+        Box::pin(async move {
+            let credential = lookup.await;
+            // Unknown users are compared as well, so that timing does not reveal them
+            let expected = credential.as_ref().map_or("", |credential| &credential.password);
+            let matches = constant_time_eq(expected.as_bytes(), password.as_bytes());
+            match credential {
+                Some(credential) if matches => Ok(credential.principal),
+                _ => Err(TokenError::InvalidCredentials),
+            }
+        })
+    }
+
+    fn challenges(&self, error: TokenError) -> Vec<String> {
+        if error == TokenError::Forbidden {
+            return Vec::new();
+        }
+        vec![format!("Basic realm={}, charset=\"UTF-8\"", quote(&self.realm))]
+    }
+}
+
+/// Hash algorithms of the `Digest` scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    Md5,
+    Md5Sess,
+    Sha256,
+    Sha256Sess,
+}
+
+impl DigestAlgorithm {
+    /// Returns the name used in challenges and responses.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Md5 => "MD5",
+            DigestAlgorithm::Md5Sess => "MD5-sess",
+            DigestAlgorithm::Sha256 => "SHA-256",
+            DigestAlgorithm::Sha256Sess => "SHA-256-sess",
+        }
+    }
+
+    /// Parses an algorithm name, case-insensitively.
+    pub fn parse(name: &str) -> Option<Self> {
+        [Self::Md5, Self::Md5Sess, Self::Sha256, Self::Sha256Sess]
+            .into_iter()
+            .find(|algorithm| algorithm.as_str().eq_ignore_ascii_case(name))
+    }
+
+    /// Hashes `data`, as lowercase hexadecimal.
+    pub fn hash(&self, data: &str) -> String {
+        match self {
+            DigestAlgorithm::Md5 | DigestAlgorithm::Md5Sess => format!("{:x}", Md5::digest(data)),
+            DigestAlgorithm::Sha256 | DigestAlgorithm::Sha256Sess => {
+                format!("{:x}", Sha256::digest(data))
+            }
+        }
+    }
+
+    fn is_session(&self) -> bool {
+        matches!(self, DigestAlgorithm::Md5Sess | DigestAlgorithm::Sha256Sess)
+    }
+}
+
+/// Nonce counts (`nc`) seen by `DigestAuthenticator`s, shared between the workers of a
+/// server so that replays are refused whichever worker receives them.
+#[derive(Debug, Clone, Default)]
+pub struct DigestNonceCounts {
+    /// Highest nonce count seen per nonce, dropped once the nonce expires.
+    counts: Arc<Mutex<HashMap<String, (Duration, u64)>>>,
+}
+
+impl DigestNonceCounts {
+    /// Creates an empty set of nonce counts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// `Digest` scheme authenticator (RFC 7616), with `qop="auth"`.
+#[derive(Clone)]
+pub struct DigestAuthenticator {
+    store: Rc<dyn CredentialStore>,
+    state: Arc<DigestState>,
+}
+
+struct DigestState {
+    realm: String,
+    algorithms: Vec<DigestAlgorithm>,
+    nonce_lifetime: Duration,
+    secret: Vec<u8>,
+    opaque: String,
+    clock: Arc<dyn Clock>,
+    counts: DigestNonceCounts,
+}
+
+impl DigestAuthenticator {
+    /// Creates an authenticator for `realm`, offering SHA-256 then MD5, with nonces valid
+    /// for 5 minutes.
+    ///
+    /// Nonces are signed with `secret`, from which the `opaque` value is derived as well.
+    /// Every worker and instance serving the realm must use the same secret.
+    pub fn new(
+        realm: impl Into<String>,
+        store: impl CredentialStore + 'static,
+        secret: impl Into<Vec<u8>>,
+    ) -> Self {
+        let mut state = DigestState {
+            realm: realm.into(),
+            algorithms: vec![DigestAlgorithm::Sha256, DigestAlgorithm::Md5],
+            nonce_lifetime: Duration::from_secs(300),
+            secret: secret.into(),
+            opaque: String::new(),
+            clock: Arc::new(SystemClock),
+            counts: DigestNonceCounts::new(),
+        };
+        state.opaque = state.sign("opaque")[..32].to_string();
+        Self { store: Rc::new(store), state: Arc::new(state) }
+    }
+
+    /// Replaces the offered algorithms, in order of preference.
+    pub fn with_algorithms(self, algorithms: impl IntoIterator<Item = DigestAlgorithm>) -> Self {
+        self.reconfigure(|state| state.algorithms = algorithms.into_iter().collect())
+    }
+
+    /// Sets how long a nonce is accepted before the client is asked to retry with a new one.
+    pub fn with_nonce_lifetime(self, lifetime: Duration) -> Self {
+        self.reconfigure(|state| state.nonce_lifetime = lifetime)
+    }
+
+    /// Replaces the `opaque` value derived from the secret.
+    pub fn with_opaque(self, opaque: impl Into<String>) -> Self {
+        self.reconfigure(|state| state.opaque = opaque.into())
+    }
+
+    /// Shares the nonce counts with the authenticators of the other workers.
+    pub fn with_nonce_counts(self, counts: DigestNonceCounts) -> Self {
+        self.reconfigure(|state| state.counts = counts)
+    }
+
+    /// Replaces the clock dating nonces (e.g. a `ManualClock` in tests).
+    pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+        self.reconfigure(|state| state.clock = Arc::new(clock))
+    }
+
+    fn reconfigure(self, change: impl FnOnce(&mut DigestState)) -> Self {
+        let state = &self.state;
+        let mut state = DigestState {
+            realm: state.realm.clone(),
+            algorithms: state.algorithms.clone(),
+            nonce_lifetime: state.nonce_lifetime,
+            secret: state.secret.clone(),
+            opaque: state.opaque.clone(),
+            clock: state.clock.clone(),
+            counts: state.counts.clone(),
+        };
+        change(&mut state);
+        Self { store: self.store, state: Arc::new(state) }
+    }
+}
+
+impl DigestState {
+    fn sign(&self, payload: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(payload.as_bytes());
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+
+    /// Issues a nonce: `base64(<issued at, µs>:<random>:<signature>)`.
+    fn nonce(&self) -> String {
+        let payload = format!("{}:{}", self.clock.now().as_micros(), uuid::Uuid::new_v4().simple());
+        STANDARD.encode(format!("{}:{}", payload, self.sign(&payload)))
+    }
+
+    /// Returns when a nonce was issued, if it was issued by this authenticator.
+    fn nonce_issued_at(&self, nonce: &str) -> Option<Duration> {
+        let decoded = String::from_utf8(STANDARD.decode(nonce).ok()?).ok()?;
+        let (payload, signature) = decoded.rsplit_once(':')?;
+        if !constant_time_eq(self.sign(payload).as_bytes(), signature.as_bytes()) {
+            return None;
+        }
+        let issued_at = payload.split_once(':')?.0.parse().ok()?;
+        Some(Duration::from_micros(issued_at))
+    }
+
+    /// Records the nonce count, refusing counts that were already used.
+    fn use_nonce_count(&self, nonce: &str, issued_at: Duration, count: u64) -> bool {
+        let Ok(mut counts) = self.counts.counts.lock() else {
+            return false;
+        };
+        let now = self.clock.now();
+        counts.retain(|_, (issued_at, _)| now.saturating_sub(*issued_at) <= self.nonce_lifetime);
+        let last = counts.entry(nonce.to_string()).or_insert((issued_at, 0));
+        if count <= last.1 {
+            return false;
+        }
+        last.1 = count;
+        true
+    }
+}
+
+/// Parses comma-separated `name=value` pairs, values being tokens or quoted strings.
+/// Names are lowercased.
+fn parse_auth_params(input: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut chars = input.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+        let name: String = std::iter::from_fn(|| chars.next_if(|c| *c != '=')).collect();
+        if chars.next().is_none() {
+            return params;
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+        } else {
+            value.extend(std::iter::from_fn(|| chars.next_if(|c| *c != ',')));
+        }
+        params.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+}
+
+impl Authenticator for DigestAuthenticator {
+    fn scheme(&self) -> &str {
+        "Digest"
+    }
+
+    fn authenticate(
+        &self,
+        credentials: &str,
+        req: &ServiceRequest,
+    ) -> LocalBoxFuture<'static, Result<Principal, TokenError>> {
+        let params = parse_auth_params(credentials);
+        let state = self.state.clone();
+        let field = |name: &str| params.get(name).cloned().ok_or(TokenError::InvalidCredentials);
+        let request_uri = req.uri().to_string();
+        let method = req.method().to_string();
+
+        let checked = (|| {
+            let algorithm = match params.get("algorithm") {
+                Some(name) => DigestAlgorithm::parse(name).ok_or(TokenError::InvalidCredentials)?,
+                None => DigestAlgorithm::Md5,
+            };
+            let uri = field("uri")?;
+            let path_and_query = req.uri().path_and_query().map(|path| path.as_str());
+            if !state.algorithms.contains(&algorithm)
+                || field("realm")? != state.realm
+                || field("qop")? != "auth"
+                || params.get("opaque").is_some_and(|opaque| *opaque != state.opaque)
+                || (uri != request_uri && Some(uri.as_str()) != path_and_query)
+            {
+                return Err(TokenError::InvalidCredentials);
+            }
+            let count = u64::from_str_radix(&field("nc")?, 16)
+                .map_err(|_| TokenError::InvalidCredentials)?;
+            Ok((algorithm, uri, count))
+        })();
+        let (algorithm, uri, count) = match checked {
+            Ok(checked) => checked,
+            Err(err) => return Box::pin(ready(Err(err))),
+        };
+        let (username, nonce, cnonce, response) =
+            match (field("username"), field("nonce"), field("cnonce"), field("response")) {
+                (Ok(username), Ok(nonce), Ok(cnonce), Ok(response)) => {
+                    (username, nonce, cnonce, response)
+                }
+                _ => return Box::pin(ready(Err(TokenError::InvalidCredentials))),
+            };
+
+        let lookup = self.store.credential(&username);
+        Box::pin(async move {
+            let issued_at = state.nonce_issued_at(&nonce).ok_or(TokenError::InvalidCredentials)?;
+            let credential = lookup.await;
+            let password = credential.as_ref().map_or("", |credential| &credential.password);
+
+            let mut ha1 = algorithm.hash(&format!("{}:{}:{}", username, state.realm, password));
+            if algorithm.is_session() {
+                ha1 = algorithm.hash(&format!("{}:{}:{}", ha1, nonce, cnonce));
+            }
+            let ha2 = algorithm.hash(&format!("{}:{}", method, uri));
+            let expected =
+                algorithm.hash(&format!("{}:{}:{:08x}:{}:auth:{}", ha1, nonce, count, cnonce, ha2));
+
+            let matches = constant_time_eq(expected.as_bytes(), response.as_bytes());
+            let credential = match credential {
+                Some(credential) if matches => credential,
+                _ => return Err(TokenError::InvalidCredentials),
+            };
+            if state.clock.now().saturating_sub(issued_at) > state.nonce_lifetime {
+                return Err(TokenError::StaleNonce);
+            }
+            if !state.use_nonce_count(&nonce, issued_at, count) {
+                return Err(TokenError::InvalidCredentials);
+            }
+            Ok(credential.principal)
+        })
+    }
+
+    fn challenges(&self, error: TokenError) -> Vec<String> {
+        if error == TokenError::Forbidden {
+            return Vec::new();
+        }
+        let state = &self.state;
+        let stale = if error == TokenError::StaleNonce { ", stale=true" } else { "" };
+        state
+            .algorithms
+            .iter()
+            .map(|algorithm| {
+                format!(
+                    "Digest realm={}, qop=\"auth\", algorithm={}, nonce={}, opaque={}{}",
+                    quote(&state.realm),
+                    algorithm.as_str(),
+                    quote(&state.nonce()),
+                    quote(&state.opaque),
+                    stale
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::auth_middleware::AuthMiddleware;
+    use crate::helpers::rate_limit_helper::ManualClock;
+    use crate::responses::{ResponsesClientCodes, ResponsesTypes};
+    use actix_web::dev::ServiceResponse;
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+
+    fn users() -> StaticCredentialStore {
+        StaticCredentialStore::new().with_user("alice", "wonderland")
+    }
+
+    async fn whoami(principal: Principal) -> HttpResponse {
+        HttpResponse::Ok().body(principal.id)
+    }
+
+    fn basic(username: &str, password: &str) -> String {
+        format!("Basic {}", STANDARD.encode(format!("{}:{}", username, password)))
+    }
+
+    /// Answers a Digest challenge the way a client would.
+    fn digest(challenge: &str, uri: &str, password: &str, nc: u64) -> String {
+        let params = parse_auth_params(challenge.strip_prefix("Digest ").unwrap());
+        let algorithm = DigestAlgorithm::parse(&params["algorithm"]).unwrap();
+        let (realm, nonce, cnonce) = (&params["realm"], &params["nonce"], "0a4f113b");
+        let mut ha1 = algorithm.hash(&format!("alice:{}:{}", realm, password));
+        if algorithm.is_session() {
+            ha1 = algorithm.hash(&format!("{}:{}:{}", ha1, nonce, cnonce));
+        }
+        let ha2 = algorithm.hash(&format!("GET:{}", uri));
+        let response =
+            algorithm.hash(&format!("{}:{}:{:08x}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2));
+        format!(
+            "Digest username=\"alice\", realm=\"{}\", uri=\"{}\", algorithm={}, nonce=\"{}\", \
+             nc={:08x}, cnonce=\"{}\", qop=auth, response=\"{}\", opaque=\"{}\"",
+            realm,
+            uri,
+            algorithm.as_str(),
+            nonce,
+            nc,
+            cnonce,
+            response,
+            params["opaque"]
+        )
+    }
+
+    fn challenges<B>(resp: &ServiceResponse<B>, header: &str) -> Vec<String> {
+        resp.headers().get_all(header).map(|value| value.to_str().unwrap().to_string()).collect()
+    }
+
+    #[actix_web::test]
+    async fn test_parse_auth_params() {
+        let params = parse_auth_params(
+            r#"username="al\"ice", Realm = "a, b",nc=00000001 , qop=auth, empty="""#,
+        );
+        assert_eq!(params["username"], "al\"ice");
+        assert_eq!(params["realm"], "a, b");
+        assert_eq!(params["nc"], "00000001");
+        assert_eq!(params["qop"], "auth");
+        assert_eq!(params["empty"], "");
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+
+    #[actix_web::test]
+    async fn test_basic() {
+        let app = test::init_service(
+            App::new()
+                .wrap(AuthMiddleware::from_authenticator(BasicAuthenticator::new("tools", users())))
+                .route("/me", web::get().to(whoami)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .insert_header(("Authorization", basic("alice", "wonderland")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(test::read_body(resp).await, "alice");
+
+        for authorization in
+            [basic("alice", "looking-glass"), basic("bob", "wonderland"), "Basic ???".to_string()]
+        {
+            let req = test::TestRequest::get()
+                .uri("/me")
+                .insert_header(("Authorization", authorization))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(resp.headers().get("X-Auth-Error").unwrap(), "Invalid Credentials");
+            assert_eq!(
+                resp.response().extensions().get::<ResponsesTypes>(),
+                Some(&ResponsesTypes::ClientError(ResponsesClientCodes::Unauthorized))
+            );
+            assert_eq!(
+                challenges(&resp, "WWW-Authenticate"),
+                ["Basic realm=\"tools\", charset=\"UTF-8\""]
+            );
+        }
+
+        let req = test::TestRequest::get().uri("/me").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("X-Auth-Error").unwrap(), "Missing Token");
+        assert_eq!(challenges(&resp, "WWW-Authenticate").len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_digest() {
+        let clock = ManualClock::new();
+        let authenticator = DigestAuthenticator::new("devices", users(), "secret")
+            .with_algorithms([DigestAlgorithm::Sha256, DigestAlgorithm::Md5Sess])
+            .with_nonce_lifetime(Duration::from_secs(60))
+            .with_clock(clock.clone());
+        let app = test::init_service(
+            App::new()
+                .wrap(AuthMiddleware::from_authenticator(authenticator))
+                .route("/me", web::get().to(whoami)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/me?verbose=1").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let offered = challenges(&resp, "WWW-Authenticate");
+        assert_eq!(offered.len(), 2);
+        assert!(offered[0].contains("algorithm=SHA-256"));
+        assert!(offered[1].contains("algorithm=MD5-sess"));
+        assert!(offered[0].starts_with("Digest realm=\"devices\", qop=\"auth\""));
+
+        for challenge in &offered {
+            let authorization = digest(challenge, "/me?verbose=1", "wonderland", 1);
+            let req = test::TestRequest::get()
+                .uri("/me?verbose=1")
+                .insert_header(("Authorization", authorization.clone()))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(test::read_body(resp).await, "alice");
+
+            // Replaying the same nonce count is refused
+            let req = test::TestRequest::get()
+                .uri("/me?verbose=1")
+                .insert_header(("Authorization", authorization))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.headers().get("X-Auth-Error").unwrap(), "Invalid Credentials");
+        }
+
+        let challenge = &offered[0];
+        let wrong_password = digest(challenge, "/me?verbose=1", "looking-glass", 2);
+        let other_uri = digest(challenge, "/admin", "wonderland", 3);
+        for authorization in [wrong_password, other_uri] {
+            let req = test::TestRequest::get()
+                .uri("/me?verbose=1")
+                .insert_header(("Authorization", authorization))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        // Once the nonce expires, a correct response is answered with `stale=true`
+        clock.advance(Duration::from_secs(61));
+        let req = test::TestRequest::get()
+            .uri("/me?verbose=1")
+            .insert_header(("Authorization", digest(challenge, "/me?verbose=1", "wonderland", 4)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(resp.headers().get("X-Auth-Error").unwrap(), "Stale Nonce");
+        assert!(challenges(&resp, "WWW-Authenticate").iter().all(|c| c.ends_with("stale=true")));
+    }
+
+    #[actix_web::test]
+    async fn test_digest_across_workers() {
+        // Each worker builds its own authenticator, sharing the secret and the nonce counts
+        let counts = DigestNonceCounts::new();
+        let worker = || {
+            let authenticator = DigestAuthenticator::new("devices", users(), "shared secret")
+                .with_nonce_counts(counts.clone());
+            test::init_service(
+                App::new()
+                    .wrap(AuthMiddleware::from_authenticator(authenticator))
+                    .route("/me", web::get().to(whoami)),
+            )
+        };
+        let (first, second) = (worker().await, worker().await);
+
+        let req = test::TestRequest::get().uri("/me").to_request();
+        let resp = test::call_service(&first, req).await;
+        let challenge = challenges(&resp, "WWW-Authenticate").remove(0);
+
+        // A nonce issued by one worker is accepted by the other
+        let authorization = digest(&challenge, "/me", "wonderland", 1);
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .insert_header(("Authorization", authorization.clone()))
+            .to_request();
+        assert_eq!(test::call_service(&second, req).await.status(), StatusCode::OK);
+
+        // ... and cannot be replayed on the first one
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .insert_header(("Authorization", authorization))
+            .to_request();
+        assert_eq!(test::call_service(&first, req).await.status(), StatusCode::UNAUTHORIZED);
+
+        // Another secret rejects the nonce
+        let other = DigestAuthenticator::new("devices", users(), "other secret");
+        let authorization = digest(&challenge, "/me", "wonderland", 2);
+        let app = test::init_service(
+            App::new()
+                .wrap(AuthMiddleware::from_authenticator(other))
+                .route("/me", web::get().to(whoami)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .insert_header(("Authorization", authorization))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn test_proxy_and_combined_schemes() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    AuthMiddleware::from_authenticator(DigestAuthenticator::new(
+                        "proxy",
+                        users(),
+                        "secret",
+                    ))
+                    .with_authenticator(BasicAuthenticator::new("proxy", users()))
+                    .as_proxy(),
+                )
+                .route("/me", web::get().to(whoami)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/me").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::PROXY_AUTHENTICATION_REQUIRED);
+        assert_eq!(
+            resp.response().extensions().get::<ResponsesTypes>(),
+            Some(&ResponsesTypes::ClientError(ResponsesClientCodes::ProxyAuthenticationRequired))
+        );
+        assert!(resp.headers().get("WWW-Authenticate").is_none());
+        let offered = challenges(&resp, "Proxy-Authenticate");
+        assert_eq!(offered.len(), 3);
+        assert!(offered[2].starts_with("Basic "));
+
+        // Origin credentials do not authenticate against the proxy
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .insert_header(("Authorization", basic("alice", "wonderland")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::PROXY_AUTHENTICATION_REQUIRED);
+
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .insert_header(("Proxy-Authorization", basic("alice", "wonderland")))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .insert_header(("Proxy-Authorization", digest(&offered[1], "/me", "wonderland", 1)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -6,10 +6,26 @@
 ///
 /// This struct implements the `Service` trait, allowing it to intercept
 /// HTTP requests and responses. It adds the following headers to the response:
-/// - `x-request-id`: A unique identifier for the request.
+/// - `x-request-id`: A unique identifier for the request (see below).
 /// - `x-response-time-ms`: The time taken to process the request in milliseconds.
 /// - `x-status-description`: A description of the status code, if available.
 ///
+/// # Request IDs
+/// The ID is taken from the first incoming source carrying a well-formed value
+/// (`X-Request-ID`, `X-Correlation-ID`, then the trace id of a W3C `traceparent` by default),
+/// so IDs propagate across services. Otherwise a new one is generated (UUID v4 by default).
+/// It is stored in the request extensions, where handlers receive it through the `RequestId`
+/// extractor.
+///
+/// # Tracing
+/// The W3C trace context of the request is stored in the request extensions (see
+/// `tracing_helper`). With the `tracing` feature, each request runs in an `http.request`
+/// span recording `http.status_code`, `simbld.internal_code` and `simbld.family`.
+///
+/// # Access log
+/// With `with_access_log`, one `AccessLogRecord` is emitted per request once its response
+/// is ready (see `access_log_helper`).
+///
 /// # Types
 /// - `Response`: The type of the response, which is `ServiceResponse<B>`.
 /// - `Error`: The type of the error, which is `Error`.
@@ -26,21 +42,233 @@
 /// # Returns
 /// - `poll_ready`: A `Poll` indicating if the service is ready.
 /// - `call`: A future that resolves to the intercepted response with custom headers.
+use crate::helpers::access_log_helper::{AccessLog, AccessLogRecord};
+use crate::helpers::auth_middleware::Principal;
+use crate::helpers::client_ip_helper::{client_ip, ClientIp};
+use crate::helpers::tracing_helper::{parse_traceparent, TraceContext};
+use crate::responses::ResponsesTypes;
+#[cfg(feature = "tracing")]
+use crate::utils::populate_metadata::status_family;
 use actix_service::{Service, Transform};
-use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header;
 use actix_web::http::header::{HeaderName, HeaderValue};
-use actix_web::Error;
-use futures_util::future::{ok, LocalBoxFuture, Ready};
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use futures_util::future::{ok, ready, LocalBoxFuture, Ready};
+use std::fmt;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
+
+/// Longest incoming ID accepted, longer values are replaced by a generated one.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Identifier of the request, stored in the request extensions by `HttpInterceptor`.
+///
+/// Handlers receive it as an extractor, to embed it in response bodies and logs.
+/// Fails with `500 Internal Server Error` when the interceptor is not installed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Wraps an identifier.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self(id.into())
+    }
 
-#[derive(Debug)]
-pub struct HttpInterceptor;
+    /// Returns the identifier.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromRequest for RequestId {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<RequestId>().cloned().ok_or_else(|| {
+            actix_web::error::ErrorInternalServerError("RequestId requires HttpInterceptor")
+        }))
+    }
+}
+
+/// Generates request IDs.
+///
+/// Any `Fn() -> String` closure is a generator as well.
+pub trait RequestIdGenerator: Send + Sync {
+    fn generate(&self) -> String;
+}
+
+impl<F> RequestIdGenerator for F
+where
+    F: Fn() -> String + Send + Sync,
+{
+    fn generate(&self) -> String {
+        self()
+    }
+}
+
+/// Random UUID (version 4).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UuidV4;
+
+impl RequestIdGenerator for UuidV4 {
+    fn generate(&self) -> String {
+        uuid::Uuid::new_v4().to_string()
+    }
+}
+
+/// Time-ordered UUID (version 7), sortable by creation time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UuidV7;
+
+impl RequestIdGenerator for UuidV7 {
+    fn generate(&self) -> String {
+        uuid::Uuid::now_v7().to_string()
+    }
+}
+
+/// ULID: 48-bit millisecond timestamp and 80 random bits, in Crockford's base32.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ulid;
+
+impl RequestIdGenerator for Ulid {
+    fn generate(&self) -> String {
+        const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let random = u128::from_be_bytes(uuid::Uuid::new_v4().into_bytes()) >> 48;
+        let value = ((millis & 0xFFFF_FFFF_FFFF) << 80) | random;
+        (0..26)
+            .rev()
+            .map(|index| ALPHABET[((value >> (index * 5)) & 0x1F) as usize] as char)
+            .collect()
+    }
+}
+
+/// Where an incoming request ID may be found.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RequestIdSource {
+    /// A header carrying the ID as is (e.g. `X-Request-ID`).
+    Header(HeaderName),
+    /// The trace id of a W3C `traceparent` header.
+    TraceParent,
+}
+
+impl RequestIdSource {
+    /// Returns the well-formed ID carried by the request, if any.
+    ///
+    /// IDs are limited to 128 visible ASCII characters without spaces or quotes,
+    /// so that they can be copied safely into headers and logs.
+    pub fn extract(&self, req: &ServiceRequest) -> Option<String> {
+        match self {
+            RequestIdSource::Header(name) => {
+                let id = req.headers().get(name)?.to_str().ok()?.trim();
+                let well_formed = !id.is_empty()
+                    && id.len() <= MAX_REQUEST_ID_LEN
+                    && id.bytes().all(|b| b.is_ascii_graphic() && b != b'"' && b != b'\\');
+                well_formed.then(|| id.to_string())
+            }
+            RequestIdSource::TraceParent => {
+                // Same rules as `TraceContext`, so that the request and trace IDs agree
+                let value = req.headers().get("traceparent")?.to_str().ok()?;
+                parse_traceparent(value).map(|(trace_id, _, _)| trace_id)
+            }
+        }
+    }
+}
+
+/// Middleware adding request IDs, timing and status description headers.
+#[derive(Clone)]
+pub struct HttpInterceptor {
+    generator: Arc<dyn RequestIdGenerator>,
+    sources: Vec<RequestIdSource>,
+    header: HeaderName,
+    access_log: Option<AccessLog>,
+}
+
+impl fmt::Debug for HttpInterceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpInterceptor")
+            .field("sources", &self.sources)
+            .field("header", &self.header)
+            .field("access_log", &self.access_log.as_ref().map(AccessLog::format))
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for HttpInterceptor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpInterceptor {
+    /// Creates an interceptor honoring incoming `X-Request-ID`, `X-Correlation-ID` and
+    /// `traceparent`, and generating UUID v4 IDs otherwise.
+    pub fn new() -> Self {
+        Self {
+            generator: Arc::new(UuidV4),
+            sources: vec![
+                RequestIdSource::Header(HeaderName::from_static("x-request-id")),
+                RequestIdSource::Header(HeaderName::from_static("x-correlation-id")),
+                RequestIdSource::TraceParent,
+            ],
+            header: HeaderName::from_static("x-request-id"),
+            access_log: None,
+        }
+    }
+
+    /// Replaces the generator of new IDs.
+    pub fn with_generator(mut self, generator: impl RequestIdGenerator + 'static) -> Self {
+        self.generator = Arc::new(generator);
+        self
+    }
+
+    /// Replaces the places where incoming IDs are looked up, in order.
+    pub fn with_sources(mut self, sources: impl IntoIterator<Item = RequestIdSource>) -> Self {
+        self.sources = sources.into_iter().collect();
+        self
+    }
+
+    /// Ignores incoming IDs: every request gets a generated one.
+    pub fn ignore_incoming(self) -> Self {
+        self.with_sources([])
+    }
+
+    /// Sets the response header carrying the ID (`x-request-id` by default).
+    pub fn with_header(mut self, header: HeaderName) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Emits one access log record per request.
+    pub fn with_access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    /// Resolves the ID of a request: the first incoming one, or a generated one.
+    pub fn request_id(&self, req: &ServiceRequest) -> RequestId {
+        let id = self.sources.iter().find_map(|source| source.extract(req));
+        RequestId(id.unwrap_or_else(|| self.generator.generate()))
+    }
+}
 
 impl<S, B> Transform<S, ServiceRequest> for HttpInterceptor
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
-    B: 'static,
+    B: MessageBody + 'static,
 {
     type Response = ServiceResponse<B>;
     type Error = Error;
@@ -49,18 +277,19 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ok(HttpInterceptorMiddleware { service: Rc::new(service) })
+        ok(HttpInterceptorMiddleware { service: Rc::new(service), config: Rc::new(self.clone()) })
     }
 }
 
 pub struct HttpInterceptorMiddleware<S> {
     service: Rc<S>,
+    config: Rc<HttpInterceptor>,
 }
 
 impl<S, B> Service<ServiceRequest> for HttpInterceptorMiddleware<S>
 where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
-    B: 'static,
+    B: MessageBody + 'static,
 {
     type Response = ServiceResponse<B>;
     type Error = Error;
@@ -72,17 +301,60 @@ where
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let service = Rc::clone(&self.service);
+        let header = self.config.header.clone();
+        let request_id = self.config.request_id(&req);
+        let trace_context = TraceContext::from_headers(req.headers());
+        #[cfg(feature = "tracing")]
+        let span = request_span(&req, &request_id, &trace_context);
+        req.extensions_mut().insert(request_id.clone());
+        req.extensions_mut().insert(trace_context);
+        let access_log = self.config.access_log.clone();
+        let record = access_log.as_ref().map(|_| pending_record(&req, &request_id));
+        #[cfg(feature = "tracing")]
+        let fut = span.in_scope(|| service.call(req));
+        #[cfg(not(feature = "tracing"))]
         let fut = service.call(req);
         let start_time = std::time::Instant::now();
-        let request_id = uuid::Uuid::new_v4().to_string();
 
-        Box::pin(async move {
-            let mut res = fut.await?;
+        let handle = async move {
+            #[cfg(feature = "tracing")]
+            let result = fut.instrument(span.clone()).await;
+            #[cfg(not(feature = "tracing"))]
+            let result = fut.await;
+            #[cfg(feature = "tracing")]
+            record_outcome(&span, &result);
+            if let (Some(access_log), Some(mut record)) = (access_log, record) {
+                record.duration = start_time.elapsed();
+                match &result {
+                    Ok(res) => {
+                        record.status = res.status().as_u16();
+                        record.bytes = match res.response().body().size() {
+                            BodySize::Sized(bytes) => Some(bytes),
+                            BodySize::None => Some(0),
+                            BodySize::Stream => None,
+                        };
+                        record.user =
+                            res.request().extensions().get::<Principal>().map(|p| p.id.clone());
+                        // Resolved by an inner `ClientIpResolver`, if any
+                        if let Some(client) = res.request().extensions().get::<ClientIp>() {
+                            record.client_ip = Some(client.ip.to_string());
+                        }
+                        if let Some(response) = res.response().extensions().get::<ResponsesTypes>()
+                        {
+                            record = record.with_response_type(response);
+                        }
+                    }
+                    Err(err) => {
+                        record.status = err.as_response_error().status_code().as_u16();
+                    }
+                }
+                access_log.emit(&record);
+            }
+            let mut res = result?;
 
-            res.headers_mut().insert(
-                HeaderName::from_static("x-request-id"),
-                HeaderValue::from_str(&request_id).unwrap(),
-            );
+            if let Ok(value) = HeaderValue::from_str(request_id.as_str()) {
+                res.headers_mut().insert(header, value);
+            }
 
             let duration = start_time.elapsed().as_millis();
             res.headers_mut().insert(
@@ -101,7 +373,73 @@ where
             }
 
             Ok(res)
-        })
+        };
+        Box::pin(handle)
+    }
+}
+
+/// Opens the span of a request, whose outcome fields are recorded by `record_outcome`.
+#[cfg(feature = "tracing")]
+fn request_span(
+    req: &ServiceRequest,
+    request_id: &RequestId,
+    trace_context: &TraceContext,
+) -> tracing::Span {
+    tracing::info_span!(
+        target: "simbld_http::http",
+        "http.request",
+        http.method = %req.method(),
+        http.route = req.match_pattern().as_deref().unwrap_or(req.path()),
+        http.request_id = request_id.as_str(),
+        trace_id = trace_context.trace_id.as_str(),
+        span_id = trace_context.span_id.as_str(),
+        parent_span_id = trace_context.parent_id.as_deref(),
+        http.status_code = tracing::field::Empty,
+        simbld.internal_code = tracing::field::Empty,
+        simbld.family = tracing::field::Empty,
+    )
+}
+
+/// Records the standard code, the catalog code and its family on the span of a request.
+#[cfg(feature = "tracing")]
+fn record_outcome<B>(span: &tracing::Span, result: &Result<ServiceResponse<B>, Error>) {
+    let (status, response) = match result {
+        Ok(res) => {
+            (res.status().as_u16(), res.response().extensions().get::<ResponsesTypes>().copied())
+        }
+        Err(err) => (err.as_response_error().status_code().as_u16(), None),
+    };
+    let internal_code = response.map(|response| {
+        let code = response.as_tuple();
+        code.internal_code.unwrap_or(code.standard_code)
+    });
+    span.record("http.status_code", status);
+    if let Some(internal_code) = internal_code {
+        span.record("simbld.internal_code", internal_code);
+    }
+    span.record("simbld.family", status_family(internal_code.unwrap_or(status)));
+}
+
+/// Captures what is known about the request before it is handled.
+fn pending_record(req: &ServiceRequest, request_id: &RequestId) -> AccessLogRecord {
+    let header = |name: header::HeaderName| {
+        req.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string)
+    };
+    AccessLogRecord {
+        timestamp: chrono::Utc::now(),
+        method: req.method().to_string(),
+        path: req.uri().path_and_query().map_or_else(|| req.path().to_string(), |p| p.to_string()),
+        version: format!("{:?}", req.version()),
+        status: 0,
+        internal_code: None,
+        internal_name: None,
+        duration: Default::default(),
+        bytes: None,
+        request_id: Some(request_id.to_string()),
+        client_ip: client_ip(req.request()).map(|ip| ip.to_string()),
+        user: None,
+        user_agent: header(header::USER_AGENT),
+        referer: header(header::REFERER),
     }
 }
 
@@ -109,12 +447,12 @@ where
 mod tests {
     use super::*;
     use actix_web::{test, web, App, HttpResponse};
-    
+
     #[actix_web::test]
     async fn test_http_interceptor() {
         let app = test::init_service(
             App::new()
-                .wrap(HttpInterceptor)
+                .wrap(HttpInterceptor::new())
                 .route("/", web::get().to(|| async { HttpResponse::Ok().body("Hello World") })),
         )
         .await;
@@ -131,7 +469,7 @@ mod tests {
     async fn test_http_interceptor_adds_header() {
         let app = test::init_service(
             App::new()
-                .wrap(HttpInterceptor)
+                .wrap(HttpInterceptor::new())
                 .route("/", web::get().to(|| async { HttpResponse::Ok().finish() })),
         )
         .await;
@@ -151,4 +489,141 @@ mod tests {
             "Request processed successfully. Response will depend on the request method used, and the result will be either a representation of the requested resource or an empty response"
         );
     }
+
+    #[actix_web::test]
+    async fn test_request_id_propagation() {
+        let app = test::init_service(App::new().wrap(HttpInterceptor::new()).route(
+            "/",
+            web::get().to(|id: RequestId| async move { HttpResponse::Ok().body(id.to_string()) }),
+        ))
+        .await;
+
+        let cases = [
+            (("X-Request-ID", "req-42"), "req-42"),
+            (("X-Correlation-ID", "corr-7"), "corr-7"),
+            (
+                ("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+                "4bf92f3577b34da6a3ce929d0e0e4736",
+            ),
+        ];
+        for (header, expected) in cases {
+            let req = test::TestRequest::with_uri("/").insert_header(header).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.headers().get("x-request-id").unwrap(), expected);
+            assert_eq!(test::read_body(resp).await, expected);
+        }
+
+        // Malformed IDs are replaced by a generated one
+        let malformed = [
+            ("X-Request-ID", "has spaces"),
+            ("X-Request-ID", &"x".repeat(129)),
+            ("traceparent", "00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            ("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01"),
+            ("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-x1"),
+        ];
+        for header in malformed {
+            let req = test::TestRequest::with_uri("/").insert_header(header).to_request();
+            let resp = test::call_service(&app, req).await;
+            let id = resp.headers().get("x-request-id").unwrap().to_str().unwrap().to_string();
+            assert!(uuid::Uuid::parse_str(&id).is_ok(), "expected a generated id, got {}", id);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_request_id_generators() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    HttpInterceptor::new()
+                        .with_generator(UuidV7)
+                        .ignore_incoming()
+                        .with_header(HeaderName::from_static("x-correlation-id")),
+                )
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req =
+            test::TestRequest::with_uri("/").insert_header(("X-Request-ID", "req-42")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.headers().get("x-request-id").is_none());
+        let id = resp.headers().get("x-correlation-id").unwrap().to_str().unwrap();
+        assert_eq!(uuid::Uuid::parse_str(id).unwrap().get_version_num(), 7);
+
+        let ulid = Ulid.generate();
+        assert_eq!(ulid.len(), 26);
+        assert!(ulid.bytes().all(|b| b"0123456789ABCDEFGHJKMNPQRSTVWXYZ".contains(&b)));
+
+        let fixed = || "fixed".to_string();
+        assert_eq!(HttpInterceptor::new().with_generator(fixed).generator.generate(), "fixed");
+    }
+
+    #[cfg(feature = "tracing")]
+    #[actix_web::test]
+    async fn test_request_span_fields() {
+        use crate::responses::ResponsesLocalApiCodes;
+        use std::sync::Mutex;
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+
+        /// Collects the fields of every span, as strings.
+        #[derive(Default)]
+        struct Fields(Mutex<Vec<(String, String)>>);
+
+        impl Visit for &Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                let value = format!("{:?}", value).trim_matches('"').to_string();
+                self.0.lock().unwrap().push((field.name().to_string(), value));
+            }
+        }
+
+        struct Collector(Arc<Fields>);
+
+        impl tracing::Subscriber for Collector {
+            fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                span.record(&mut &*self.0);
+                Id::from_u64(1)
+            }
+            fn record(&self, _: &Id, values: &Record<'_>) {
+                values.record(&mut &*self.0);
+            }
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &tracing::Event<'_>) {}
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let fields = Arc::new(Fields::default());
+        let _guard = tracing::subscriber::set_default(Collector(fields.clone()));
+        let app = test::init_service(App::new().wrap(HttpInterceptor::new()).route(
+            "/users/{id}",
+            web::get().to(|| async {
+                ResponsesTypes::LocalApiError(ResponsesLocalApiCodes::OperationNotAllowed)
+                    .into_http_response()
+            }),
+        ))
+        .await;
+
+        let req = test::TestRequest::with_uri("/users/7")
+            .insert_header((
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            ))
+            .to_request();
+        test::call_service(&app, req).await;
+
+        let fields = fields.0.lock().unwrap();
+        let field = |name: &str| {
+            fields.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+        };
+        assert_eq!(field("http.route"), Some("/users/{id}"));
+        assert_eq!(field("trace_id"), Some("4bf92f3577b34da6a3ce929d0e0e4736"));
+        assert_eq!(field("parent_span_id"), Some("00f067aa0ba902b7"));
+        assert_eq!(field("http.status_code"), Some("403"));
+        assert_eq!(field("simbld.internal_code"), Some("904"));
+        assert_eq!(field("simbld.family"), Some("Local API Error"));
+    }
 }
@@ -0,0 +1,728 @@
+//! # Idempotency Keys
+//!
+//! Provides `Idempotency`, a middleware making retried requests safe: the first response to
+//! a request carrying an `Idempotency-Key` header is stored, and replayed byte for byte,
+//! with an `Idempotent-Replayed: true` header, to every retry of that request.
+//!
+//! Keys are scoped per client (the authenticated `Principal`, or else the client IP), and
+//! bound to a fingerprint of the method, path and body of the first request:
+//! - A retry arriving while the first request is still being handled is refused with
+//!   `Conflict` (409).
+//! - Reusing a key for a different request is refused with `DuplicatedTransactionId` (902).
+//! - Server errors and failed handlers are not stored, so the request can be retried.
+//! - Request bodies larger than the configured limit (1 MiB by default) are refused with
+//!   `ContentTooLarge` (413). Streaming responses, and responses larger than the limit,
+//!   are passed through without being stored.
+//!
+//! Records are kept in an `IdempotencyStore`, for a configurable time-to-live.
+//! `InMemoryIdempotencyStore` keeps them in the process; other backends implement the trait.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::{web, App, HttpResponse};
+//! use simbld_http::helpers::idempotency_helper::{Idempotency, InMemoryIdempotencyStore};
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let idempotency = Idempotency::new(Arc::new(InMemoryIdempotencyStore::new()))
+//!     .with_ttl(Duration::from_secs(24 * 3600));
+//!
+//! let app = App::new().service(
+//!     web::scope("/payments")
+//!         .wrap(idempotency)
+//!         .route("", web::post().to(HttpResponse::Created)),
+//! );
+//! ```
+
+use crate::helpers::auth_middleware::Principal;
+use crate::helpers::client_ip_helper::client_ip;
+use crate::helpers::rate_limit_helper::{Clock, SystemClock};
+use crate::helpers::tracing_helper::trace_event;
+use crate::responses::{
+    ResponsesClientCodes, ResponsesLocalApiCodes, ResponsesServerCodes, ResponsesTypes,
+};
+use actix_web::{
+    body::{self, BodySize, BoxBody, EitherBody, MessageBody},
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{self, HeaderName, HeaderValue},
+        Method, StatusCode,
+    },
+    web::{Bytes, BytesMut},
+    Error, HttpMessage, HttpResponse, ResponseError,
+};
+use futures_util::future::{ok, ready, LocalBoxFuture, Ready};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use thiserror::Error;
+
+const IDEMPOTENCY_TARGET: &str = "simbld_http::idempotency";
+
+/// Header carrying the idempotency key of a request.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Header marking replayed responses.
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// Maximum length of an idempotency key.
+pub const MAX_KEY_LENGTH: usize = 255;
+
+/// Default time-to-live of stored responses.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Default maximum size of the request and response bodies buffered by `Idempotency`.
+pub const DEFAULT_MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+/// Default time after which a request still in flight no longer holds its key.
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Reasons for refusing a request carrying an idempotency key.
+#[derive(Debug, Clone, Copy, PartialEq, Error)]
+pub enum IdempotencyError {
+    #[error("Invalid Idempotency Key")]
+    InvalidKey,
+    #[error("Conflict")]
+    Conflict,
+    #[error("Duplicated Transaction ID")]
+    DuplicatedTransactionId,
+    #[error("Content Too Large")]
+    ContentTooLarge,
+    #[error("Idempotency Store Unavailable")]
+    StoreUnavailable,
+}
+
+impl IdempotencyError {
+    /// Returns the catalog response describing the refusal.
+    pub fn response_type(&self) -> ResponsesTypes {
+        match self {
+            IdempotencyError::InvalidKey => {
+                ResponsesTypes::ClientError(ResponsesClientCodes::BadRequest)
+            }
+            IdempotencyError::Conflict => {
+                ResponsesTypes::ClientError(ResponsesClientCodes::Conflict)
+            }
+            IdempotencyError::DuplicatedTransactionId => {
+                ResponsesTypes::LocalApiError(ResponsesLocalApiCodes::DuplicatedTransactionId)
+            }
+            IdempotencyError::ContentTooLarge => {
+                ResponsesTypes::ClientError(ResponsesClientCodes::ContentTooLarge)
+            }
+            IdempotencyError::StoreUnavailable => {
+                ResponsesTypes::ServerError(ResponsesServerCodes::InternalServerError)
+            }
+        }
+    }
+}
+
+impl ResponseError for IdempotencyError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.response_type().get_code()).unwrap_or(StatusCode::BAD_REQUEST)
+    }
+
+    /// Renders the catalog body, with `X-Idempotency-Error` naming the refusal.
+    fn error_response(&self) -> HttpResponse {
+        let response = self.response_type();
+        response
+            .response_builder()
+            .insert_header(("X-HTTP-Status-Code", response.get_code().to_string()))
+            .insert_header(("X-Idempotency-Error", self.to_string()))
+            .content_type("application/json")
+            .body(response.as_json().to_string())
+    }
+}
+
+/// Errors raised by idempotency stores.
+#[derive(Debug, Error)]
+pub enum IdempotencyStoreError {
+    #[error("The idempotency store lock was poisoned.")]
+    Poisoned,
+    #[error("Idempotency store failure: {0}")]
+    Backend(String),
+}
+
+/// A response as stored for replay.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub body: Vec<u8>,
+}
+
+impl StoredResponse {
+    /// Captures the status, headers and body of a response.
+    pub fn new(response: &HttpResponse<()>, body: &[u8]) -> Self {
+        Self {
+            status: response.status().as_u16(),
+            headers: response
+                .headers()
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+                .collect(),
+            body: body.to_vec(),
+        }
+    }
+
+    /// Rebuilds the response, marked with `Idempotent-Replayed: true`.
+    pub fn replay(&self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+        let mut builder = HttpResponse::build(status);
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) =
+                (HeaderName::try_from(name.as_str()), HeaderValue::from_bytes(value))
+            {
+                builder.append_header((name, value));
+            }
+        }
+        builder.insert_header((REPLAYED_HEADER, "true")).body(self.body.clone())
+    }
+}
+
+/// What a store knows about an idempotency key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IdempotencyRecord {
+    /// The first request is still being handled.
+    InFlight { fingerprint: String },
+    /// The first request was answered with `response`.
+    Completed { fingerprint: String, response: StoredResponse },
+}
+
+impl IdempotencyRecord {
+    /// Returns the fingerprint of the request which created the record.
+    pub fn fingerprint(&self) -> &str {
+        match self {
+            IdempotencyRecord::InFlight { fingerprint }
+            | IdempotencyRecord::Completed { fingerprint, .. } => fingerprint,
+        }
+    }
+}
+
+/// Storage of idempotency records.
+pub trait IdempotencyStore: Send + Sync {
+    /// Atomically reserves `key` as in flight, unless a live record exists for it.
+    ///
+    /// Returns `None` when the key was reserved, or the existing record.
+    ///
+    /// # arguments
+    ///
+    /// * `key` - The idempotency key, scoped to the client
+    /// * `fingerprint` - Fingerprint of the request
+    /// * `now` - Current time, as returned by the middleware `Clock`
+    /// * `ttl` - How long the reservation holds
+    ///
+    fn reserve(
+        &self,
+        key: String,
+        fingerprint: String,
+        now: Duration,
+        ttl: Duration,
+    ) -> LocalBoxFuture<'static, Result<Option<IdempotencyRecord>, IdempotencyStoreError>>;
+
+    /// Replaces the reservation of `key` with the response to replay, kept for `ttl`.
+    fn complete(
+        &self,
+        key: String,
+        fingerprint: String,
+        response: StoredResponse,
+        now: Duration,
+        ttl: Duration,
+    ) -> LocalBoxFuture<'static, Result<(), IdempotencyStoreError>>;
+
+    /// Removes the record of `key`, so that the request can be retried.
+    fn release(&self, key: String) -> LocalBoxFuture<'static, Result<(), IdempotencyStoreError>>;
+}
+
+/// In-process store evicting records once their TTL has elapsed.
+#[derive(Debug, Default)]
+pub struct InMemoryIdempotencyStore {
+    inner: Mutex<IdempotencyEntries>,
+}
+
+#[derive(Debug, Default)]
+struct IdempotencyEntries {
+    records: HashMap<String, (IdempotencyRecord, Duration)>,
+    expirations: BTreeSet<(Duration, String)>,
+}
+
+impl IdempotencyEntries {
+    /// Removes every record whose TTL has elapsed.
+    fn evict_expired(&mut self, now: Duration) {
+        while self.expirations.first().is_some_and(|(expires_at, _)| *expires_at <= now) {
+            if let Some((_, key)) = self.expirations.pop_first() {
+                self.records.remove(&key);
+            }
+        }
+    }
+
+    fn insert(&mut self, key: String, record: IdempotencyRecord, expires_at: Duration) {
+        self.remove(&key);
+        self.expirations.insert((expires_at, key.clone()));
+        self.records.insert(key, (record, expires_at));
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((_, expires_at)) = self.records.remove(key) {
+            self.expirations.remove(&(expires_at, key.to_string()));
+        }
+    }
+}
+
+impl InMemoryIdempotencyStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of records currently stored, including not yet evicted expired ones.
+    pub fn len(&self) -> usize {
+        self.inner.lock().map(|entries| entries.records.len()).unwrap_or(0)
+    }
+
+    /// Returns `true` when no record is stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn entries(
+        &self,
+    ) -> Result<std::sync::MutexGuard<'_, IdempotencyEntries>, IdempotencyStoreError> {
+        self.inner.lock().map_err(|_| IdempotencyStoreError::Poisoned)
+    }
+}
+
+impl IdempotencyStore for InMemoryIdempotencyStore {
+    fn reserve(
+        &self,
+        key: String,
+        fingerprint: String,
+        now: Duration,
+        ttl: Duration,
+    ) -> LocalBoxFuture<'static, Result<Option<IdempotencyRecord>, IdempotencyStoreError>> {
+        let result = self.entries().map(|mut entries| {
+            entries.evict_expired(now);
+            if let Some((record, _)) = entries.records.get(&key) {
+                return Some(record.clone());
+            }
+            entries.insert(key, IdempotencyRecord::InFlight { fingerprint }, now + ttl);
+            None
+        });
+        Box::pin(ready(result))
+    }
+
+    fn complete(
+        &self,
+        key: String,
+        fingerprint: String,
+        response: StoredResponse,
+        now: Duration,
+        ttl: Duration,
+    ) -> LocalBoxFuture<'static, Result<(), IdempotencyStoreError>> {
+        let result = self.entries().map(|mut entries| {
+            entries.insert(key, IdempotencyRecord::Completed { fingerprint, response }, now + ttl);
+        });
+        Box::pin(ready(result))
+    }
+
+    fn release(&self, key: String) -> LocalBoxFuture<'static, Result<(), IdempotencyStoreError>> {
+        let result = self.entries().map(|mut entries| entries.remove(&key));
+        Box::pin(ready(result))
+    }
+}
+
+/// Middleware storing and replaying the responses of requests carrying an idempotency key.
+#[derive(Clone)]
+pub struct Idempotency {
+    store: Arc<dyn IdempotencyStore>,
+    header: HeaderName,
+    methods: Vec<Method>,
+    ttl: Duration,
+    lock_timeout: Duration,
+    max_body_size: u64,
+    clock: Arc<dyn Clock>,
+}
+
+impl Idempotency {
+    /// Creates the middleware for `POST` and `PATCH` requests, keeping records in `store`,
+    /// and buffering bodies up to 1 MiB.
+    pub fn new(store: Arc<dyn IdempotencyStore>) -> Self {
+        Self {
+            store,
+            header: HeaderName::from_static(IDEMPOTENCY_KEY_HEADER),
+            methods: vec![Method::POST, Method::PATCH],
+            ttl: DEFAULT_TTL,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Reads the key from another header.
+    pub fn with_header(mut self, header: HeaderName) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Sets the methods whose requests are made idempotent.
+    pub fn with_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+        self.methods = methods.into_iter().collect();
+        self
+    }
+
+    /// Sets how long responses are kept for replay.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets how long a request in flight holds its key, should it never complete.
+    pub fn with_lock_timeout(mut self, lock_timeout: Duration) -> Self {
+        self.lock_timeout = lock_timeout;
+        self
+    }
+
+    /// Sets the maximum size of the request bodies accepted, and of the responses stored.
+    pub fn with_max_body_size(mut self, max_body_size: u64) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Replaces the clock used to expire records.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Returns the key of `req` scoped to its client, or `None` when it carries no key.
+    fn scoped_key(&self, req: &ServiceRequest) -> Option<Result<String, IdempotencyError>> {
+        let value = req.headers().get(&self.header)?;
+        let key = match value.to_str() {
+            Ok(key) if !key.trim().is_empty() && key.len() <= MAX_KEY_LENGTH => key.trim(),
+            _ => return Some(Err(IdempotencyError::InvalidKey)),
+        };
+        let client = match req.extensions().get::<Principal>() {
+            Some(principal) => format!("principal:{}", principal.id),
+            None => client_ip(req.request())
+                .map_or_else(|| "anonymous".to_string(), |ip| format!("ip:{}", ip)),
+        };
+        Some(Ok(format!("{}|{}", client, key)))
+    }
+}
+
+/// Hashes the method, path, query and body of a request.
+fn fingerprint(req: &ServiceRequest, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(req.method().as_str());
+    hasher.update(b"\n");
+    hasher.update(req.uri().path_and_query().map_or(req.path(), |p| p.as_str()));
+    hasher.update(b"\n");
+    hasher.update(body);
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Idempotency
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Transform = IdempotencyService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(IdempotencyService { service: Rc::new(service), config: Rc::new(self.clone()) })
+    }
+}
+
+/// Service created by `Idempotency` to process requests.
+pub struct IdempotencyService<S> {
+    service: Rc<S>,
+    config: Rc<Idempotency>,
+}
+
+impl<S, B> Service<ServiceRequest> for IdempotencyService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    /// Replays stored responses, refuses conflicting retries, and stores first responses.
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let config = self.config.clone();
+        let service = self.service.clone();
+        let key = match config.methods.contains(req.method()) {
+            true => config.scoped_key(&req),
+            false => None,
+        };
+        let key = match key {
+            None => {
+                let fut = service.call(req);
+                return Box::pin(async move { fut.await.map(|res| res.map_into_left_body()) });
+            }
+            Some(Err(err)) => {
+                return Box::pin(async move {
+                    Ok(req.into_response(err.error_response()).map_into_right_body())
+                })
+            }
+            Some(Ok(key)) => key,
+        };
+
+        Box::pin(async move {
+            let too_large = |req: ServiceRequest| {
+                let err = IdempotencyError::ContentTooLarge;
+                Ok(req.into_response(err.error_response()).map_into_right_body())
+            };
+            let length = req
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<u64>().ok());
+            if length.is_some_and(|length| length > config.max_body_size) {
+                return too_large(req);
+            }
+
+            // Buffer the body to fingerprint it, then hand it over to the handler
+            let mut payload = req.take_payload();
+            let mut body = BytesMut::new();
+            while let Some(chunk) = payload.next().await {
+                body.extend_from_slice(&chunk?);
+                if body.len() as u64 > config.max_body_size {
+                    return too_large(req);
+                }
+            }
+            let body = body.freeze();
+            let fingerprint = fingerprint(&req, &body);
+            req.set_payload(Payload::Stream {
+                payload: Box::pin(stream::once(ready(Ok::<Bytes, _>(body)))),
+            });
+
+            let now = config.clock.now();
+            let reserved = config
+                .store
+                .reserve(key.clone(), fingerprint.clone(), now, config.lock_timeout)
+                .await;
+            let refusal = match reserved {
+                Ok(None) => None,
+                Ok(Some(record)) if record.fingerprint() != fingerprint => {
+                    Some(IdempotencyError::DuplicatedTransactionId)
+                }
+                Ok(Some(IdempotencyRecord::InFlight { .. })) => Some(IdempotencyError::Conflict),
+                Ok(Some(IdempotencyRecord::Completed { response, .. })) => {
+                    return Ok(req.into_response(response.replay()).map_into_right_body());
+                }
+                Err(err) => {
+                    trace_event!(error, target: IDEMPOTENCY_TARGET, { error = %err, }, "Idempotency store failure: {}", err);
+                    Some(IdempotencyError::StoreUnavailable)
+                }
+            };
+            if let Some(err) = refusal {
+                return Ok(req.into_response(err.error_response()).map_into_right_body());
+            }
+
+            let release = |key: String| async {
+                if let Err(err) = config.store.release(key).await {
+                    trace_event!(error, target: IDEMPOTENCY_TARGET, { error = %err, }, "Idempotency store failure: {}", err);
+                }
+            };
+            let res = match service.call(req).await {
+                Ok(res) if !res.status().is_server_error() => res,
+                other => {
+                    release(key).await;
+                    return other.map(|res| res.map_into_left_body());
+                }
+            };
+            // Streaming and large responses are not buffered, so they cannot be replayed
+            let storable = match res.response().body().size() {
+                BodySize::None => true,
+                BodySize::Sized(size) => size <= config.max_body_size,
+                BodySize::Stream => false,
+            };
+            if !storable {
+                release(key).await;
+                return Ok(res.map_into_left_body());
+            }
+
+            let (req, res) = res.into_parts();
+            let (head, body) = res.into_parts();
+            let body = match body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(err) => {
+                    release(key).await;
+                    return Err(actix_web::error::ErrorInternalServerError(err.into()));
+                }
+            };
+            let stored = StoredResponse::new(&head, &body);
+            let completed =
+                config.store.complete(key, fingerprint, stored, config.clock.now(), config.ttl);
+            if let Err(err) = completed.await {
+                trace_event!(error, target: IDEMPOTENCY_TARGET, { error = %err, }, "Idempotency store failure: {}", err);
+            }
+            let res = head.set_body(BoxBody::new(body));
+            Ok(ServiceResponse::new(req, res).map_into_right_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::rate_limit_helper::ManualClock;
+    use actix_web::{test, web, App};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn payment(key: &str, body: &'static str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/payments")
+            .insert_header((IDEMPOTENCY_KEY_HEADER, key))
+            .set_payload(body)
+    }
+
+    #[actix_web::test]
+    async fn test_replay_and_duplicate() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let app = test::init_service(
+            App::new().wrap(Idempotency::new(Arc::new(InMemoryIdempotencyStore::new()))).route(
+                "/payments",
+                web::post().to(move |body: Bytes| {
+                    let call = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                    async move {
+                        HttpResponse::Created()
+                            .insert_header(("x-call", call.to_string()))
+                            .body(format!("paid {}", String::from_utf8_lossy(&body)))
+                    }
+                }),
+            ),
+        )
+        .await;
+
+        let first = test::call_service(&app, payment("k-1", "10 EUR").to_request()).await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert!(first.headers().get(REPLAYED_HEADER).is_none());
+        assert_eq!(test::read_body(first).await, "paid 10 EUR");
+
+        let retry = test::call_service(&app, payment("k-1", "10 EUR").to_request()).await;
+        assert_eq!(retry.status(), StatusCode::CREATED);
+        assert_eq!(retry.headers().get(REPLAYED_HEADER).unwrap(), "true");
+        assert_eq!(retry.headers().get("x-call").unwrap(), "1");
+        assert_eq!(test::read_body(retry).await, "paid 10 EUR");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let reused = test::call_service(&app, payment("k-1", "99 EUR").to_request()).await;
+        assert_eq!(reused.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(reused).await;
+        assert_eq!(body["details"]["internal http code"]["code"], 902);
+
+        // Requests without a key and other methods are not tracked
+        let get = test::TestRequest::get().uri("/payments").to_request();
+        assert_eq!(test::call_service(&app, get).await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_in_flight_conflict_and_release() {
+        let store = Arc::new(InMemoryIdempotencyStore::new());
+        let clock = ManualClock::new();
+        let idempotency = Idempotency::new(store.clone()).with_clock(clock.clone());
+        let app = test::init_service(App::new().wrap(idempotency).service(
+            web::resource("/payments").route(web::post().to(|body: Bytes| async move {
+                match body.as_ref() {
+                    b"fail" => HttpResponse::ServiceUnavailable().finish(),
+                    _ => HttpResponse::Ok().finish(),
+                }
+            })),
+        ))
+        .await;
+
+        // Another request currently holds the key
+        let req = payment("k-2", "10 EUR").to_srv_request();
+        let key = Idempotency::new(store.clone()).scoped_key(&req).unwrap().unwrap();
+        let held = store.reserve(key, fingerprint(&req, b"10 EUR"), clock.now(), DEFAULT_TTL);
+        assert_eq!(held.await.unwrap(), None);
+
+        let res = test::call_service(&app, payment("k-2", "10 EUR").to_request()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(res.headers().get("X-Idempotency-Error").unwrap(), "Conflict");
+
+        // Server errors are not stored
+        let res = test::call_service(&app, payment("k-3", "fail").to_request()).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(store.len(), 1);
+
+        let res = test::call_service(&app, payment(&"k".repeat(300), "").to_request()).await;
+        assert_eq!(res.headers().get("X-Idempotency-Error").unwrap(), "Invalid Idempotency Key");
+    }
+
+    #[actix_web::test]
+    async fn test_body_size_limits() {
+        let store = Arc::new(InMemoryIdempotencyStore::new());
+        let idempotency = Idempotency::new(store.clone()).with_max_body_size(16);
+        let app = test::init_service(App::new().wrap(idempotency).service(
+            web::resource("/payments").route(web::post().to(|body: Bytes| async move {
+                match body.as_ref() {
+                    b"stream" => HttpResponse::Ok()
+                        .streaming(stream::once(ready(Ok::<_, Error>(Bytes::from("streamed"))))),
+                    b"large" => HttpResponse::Ok().body("x".repeat(32)),
+                    _ => HttpResponse::Ok().body("ok"),
+                }
+            })),
+        ))
+        .await;
+
+        // Oversized requests are refused, whether they announce their length or not
+        let req = payment("k-1", "far more than sixteen").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(res.headers().get("X-Idempotency-Error").unwrap(), "Content Too Large");
+        let mut req = payment("k-1", "far more than sixteen").to_request();
+        req.headers_mut().remove(header::CONTENT_LENGTH);
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(store.len(), 0);
+
+        // Streaming and oversized responses are passed through and release their key
+        for body in ["stream", "large"] {
+            let res = test::call_service(&app, payment("k-2", body).to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert!(res.headers().get(REPLAYED_HEADER).is_none());
+            assert_eq!(store.len(), 0);
+        }
+        let res = test::call_service(&app, payment("k-3", "small").to_request()).await;
+        assert_eq!(test::read_body(res).await, "ok");
+        assert_eq!(store.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_in_memory_store_ttl() {
+        let clock = ManualClock::new();
+        let store = InMemoryIdempotencyStore::new();
+        let ttl = Duration::from_secs(60);
+        let response = StoredResponse { status: 201, headers: vec![], body: b"ok".to_vec() };
+
+        assert_eq!(store.reserve("a".into(), "f".into(), clock.now(), ttl).await.unwrap(), None);
+        store.complete("a".into(), "f".into(), response.clone(), clock.now(), ttl).await.unwrap();
+        let record = store.reserve("a".into(), "f".into(), clock.now(), ttl).await.unwrap();
+        assert_eq!(
+            record,
+            Some(IdempotencyRecord::Completed { fingerprint: "f".into(), response })
+        );
+
+        clock.advance(ttl);
+        assert_eq!(store.reserve("a".into(), "g".into(), clock.now(), ttl).await.unwrap(), None);
+        assert_eq!(store.len(), 1);
+    }
+}
@@ -0,0 +1,603 @@
+//! # IP Allow and Deny Lists
+//!
+//! Provides `IpPolicy`, a middleware refusing requests according to IPv4 and IPv6 CIDR
+//! allow and deny lists, evaluated against the client IP address.
+//!
+//! A request is refused when its address falls in a denied range, or when allowed ranges
+//! are set and its address falls in none of them. Refusals map to `Forbidden` (403), or
+//! to `OriginError` (433) when configured. IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`)
+//! are matched as IPv4 addresses.
+//!
+//! Ranges are stored in `IpRangeSet`, a binary prefix tree per address family, so lookups
+//! walk at most 32 (IPv4) or 128 (IPv6) nodes whatever the number of ranges.
+//!
+//! Lists can be loaded from a file and reloaded without a restart, one entry per line:
+//!
+//! ```text
+//! # Office and VPN
+//! allow 203.0.113.0/24
+//! allow 2001:db8::/32
+//! deny 203.0.113.66
+//! ```
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::{web, App, HttpResponse};
+//! use simbld_http::helpers::ip_policy_helper::IpPolicy;
+//!
+//! let policy = IpPolicy::new()
+//!     .allow(["10.0.0.0/8", "fd00::/8"])
+//!     .unwrap()
+//!     .deny(["10.0.13.0/24"])
+//!     .unwrap()
+//!     .deny_as_origin_error();
+//!
+//! let app = App::new()
+//!     .wrap(policy)
+//!     .route("/", web::get().to(HttpResponse::Ok));
+//! ```
+
+use crate::helpers::client_ip_helper::client_ip;
+use crate::responses::{ResponsesClientCodes, ResponsesTypes};
+use actix_web::{
+    body::{BoxBody, EitherBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    Error, HttpResponse, ResponseError,
+};
+use futures_util::future::{ok, LocalBoxFuture, Ready};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+use thiserror::Error;
+
+/// Reasons for refusing a client IP address.
+#[derive(Debug, Clone, Copy, PartialEq, Error)]
+pub enum IpPolicyError {
+    #[error("Forbidden")]
+    Forbidden,
+    #[error("Origin Error")]
+    OriginError,
+}
+
+impl IpPolicyError {
+    /// Returns the catalog response describing the refusal.
+    pub fn response_type(&self) -> ResponsesTypes {
+        match self {
+            IpPolicyError::Forbidden => {
+                ResponsesTypes::ClientError(ResponsesClientCodes::Forbidden)
+            }
+            IpPolicyError::OriginError => {
+                ResponsesTypes::ClientError(ResponsesClientCodes::OriginError)
+            }
+        }
+    }
+}
+
+impl ResponseError for IpPolicyError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        actix_web::http::StatusCode::from_u16(self.response_type().get_code())
+            .unwrap_or(actix_web::http::StatusCode::FORBIDDEN)
+    }
+
+    /// Renders the catalog body, with `X-IP-Policy-Error` naming the refusal.
+    fn error_response(&self) -> HttpResponse {
+        let response = self.response_type();
+        response
+            .response_builder()
+            .insert_header(("X-HTTP-Status-Code", response.get_code().to_string()))
+            .insert_header(("X-IP-Policy-Error", self.to_string()))
+            .content_type("application/json")
+            .body(response.as_json().to_string())
+    }
+}
+
+/// Errors raised while reading IP ranges.
+#[derive(Debug, Error)]
+pub enum IpListError {
+    #[error("Invalid IP range: {0}")]
+    InvalidRange(String),
+    #[error("Invalid entry at line {line}: {entry}")]
+    InvalidEntry { line: usize, entry: String },
+    #[error("Failed to read IP list: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// An IPv4 or IPv6 CIDR range, such as `10.0.0.0/8` or `2001:db8::/32`.
+///
+/// A bare address is a range of a single address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    /// Creates the range of the addresses sharing the first `prefix` bits of `addr`.
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self, IpListError> {
+        let addr = canonical(addr);
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix > max {
+            return Err(IpListError::InvalidRange(format!("{}/{}", addr, prefix)));
+        }
+        Ok(Self { addr, prefix })
+    }
+
+    /// Whether `addr` falls in the range.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, canonical(addr)) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                leading_bits_eq(u32::from(net).into(), u32::from(addr).into(), 32, self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                leading_bits_eq(u128::from(net), u128::from(addr), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = IpListError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || IpListError::InvalidRange(value.to_string());
+        let (addr, prefix) = match value.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix.parse::<u8>().map_err(|_| invalid())?)),
+            None => (value.trim(), None),
+        };
+        let addr = canonical(addr.parse::<IpAddr>().map_err(|_| invalid())?);
+        let prefix = prefix.unwrap_or(if addr.is_ipv4() { 32 } else { 128 });
+        Self::new(addr, prefix).map_err(|_| invalid())
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Maps IPv4-mapped IPv6 addresses to IPv4.
+fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        v4 => v4,
+    }
+}
+
+fn leading_bits_eq(left: u128, right: u128, width: u8, prefix: u8) -> bool {
+    prefix == 0 || (left ^ right) >> (width - prefix) == 0
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Node {
+    /// Indices of the children for bits 0 and 1, 0 meaning none (the root is never a child).
+    children: [u32; 2],
+    /// Whether a range ends at this node.
+    terminal: bool,
+}
+
+/// Binary prefix tree of ranges of one address family.
+#[derive(Debug, Clone)]
+struct PrefixTree {
+    nodes: Vec<Node>,
+    width: u8,
+}
+
+impl PrefixTree {
+    fn new(width: u8) -> Self {
+        Self { nodes: vec![Node::default()], width }
+    }
+
+    fn insert(&mut self, bits: u128, prefix: u8) {
+        let mut index = 0;
+        for depth in 0..prefix {
+            if self.nodes[index].terminal {
+                // Already covered by a shorter range
+                return;
+            }
+            let bit = ((bits >> (self.width - 1 - depth)) & 1) as usize;
+            if self.nodes[index].children[bit] == 0 {
+                self.nodes.push(Node::default());
+                self.nodes[index].children[bit] = (self.nodes.len() - 1) as u32;
+            }
+            index = self.nodes[index].children[bit] as usize;
+        }
+        // Longer ranges below are now redundant
+        self.nodes[index] = Node { children: [0, 0], terminal: true };
+    }
+
+    fn contains(&self, bits: u128) -> bool {
+        let mut index = 0;
+        for depth in 0..self.width {
+            if self.nodes[index].terminal {
+                return true;
+            }
+            let bit = ((bits >> (self.width - 1 - depth)) & 1) as usize;
+            match self.nodes[index].children[bit] {
+                0 => return false,
+                child => index = child as usize,
+            }
+        }
+        self.nodes[index].terminal
+    }
+}
+
+/// Set of IPv4 and IPv6 ranges with fast membership lookups.
+#[derive(Debug, Clone)]
+pub struct IpRangeSet {
+    v4: PrefixTree,
+    v6: PrefixTree,
+    len: usize,
+}
+
+impl Default for IpRangeSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IpRangeSet {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        Self { v4: PrefixTree::new(32), v6: PrefixTree::new(128), len: 0 }
+    }
+
+    /// Adds a range.
+    pub fn insert(&mut self, net: IpNet) {
+        match net.addr {
+            IpAddr::V4(addr) => self.v4.insert(u32::from(addr).into(), net.prefix),
+            IpAddr::V6(addr) => self.v6.insert(u128::from(addr), net.prefix),
+        }
+        self.len += 1;
+    }
+
+    /// Whether `addr` falls in one of the ranges.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match canonical(addr) {
+            IpAddr::V4(addr) => self.v4.contains(u32::from(addr).into()),
+            IpAddr::V6(addr) => self.v6.contains(u128::from(addr)),
+        }
+    }
+
+    /// Number of ranges inserted.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether no range was inserted.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl FromIterator<IpNet> for IpRangeSet {
+    fn from_iter<I: IntoIterator<Item = IpNet>>(iter: I) -> Self {
+        let mut set = Self::new();
+        iter.into_iter().for_each(|net| set.insert(net));
+        set
+    }
+}
+
+/// Allowed and denied ranges.
+#[derive(Debug, Clone, Default)]
+pub struct IpLists {
+    pub allowed: IpRangeSet,
+    pub denied: IpRangeSet,
+}
+
+impl IpLists {
+    /// Parses `allow <range>` and `deny <range>` lines, ignoring blank lines and `#` comments.
+    pub fn parse(content: &str) -> Result<Self, IpListError> {
+        let mut lists = Self::default();
+        for (index, line) in content.lines().enumerate() {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let invalid =
+                || IpListError::InvalidEntry { line: index + 1, entry: entry.to_string() };
+            let (action, range) = entry.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let net = range.parse::<IpNet>().map_err(|_| invalid())?;
+            match action {
+                "allow" => lists.allowed.insert(net),
+                "deny" => lists.denied.insert(net),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(lists)
+    }
+
+    /// Whether `addr` may pass: not denied, and allowed when allowed ranges are set.
+    pub fn permits(&self, addr: Option<IpAddr>) -> bool {
+        match addr {
+            Some(addr) => {
+                !self.denied.contains(addr)
+                    && (self.allowed.is_empty() || self.allowed.contains(addr))
+            }
+            None => self.allowed.is_empty(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ListSource {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+/// Middleware applying IP allow and deny lists.
+///
+/// Clones share the same lists, so a clone kept as a handle can reload them.
+#[derive(Clone)]
+pub struct IpPolicy {
+    lists: Arc<RwLock<Arc<IpLists>>>,
+    source: Arc<RwLock<Option<ListSource>>>,
+    denial: IpPolicyError,
+}
+
+impl Default for IpPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IpPolicy {
+    /// Creates a policy letting every address through.
+    pub fn new() -> Self {
+        Self {
+            lists: Arc::new(RwLock::new(Arc::new(IpLists::default()))),
+            source: Arc::new(RwLock::new(None)),
+            denial: IpPolicyError::Forbidden,
+        }
+    }
+
+    /// Creates a policy from the lists of a file, which `reload` reads again.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, IpListError> {
+        let policy = Self::new();
+        *policy.source.write().unwrap_or_else(|poisoned| poisoned.into_inner()) =
+            Some(ListSource { path: path.as_ref().to_path_buf(), modified: None });
+        policy.reload()?;
+        Ok(policy)
+    }
+
+    /// Adds allowed ranges.
+    pub fn allow<I: IntoIterator<Item = V>, V: AsRef<str>>(
+        self,
+        ranges: I,
+    ) -> Result<Self, IpListError> {
+        self.extend(ranges, |lists| &mut lists.allowed)
+    }
+
+    /// Adds denied ranges.
+    pub fn deny<I: IntoIterator<Item = V>, V: AsRef<str>>(
+        self,
+        ranges: I,
+    ) -> Result<Self, IpListError> {
+        self.extend(ranges, |lists| &mut lists.denied)
+    }
+
+    /// Reports refusals as `OriginError` (433) instead of `Forbidden` (403).
+    pub fn deny_as_origin_error(mut self) -> Self {
+        self.denial = IpPolicyError::OriginError;
+        self
+    }
+
+    fn extend<I: IntoIterator<Item = V>, V: AsRef<str>>(
+        self,
+        ranges: I,
+        list: impl Fn(&mut IpLists) -> &mut IpRangeSet,
+    ) -> Result<Self, IpListError> {
+        let mut lists = IpLists::clone(&self.lists());
+        for range in ranges {
+            list(&mut lists).insert(range.as_ref().parse()?);
+        }
+        self.replace(lists);
+        Ok(self)
+    }
+
+    /// Returns the lists currently applied.
+    pub fn lists(&self) -> Arc<IpLists> {
+        self.lists.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    /// Applies new lists to the requests received from now on.
+    pub fn replace(&self, lists: IpLists) {
+        *self.lists.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(lists);
+    }
+
+    /// Reads the file of the policy again. The current lists are kept if it is invalid.
+    ///
+    /// Does nothing for policies not created with `from_file`.
+    pub fn reload(&self) -> Result<(), IpListError> {
+        let mut source = self.source.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Some(source) = source.as_mut() else {
+            return Ok(());
+        };
+        let modified = std::fs::metadata(&source.path)?.modified().ok();
+        let lists = IpLists::parse(&std::fs::read_to_string(&source.path)?)?;
+        self.replace(lists);
+        source.modified = modified;
+        Ok(())
+    }
+
+    /// Reads the file of the policy again if it changed since the last read.
+    ///
+    /// Returns whether the lists were reloaded.
+    pub fn reload_if_modified(&self) -> Result<bool, IpListError> {
+        let changed = {
+            let source = self.source.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+            match source.as_ref() {
+                Some(source) => {
+                    let modified = std::fs::metadata(&source.path)?.modified().ok();
+                    modified.is_none() || modified != source.modified
+                }
+                None => false,
+            }
+        };
+        if changed {
+            self.reload()?;
+        }
+        Ok(changed)
+    }
+
+    /// Checks the client address of a request.
+    pub fn check(&self, addr: Option<IpAddr>) -> Result<(), IpPolicyError> {
+        if self.lists().permits(addr) {
+            Ok(())
+        } else {
+            Err(self.denial)
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for IpPolicy
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Transform = IpPolicyService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(IpPolicyService { service: Rc::new(service), policy: self.clone() })
+    }
+}
+
+/// Service created by `IpPolicy` to process requests.
+pub struct IpPolicyService<S> {
+    service: Rc<S>,
+    policy: IpPolicy,
+}
+
+impl<S, B> Service<ServiceRequest> for IpPolicyService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    /// Answers refused addresses with the catalog response.
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        match self.policy.check(client_ip(req.request())) {
+            Ok(()) => {
+                let fut = self.service.call(req);
+                Box::pin(async move { fut.await.map(|res| res.map_into_left_body()) })
+            }
+            Err(err) => Box::pin(async move {
+                Ok(req.into_response(err.error_response()).map_into_right_body())
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    fn addr(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_range_matching() {
+        let set: IpRangeSet = ["10.0.0.0/8", "192.168.1.7", "2001:db8::/32"]
+            .iter()
+            .map(|range| range.parse().unwrap())
+            .collect();
+
+        assert!(set.contains(addr("10.200.3.4")));
+        assert!(set.contains(addr("192.168.1.7")));
+        assert!(!set.contains(addr("192.168.1.8")));
+        assert!(set.contains(addr("2001:db8:ffff::1")));
+        assert!(!set.contains(addr("2001:db9::1")));
+        // IPv4-mapped IPv6 addresses match IPv4 ranges
+        assert!(set.contains(addr("::ffff:10.1.2.3")));
+        assert!(!set.contains(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+
+        let mut everything = IpRangeSet::new();
+        everything.insert("10.1.0.0/16".parse().unwrap());
+        everything.insert("0.0.0.0/0".parse().unwrap());
+        assert!(everything.contains(addr("8.8.8.8")));
+        assert_eq!(everything.len(), 2);
+
+        for invalid in ["10.0.0.0/33", "::/129", "not-an-ip", "10.0.0.0/x"] {
+            assert!(invalid.parse::<IpNet>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_many_ranges() {
+        let set: IpRangeSet = (0..50_000u32)
+            .map(|index| IpNet::new(IpAddr::V4(Ipv4Addr::from(index << 8)), 24).unwrap())
+            .collect();
+        assert!(set.contains(IpAddr::V4(Ipv4Addr::from((42_000 << 8) | 7))));
+        assert!(!set.contains(IpAddr::V4(Ipv4Addr::from(50_000 << 8))));
+    }
+
+    #[actix_web::test]
+    async fn test_policy_middleware() {
+        let policy = IpPolicy::new()
+            .allow(["10.0.0.0/8", "fd00::/8"])
+            .unwrap()
+            .deny(["10.0.13.0/24"])
+            .unwrap()
+            .deny_as_origin_error();
+        let app =
+            test::init_service(App::new().wrap(policy).route("/", web::get().to(HttpResponse::Ok)))
+                .await;
+
+        let cases = [
+            ("10.1.2.3:80", StatusCode::OK),
+            ("[fd00::1]:80", StatusCode::OK),
+            ("10.0.13.5:80", StatusCode::BAD_REQUEST),
+            ("172.16.0.1:80", StatusCode::BAD_REQUEST),
+        ];
+        for (peer, status) in cases {
+            let req = test::TestRequest::get().uri("/").peer_addr(peer.parse().unwrap());
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), status, "{}", peer);
+            if status != StatusCode::OK {
+                let body: serde_json::Value = test::read_body_json(resp).await;
+                assert_eq!(body["details"]["internal http code"]["code"], 433);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_reload_from_file() {
+        let path = std::env::temp_dir().join(format!("simbld-ips-{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "# blocked\ndeny 203.0.113.0/24\n").unwrap();
+        let policy = IpPolicy::from_file(&path).unwrap();
+        assert_eq!(policy.check(Some(addr("203.0.113.9"))), Err(IpPolicyError::Forbidden));
+        assert_eq!(policy.check(Some(addr("198.51.100.1"))), Ok(()));
+
+        std::fs::write(&path, "deny 198.51.100.0/24 # moved\n").unwrap();
+        policy.reload().unwrap();
+        assert_eq!(policy.check(Some(addr("203.0.113.9"))), Ok(()));
+        assert_eq!(policy.check(Some(addr("198.51.100.1"))), Err(IpPolicyError::Forbidden));
+
+        // Invalid files keep the current lists
+        std::fs::write(&path, "block 192.0.2.1\n").unwrap();
+        assert!(matches!(policy.reload(), Err(IpListError::InvalidEntry { line: 1, .. })));
+        assert_eq!(policy.check(Some(addr("198.51.100.1"))), Err(IpPolicyError::Forbidden));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
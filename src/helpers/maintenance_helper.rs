@@ -0,0 +1,440 @@
+//! # Maintenance Mode
+//!
+//! Provides `Maintenance`, a middleware answering `ServiceUnavailable` (503) with a
+//! `Retry-After` header and the catalog body while a maintenance window is active.
+//!
+//! Windows are either toggled at runtime (`enable`, `enable_for`, `disable`) or scheduled
+//! ahead of time with start and end timestamps (`schedule`). Clones share the same windows,
+//! so a clone kept as an admin handle (e.g. in `web::Data`) controls the middleware.
+//!
+//! During a window, requests are let through when they:
+//! - come from an allow-listed IP address,
+//! - target a bypass route (e.g. `/health`),
+//! - carry an admin token in the `X-Maintenance-Token` header.
+//!
+//! Before a scheduled window starts, responses announce it with a `Sunset` header (RFC 8594)
+//! holding its start date.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::{web, App, HttpResponse};
+//! use chrono::{TimeZone, Utc};
+//! use simbld_http::helpers::maintenance_helper::Maintenance;
+//! use std::time::Duration;
+//!
+//! let maintenance = Maintenance::new()
+//!     .with_bypass_route("/health")
+//!     .with_admin_token("s3cr3t")
+//!     .with_announcement(Duration::from_secs(24 * 3600));
+//! maintenance.schedule(
+//!     Utc.with_ymd_and_hms(2030, 1, 1, 2, 0, 0).unwrap(),
+//!     Utc.with_ymd_and_hms(2030, 1, 1, 4, 0, 0).unwrap(),
+//! );
+//!
+//! let app = App::new()
+//!     .app_data(web::Data::new(maintenance.clone()))
+//!     .wrap(maintenance)
+//!     .route("/health", web::get().to(HttpResponse::Ok));
+//! ```
+
+use crate::helpers::client_ip_helper::client_ip;
+use crate::helpers::http_auth_helper::constant_time_eq;
+use crate::helpers::rate_limit_helper::{Clock, SystemClock};
+use crate::responses::{ResponsesServerCodes, ResponsesTypes};
+use actix_web::{
+    body::{BoxBody, EitherBody, MessageBody},
+    dev::{ResourceDef, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{self, HeaderName, HeaderValue, HttpDate},
+    Error, HttpResponse,
+};
+use chrono::{DateTime, Utc};
+use futures_util::future::{ok, LocalBoxFuture, Ready};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+/// A period during which the service is under maintenance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    /// Start of the window.
+    pub start: DateTime<Utc>,
+    /// End of the window, `None` until disabled.
+    pub end: Option<DateTime<Utc>>,
+}
+
+impl MaintenanceWindow {
+    /// Whether the window covers `now`.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.start <= now && self.end.is_none_or(|end| now < end)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Windows {
+    /// Window toggled at runtime.
+    manual: Option<MaintenanceWindow>,
+    /// Windows scheduled ahead of time.
+    scheduled: Vec<MaintenanceWindow>,
+}
+
+/// Maintenance middleware, and the admin handle of its windows.
+#[derive(Clone)]
+pub struct Maintenance {
+    windows: Arc<RwLock<Windows>>,
+    allowed_ips: HashSet<IpAddr>,
+    bypass_routes: Vec<ResourceDef>,
+    admin_tokens: Vec<String>,
+    token_header: HeaderName,
+    retry_after: Duration,
+    announcement: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Maintenance {
+    /// Creates a middleware without windows, bypass or announcement.
+    pub fn new() -> Self {
+        Self {
+            windows: Arc::new(RwLock::new(Windows::default())),
+            allowed_ips: HashSet::new(),
+            bypass_routes: Vec::new(),
+            admin_tokens: Vec::new(),
+            token_header: HeaderName::from_static("x-maintenance-token"),
+            retry_after: Duration::from_secs(300),
+            announcement: Duration::ZERO,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Lets requests from `ips` through during windows.
+    pub fn with_allowed_ips(mut self, ips: impl IntoIterator<Item = IpAddr>) -> Self {
+        self.allowed_ips.extend(ips);
+        self
+    }
+
+    /// Lets requests whose path matches `pattern` or lies below it through during windows.
+    pub fn with_bypass_route(mut self, pattern: &str) -> Self {
+        self.bypass_routes.push(ResourceDef::prefix(pattern));
+        self
+    }
+
+    /// Lets requests carrying `token` in the token header through during windows.
+    pub fn with_admin_token(mut self, token: impl Into<String>) -> Self {
+        self.admin_tokens.push(token.into());
+        self
+    }
+
+    /// Reads admin tokens from `header` instead of `X-Maintenance-Token`.
+    pub fn with_token_header(mut self, header: HeaderName) -> Self {
+        self.token_header = header;
+        self
+    }
+
+    /// Sets the `Retry-After` of windows without end (5 minutes by default).
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Announces scheduled windows with a `Sunset` header during `lead` before they start.
+    pub fn with_announcement(mut self, lead: Duration) -> Self {
+        self.announcement = lead;
+        self
+    }
+
+    /// Uses `clock` to tell whether windows are active.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Starts maintenance now, until `disable` is called.
+    pub fn enable(&self) {
+        self.write().manual = Some(MaintenanceWindow { start: self.now(), end: None });
+    }
+
+    /// Starts maintenance now, for `duration`, or until `disable` is called when the end
+    /// would be past the dates `chrono` can represent.
+    pub fn enable_for(&self, duration: Duration) {
+        let start = self.now();
+        let end = chrono::Duration::from_std(duration)
+            .ok()
+            .and_then(|duration| start.checked_add_signed(duration));
+        self.write().manual = Some(MaintenanceWindow { start, end });
+    }
+
+    /// Ends the maintenance started at runtime. Scheduled windows are kept.
+    pub fn disable(&self) {
+        self.write().manual = None;
+    }
+
+    /// Schedules a window from `start` to `end`.
+    pub fn schedule(&self, start: DateTime<Utc>, end: DateTime<Utc>) {
+        self.write().scheduled.push(MaintenanceWindow { start, end: Some(end) });
+    }
+
+    /// Cancels every scheduled window.
+    pub fn clear_schedule(&self) {
+        self.write().scheduled.clear();
+    }
+
+    /// Returns the windows toggled or scheduled, ended ones excluded.
+    pub fn windows(&self) -> Vec<MaintenanceWindow> {
+        let now = self.now();
+        let windows = self.read();
+        windows
+            .manual
+            .iter()
+            .chain(&windows.scheduled)
+            .filter(|window| window.end.is_none_or(|end| now < end))
+            .copied()
+            .collect()
+    }
+
+    /// Returns the window active now, if any.
+    pub fn active_window(&self) -> Option<MaintenanceWindow> {
+        let now = self.now();
+        let windows = self.read();
+        windows
+            .manual
+            .iter()
+            .chain(&windows.scheduled)
+            .find(|window| window.is_active(now))
+            .copied()
+    }
+
+    /// Returns the next scheduled window starting within the announcement lead, if any.
+    pub fn announced_window(&self) -> Option<MaintenanceWindow> {
+        let now = self.now();
+        let lead = chrono::Duration::from_std(self.announcement).ok()?;
+        let windows = self.read();
+        windows
+            .manual
+            .iter()
+            .chain(&windows.scheduled)
+            .filter(|window| now < window.start && window.start - now <= lead)
+            .min_by_key(|window| window.start)
+            .copied()
+    }
+
+    /// Whether `req` may reach the service during a window.
+    pub fn bypasses(&self, req: &ServiceRequest) -> bool {
+        let allowed_ip = client_ip(req.request()).is_some_and(|ip| self.allowed_ips.contains(&ip));
+        let bypass_route = self.bypass_routes.iter().any(|route| route.is_match(req.path()));
+        let admin = req.headers().get(&self.token_header).is_some_and(|value| {
+            let token = value.as_bytes();
+            self.admin_tokens.iter().any(|admin| constant_time_eq(admin.as_bytes(), token))
+        });
+        allowed_ip || bypass_route || admin
+    }
+
+    /// Builds the `503` answer of a window, `Retry-After` counting down to its end.
+    pub fn unavailable_response(&self, window: &MaintenanceWindow) -> HttpResponse {
+        let retry_after = match window.end {
+            Some(end) => (end - self.now()).to_std().unwrap_or_default(),
+            None => self.retry_after,
+        };
+        let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        let response = ResponsesTypes::ServerError(ResponsesServerCodes::ServiceUnavailable);
+        response
+            .response_builder()
+            .insert_header(("X-HTTP-Status-Code", response.get_code().to_string()))
+            .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+            .content_type("application/json")
+            .body(response.as_json().to_string())
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        let now = self.clock.now();
+        DateTime::from_timestamp(now.as_secs() as i64, now.subsec_nanos()).unwrap_or_default()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Windows> {
+        self.windows.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Locks the windows for a change, dropping the scheduled windows that ended.
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Windows> {
+        let now = self.now();
+        let mut windows = self.windows.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        windows.scheduled.retain(|window| window.end.is_none_or(|end| now < end));
+        windows
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Maintenance
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Transform = MaintenanceService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MaintenanceService { service: Rc::new(service), config: self.clone() })
+    }
+}
+
+/// Service created by `Maintenance` to process requests.
+pub struct MaintenanceService<S> {
+    service: Rc<S>,
+    config: Maintenance,
+}
+
+impl<S, B> Service<ServiceRequest> for MaintenanceService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    /// Answers `503` during windows unless the request bypasses them, and announces the
+    /// upcoming window otherwise.
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if let Some(window) = self.config.active_window() {
+            if !self.config.bypasses(&req) {
+                let response = self.config.unavailable_response(&window);
+                return Box::pin(
+                    async move { Ok(req.into_response(response).map_into_right_body()) },
+                );
+            }
+        }
+
+        let sunset = self.config.announced_window().and_then(|window| {
+            let date = HttpDate::from(SystemTime::from(window.start));
+            HeaderValue::from_str(&date.to_string()).ok()
+        });
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+            if let Some(sunset) = sunset {
+                res.headers_mut().insert(HeaderName::from_static("sunset"), sunset);
+            }
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::rate_limit_helper::ManualClock;
+    use actix_web::{http::StatusCode, test, web, App};
+    use chrono::TimeZone;
+
+    fn at(hour: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2030, 1, 1, hour, min, 0).unwrap()
+    }
+
+    fn clock_at(time: DateTime<Utc>) -> ManualClock {
+        let clock = ManualClock::new();
+        clock.set(Duration::from_secs(time.timestamp() as u64));
+        clock
+    }
+
+    #[actix_web::test]
+    async fn test_scheduled_window() {
+        let clock = clock_at(at(1, 0));
+        let maintenance = Maintenance::new()
+            .with_announcement(Duration::from_secs(2 * 3600))
+            .with_clock(clock.clone());
+        maintenance.schedule(at(2, 0), at(4, 0));
+        let app = test::init_service(
+            App::new().wrap(maintenance.clone()).route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        // Announced an hour ahead
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("Sunset").unwrap(), "Tue, 01 Jan 2030 02:00:00 GMT");
+
+        clock.advance(Duration::from_secs(3600 + 30 * 60));
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers().get("Retry-After").unwrap(), "5400");
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["details"]["standard http code"]["code"], 503);
+
+        clock.advance(Duration::from_secs(2 * 3600));
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get("Sunset").is_none());
+        assert!(maintenance.windows().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_runtime_toggle_and_bypass() {
+        let clock = clock_at(at(10, 0));
+        let maintenance = Maintenance::new()
+            .with_allowed_ips(["10.0.0.5".parse().unwrap()])
+            .with_bypass_route("/health")
+            .with_admin_token("s3cr3t")
+            .with_retry_after(Duration::from_secs(120))
+            .with_clock(clock.clone());
+        let app = test::init_service(
+            App::new().wrap(maintenance.clone()).default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+
+        maintenance.enable();
+        let req = test::TestRequest::get().uri("/orders").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers().get("Retry-After").unwrap(), "120");
+
+        let bypassing = [
+            test::TestRequest::get().uri("/health/live"),
+            test::TestRequest::get().uri("/orders").peer_addr("10.0.0.5:4000".parse().unwrap()),
+            test::TestRequest::get()
+                .uri("/orders")
+                .insert_header(("X-Maintenance-Token", "s3cr3t")),
+        ];
+        for req in bypassing {
+            assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+        }
+        let req =
+            test::TestRequest::get().uri("/orders").insert_header(("X-Maintenance-Token", "guess"));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        maintenance.disable();
+        let req = test::TestRequest::get().uri("/orders").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        maintenance.enable_for(Duration::from_secs(90));
+        clock.advance(Duration::from_secs(30));
+        let req = test::TestRequest::get().uri("/orders").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("Retry-After").unwrap(), "60");
+        clock.advance(Duration::from_secs(60));
+        let req = test::TestRequest::get().uri("/orders").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        // Durations past the representable dates last until `disable`
+        maintenance.enable_for(Duration::MAX);
+        assert_eq!(maintenance.active_window().unwrap().end, None);
+        let req = test::TestRequest::get().uri("/orders").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("Retry-After").unwrap(), "120");
+    }
+}
@@ -0,0 +1,455 @@
+//! # Prometheus Metrics
+//!
+//! Provides `Metrics`, a middleware recording requests, and `metrics_handler`, an actix
+//! handler exposing them in the Prometheus text exposition format (version 0.0.4).
+//!
+//! Exported series:
+//! - `http_requests_total{route, method, code, family, internal_code}`: counter of handled
+//!   requests. `code` is the standard status, `internal_code` the catalog code when the
+//!   response was rendered from the catalog (empty otherwise), and `family` the status
+//!   family of the internal code, or of the standard code without one, classified like
+//!   `populate_metadata`.
+//! - `http_request_duration_seconds{route, method}`: latency histogram.
+//! - `http_requests_in_flight{method}`: gauge of requests being handled.
+//!
+//! `route` is the route pattern (e.g. `/users/{id}`), never the raw path; requests matching
+//! no route are labeled `unmatched`. To bound label cardinality, routes beyond the
+//! configured maximum are folded into `__overflow__`, and non-standard methods into `OTHER`.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::{web, App, HttpResponse};
+//! use simbld_http::helpers::metrics_helper::{metrics_handler, Metrics};
+//!
+//! let metrics = Metrics::new();
+//! let app = App::new()
+//!     .app_data(web::Data::new(metrics.clone()))
+//!     .wrap(metrics)
+//!     .route("/metrics", web::get().to(metrics_handler))
+//!     .route("/users/{id}", web::get().to(HttpResponse::Ok));
+//! ```
+
+use crate::responses::ResponsesTypes;
+use crate::utils::populate_metadata::status_family;
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    web, Error, HttpResponse,
+};
+use futures_util::future::{ok, LocalBoxFuture, Ready};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+/// Default latency buckets, in seconds.
+pub const DEFAULT_BUCKETS: [f64; 11] =
+    [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+const UNMATCHED_ROUTE: &str = "unmatched";
+const OVERFLOW_ROUTE: &str = "__overflow__";
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RequestLabels {
+    route: String,
+    method: String,
+    code: u16,
+    family: &'static str,
+    internal_code: Option<u16>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Observations per bucket, not cumulated.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    requests: BTreeMap<RequestLabels, u64>,
+    durations: BTreeMap<(String, String), Histogram>,
+    in_flight: BTreeMap<String, i64>,
+    routes: HashSet<String>,
+}
+
+#[derive(Debug)]
+struct MetricsConfig {
+    namespace: Option<String>,
+    buckets: Vec<f64>,
+    max_routes: usize,
+}
+
+/// Request metrics registry and middleware.
+///
+/// Clones share the same registry, so a clone can be wrapped around the application while
+/// another one is handed to `metrics_handler` through `web::Data`.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    config: Arc<MetricsConfig>,
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    /// Creates a registry with the default buckets and at most 200 distinct routes.
+    pub fn new() -> Self {
+        Self {
+            config: Arc::new(MetricsConfig {
+                namespace: None,
+                buckets: DEFAULT_BUCKETS.to_vec(),
+                max_routes: 200,
+            }),
+            registry: Arc::new(Mutex::new(Registry::default())),
+        }
+    }
+
+    /// Prefixes metric names with `namespace_`.
+    pub fn with_namespace(self, namespace: impl Into<String>) -> Self {
+        self.reconfigure(|config| config.namespace = Some(namespace.into()))
+    }
+
+    /// Replaces the latency buckets, in seconds.
+    pub fn with_buckets(self, buckets: impl IntoIterator<Item = f64>) -> Self {
+        self.reconfigure(|config| {
+            config.buckets = buckets.into_iter().collect();
+            config.buckets.sort_by(f64::total_cmp);
+        })
+    }
+
+    /// Sets how many distinct route labels are kept before folding into `__overflow__`.
+    pub fn with_max_routes(self, max_routes: usize) -> Self {
+        self.reconfigure(|config| config.max_routes = max_routes)
+    }
+
+    fn reconfigure(self, change: impl FnOnce(&mut MetricsConfig)) -> Self {
+        let mut config = MetricsConfig {
+            namespace: self.config.namespace.clone(),
+            buckets: self.config.buckets.clone(),
+            max_routes: self.config.max_routes,
+        };
+        change(&mut config);
+        Self { config: Arc::new(config), registry: Arc::new(Mutex::new(Registry::default())) }
+    }
+
+    fn name(&self, metric: &str) -> String {
+        match &self.config.namespace {
+            Some(namespace) => format!("{}_{}", namespace, metric),
+            None => metric.to_string(),
+        }
+    }
+
+    /// Records a handled request.
+    ///
+    /// * `route` - Route pattern, `None` when no route matched
+    /// * `response` - Catalog response type, when the response was rendered from the catalog
+    pub fn observe(
+        &self,
+        route: Option<&str>,
+        method: &Method,
+        code: u16,
+        response: Option<&ResponsesTypes>,
+        seconds: f64,
+    ) {
+        let Ok(mut registry) = self.registry.lock() else {
+            return;
+        };
+        let route = match route {
+            None => UNMATCHED_ROUTE.to_string(),
+            Some(route) if registry.routes.contains(route) => route.to_string(),
+            Some(_) if registry.routes.len() >= self.config.max_routes => {
+                OVERFLOW_ROUTE.to_string()
+            }
+            Some(route) => {
+                registry.routes.insert(route.to_string());
+                route.to_string()
+            }
+        };
+        let method = method_label(method);
+        let internal_code = response.map(|response| {
+            let code = response.as_tuple();
+            code.internal_code.unwrap_or(code.standard_code)
+        });
+
+        let labels = RequestLabels {
+            route: route.clone(),
+            method: method.clone(),
+            code,
+            family: status_family(internal_code.unwrap_or(code)),
+            internal_code,
+        };
+        *registry.requests.entry(labels).or_default() += 1;
+
+        let histogram = registry.durations.entry((route, method)).or_insert_with(|| Histogram {
+            counts: vec![0; self.config.buckets.len()],
+            ..Histogram::default()
+        });
+        if let Some(index) = self.config.buckets.iter().position(|bound| seconds <= *bound) {
+            histogram.counts[index] += 1;
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    fn track_in_flight(&self, method: &Method) -> InFlight {
+        let method = method_label(method);
+        if let Ok(mut registry) = self.registry.lock() {
+            *registry.in_flight.entry(method.clone()).or_default() += 1;
+        }
+        InFlight { registry: self.registry.clone(), method }
+    }
+
+    /// Renders every series in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let Ok(registry) = self.registry.lock() else {
+            return String::new();
+        };
+        let mut out = String::new();
+
+        let name = self.name("http_requests_total");
+        let _ = writeln!(out, "# HELP {} Total number of HTTP requests handled.", name);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (labels, value) in &registry.requests {
+            let internal_code = labels.internal_code.map(|code| code.to_string());
+            let _ = writeln!(
+                out,
+                "{}{{route=\"{}\",method=\"{}\",code=\"{}\",family=\"{}\",internal_code=\"{}\"}} {}",
+                name,
+                escape(&labels.route),
+                labels.method,
+                labels.code,
+                labels.family,
+                internal_code.unwrap_or_default(),
+                value
+            );
+        }
+
+        let name = self.name("http_request_duration_seconds");
+        let _ = writeln!(out, "# HELP {} HTTP request latency in seconds.", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for ((route, method), histogram) in &registry.durations {
+            let labels = format!("route=\"{}\",method=\"{}\"", escape(route), method);
+            let mut cumulative = 0;
+            for (bound, count) in self.config.buckets.iter().zip(&histogram.counts) {
+                cumulative += count;
+                let _ =
+                    writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, cumulative);
+            }
+            let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, histogram.count);
+            let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+            let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+        }
+
+        let name = self.name("http_requests_in_flight");
+        let _ = writeln!(out, "# HELP {} Number of HTTP requests being handled.", name);
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for (method, value) in &registry.in_flight {
+            let _ = writeln!(out, "{}{{method=\"{}\"}} {}", name, method, value);
+        }
+
+        out
+    }
+}
+
+/// Keeps standard methods as labels, and folds extension methods into `OTHER`.
+fn method_label(method: &Method) -> String {
+    const STANDARD: [Method; 9] = [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::DELETE,
+        Method::HEAD,
+        Method::OPTIONS,
+        Method::CONNECT,
+        Method::PATCH,
+        Method::TRACE,
+    ];
+    if STANDARD.contains(method) {
+        method.to_string()
+    } else {
+        "OTHER".to_string()
+    }
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Decrements the in-flight gauge when the request completes or is dropped.
+struct InFlight {
+    registry: Arc<Mutex<Registry>>,
+    method: String,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Ok(mut registry) = self.registry.lock() {
+            *registry.in_flight.entry(self.method.clone()).or_default() -= 1;
+        }
+    }
+}
+
+/// Actix handler exposing the `Metrics` stored in the application data.
+pub async fn metrics_handler(metrics: web::Data<Metrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(metrics.render())
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Metrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = MetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MetricsMiddleware { service: Rc::new(service), metrics: self.clone() })
+    }
+}
+
+/// Service created by `Metrics` to process requests.
+pub struct MetricsMiddleware<S> {
+    service: Rc<S>,
+    metrics: Metrics,
+}
+
+impl<S, B> Service<ServiceRequest> for MetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let metrics = self.metrics.clone();
+        let method = req.method().clone();
+        let in_flight = metrics.track_in_flight(&method);
+        let start = Instant::now();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let result = fut.await;
+            let seconds = start.elapsed().as_secs_f64();
+            match &result {
+                Ok(res) => metrics.observe(
+                    res.request().match_pattern().as_deref(),
+                    &method,
+                    res.status().as_u16(),
+                    res.response().extensions().get::<ResponsesTypes>(),
+                    seconds,
+                ),
+                Err(err) => metrics.observe(
+                    None,
+                    &method,
+                    err.as_response_error().status_code().as_u16(),
+                    None,
+                    seconds,
+                ),
+            }
+            drop(in_flight);
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::responses::{ResponsesClientCodes, ResponsesLocalApiCodes};
+    use actix_web::{test, App};
+
+    #[actix_web::test]
+    async fn test_metrics_endpoint() {
+        let metrics = Metrics::new().with_buckets([0.1, 1.0]);
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(metrics.clone()))
+                .wrap(metrics)
+                .route("/metrics", web::get().to(metrics_handler))
+                .route("/users/{id}", web::get().to(HttpResponse::Ok))
+                .route(
+                    "/forbidden",
+                    web::get().to(|| async {
+                        ResponsesTypes::LocalApiError(ResponsesLocalApiCodes::OperationNotAllowed)
+                            .into_http_response()
+                    }),
+                ),
+        )
+        .await;
+
+        for uri in ["/users/1", "/users/2", "/forbidden", "/missing"] {
+            test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        }
+
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get("content-type").unwrap(),
+            "text/plain; version=0.0.4; charset=utf-8"
+        );
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+
+        assert!(body.contains("# TYPE http_requests_total counter"));
+        assert!(body.contains(
+            "http_requests_total{route=\"/users/{id}\",method=\"GET\",code=\"200\",family=\"Success\",internal_code=\"\"} 2"
+        ));
+        assert!(body.contains(
+            "http_requests_total{route=\"/forbidden\",method=\"GET\",code=\"403\",family=\"Local API Error\",internal_code=\"904\"} 1"
+        ));
+        assert!(body.contains(
+            "http_requests_total{route=\"unmatched\",method=\"GET\",code=\"404\",family=\"Client Error\",internal_code=\"\"} 1"
+        ));
+        assert!(body.contains(
+            "http_request_duration_seconds_bucket{route=\"/users/{id}\",method=\"GET\",le=\"+Inf\"} 2"
+        ));
+        assert!(body.contains(
+            "http_request_duration_seconds_count{route=\"/users/{id}\",method=\"GET\"} 2"
+        ));
+        // The scrape itself is in flight while rendering
+        assert!(body.contains("http_requests_in_flight{method=\"GET\"} 1"));
+    }
+
+    #[actix_web::test]
+    async fn test_cardinality_guard() {
+        let metrics = Metrics::new().with_namespace("api").with_max_routes(2);
+        let unauthorized = ResponsesTypes::ClientError(ResponsesClientCodes::Unauthorized);
+        for route in ["/a", "/b", "/c", "/d", "/a"] {
+            metrics.observe(Some(route), &Method::GET, 401, Some(&unauthorized), 0.02);
+        }
+        let brew = Method::from_bytes(b"BREW").unwrap();
+        metrics.observe(Some("/a"), &brew, 200, None, 0.02);
+
+        let body = metrics.render();
+        assert!(body.contains("api_http_requests_total{route=\"/a\",method=\"GET\",code=\"401\",family=\"Client Error\",internal_code=\"401\"} 2"));
+        assert!(body.contains("api_http_requests_total{route=\"__overflow__\",method=\"GET\",code=\"401\",family=\"Client Error\",internal_code=\"401\"} 2"));
+        assert!(body.contains("route=\"/a\",method=\"OTHER\""));
+        assert!(!body.contains("route=\"/c\""));
+        assert!(body.contains(
+            "api_http_request_duration_seconds_bucket{route=\"/a\",method=\"GET\",le=\"0.025\"} 2"
+        ));
+        assert!(body.contains(
+            "api_http_request_duration_seconds_bucket{route=\"/a\",method=\"GET\",le=\"0.01\"} 0"
+        ));
+    }
+}
@@ -1,14 +1,36 @@
 /// This module aggregates various helper modules for the `simbld-http` crate.
 /// Each helper module provides specific functionality to simplify HTTP response handling.
+pub mod access_log_helper;
 pub mod auth_middleware;
+pub mod authorization_helper;
+pub mod batch_response_helper;
+pub mod circuit_breaker_helper;
+pub mod client_ip_helper;
+pub mod conditional_helper;
+pub mod cors_helper;
+pub mod error_normalization_helper;
 pub mod generate_responses_functions;
 
+pub mod http_auth_helper;
 pub mod http_code_helper;
 pub mod http_interceptor_helper;
+pub mod idempotency_helper;
+pub mod ip_policy_helper;
+pub mod jwt_helper;
+pub mod maintenance_helper;
+pub mod metrics_helper;
+pub mod precondition_helper;
+pub mod rate_limit_helper;
+pub mod rate_limit_key_helper;
+pub mod rate_limit_store_helper;
+pub mod request_limits_helper;
 pub mod response_helpers;
 pub mod response_with_cookie_helper;
 pub mod response_with_headers_helper;
+pub mod security_headers_helper;
 pub mod three_fields_tuple_helper;
+pub mod timeout_helper;
+pub mod tracing_helper;
 pub mod two_fields_tuple_helper;
 pub mod unified_middleware_helper;
 pub mod unified_tuple_helper;
@@ -0,0 +1,366 @@
+//! # Optimistic Concurrency
+//!
+//! Prevents lost updates: a client may only modify a resource by proving it saw its
+//! current state, with `If-Match` (an ETag) or `If-Unmodified-Since` (a date).
+//!
+//! - `PreconditionGuard` is a middleware requiring one of these headers on the `PUT`,
+//!   `PATCH` and `DELETE` requests of configured routes, answering their absence with
+//!   `PreconditionRequired` (428).
+//! - `Preconditions` is an extractor giving handlers the conditions of a request, to
+//!   evaluate against the current `Validators` of the resource (its ETag, a version
+//!   through `version_etag`, or its last modification time). Stale conditions fail
+//!   with `PreconditionFailed` (412).
+//!
+//! As required by RFC 9110, `If-Match` takes precedence over `If-Unmodified-Since`, and
+//! entity tags are compared strongly.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::{web, App, HttpResponse};
+//! use simbld_http::helpers::conditional_helper::Validators;
+//! use simbld_http::helpers::precondition_helper::{
+//!     version_etag, PreconditionError, PreconditionGuard, Preconditions,
+//! };
+//!
+//! async fn update(preconditions: Preconditions) -> Result<HttpResponse, PreconditionError> {
+//!     let current_version = 7; // e.g. loaded from the database
+//!     preconditions.evaluate(&Validators::new().with_etag(version_etag(current_version)))?;
+//!     // ... write, then return the new version
+//!     Ok(HttpResponse::Ok().insert_header(("ETag", version_etag(8).to_string())).finish())
+//! }
+//!
+//! let app = App::new()
+//!     .wrap(PreconditionGuard::table().route("/documents"))
+//!     .route("/documents/{id}", web::put().to(update));
+//! ```
+
+use crate::helpers::conditional_helper::{http_date_precision, Validators};
+use crate::responses::{ResponsesClientCodes, ResponsesTypes};
+use actix_web::{
+    body::{BoxBody, EitherBody, MessageBody},
+    dev::{Payload, ResourceDef, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{
+        header::{self, EntityTag, HeaderMap, HttpDate},
+        Method,
+    },
+    Error, FromRequest, HttpRequest, HttpResponse, ResponseError,
+};
+use futures_util::future::{ok, ready, LocalBoxFuture, Ready};
+use std::fmt;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+use thiserror::Error;
+
+/// Reasons for refusing a conditional write.
+#[derive(Debug, Clone, Copy, PartialEq, Error)]
+pub enum PreconditionError {
+    #[error("Precondition Required")]
+    Required,
+    #[error("Precondition Failed")]
+    Failed,
+}
+
+impl PreconditionError {
+    /// Returns the catalog response describing the refusal.
+    pub fn response_type(&self) -> ResponsesTypes {
+        match self {
+            PreconditionError::Required => {
+                ResponsesTypes::ClientError(ResponsesClientCodes::PreconditionRequired)
+            }
+            PreconditionError::Failed => {
+                ResponsesTypes::ClientError(ResponsesClientCodes::PreconditionFailed)
+            }
+        }
+    }
+}
+
+impl ResponseError for PreconditionError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        actix_web::http::StatusCode::from_u16(self.response_type().get_code())
+            .unwrap_or(actix_web::http::StatusCode::PRECONDITION_FAILED)
+    }
+
+    /// Renders the catalog body, with `X-Precondition-Error` naming the refusal.
+    fn error_response(&self) -> HttpResponse {
+        let response = self.response_type();
+        response
+            .response_builder()
+            .insert_header(("X-HTTP-Status-Code", response.get_code().to_string()))
+            .insert_header(("X-Precondition-Error", self.to_string()))
+            .content_type("application/json")
+            .body(response.as_json().to_string())
+    }
+}
+
+/// Returns the strong ETag standing for a version number or revision identifier.
+pub fn version_etag(version: impl fmt::Display) -> EntityTag {
+    EntityTag::new_strong(version.to_string())
+}
+
+/// The `If-Match` condition of a request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IfMatch {
+    /// `*`: the resource must exist.
+    Any,
+    /// One of the tags must match the current ETag.
+    Items(Vec<EntityTag>),
+}
+
+/// The write preconditions of a request.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Preconditions {
+    pub if_match: Option<IfMatch>,
+    pub if_unmodified_since: Option<SystemTime>,
+}
+
+impl Preconditions {
+    /// Reads `If-Match` and `If-Unmodified-Since`, ignoring malformed values.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let value = |name| headers.get(name).and_then(|value| value.to_str().ok());
+        let if_match = value(header::IF_MATCH).map(|value| match value.trim() {
+            "*" => IfMatch::Any,
+            tags => {
+                IfMatch::Items(tags.split(',').filter_map(|tag| tag.trim().parse().ok()).collect())
+            }
+        });
+        Self {
+            if_match,
+            if_unmodified_since: value(header::IF_UNMODIFIED_SINCE)
+                .and_then(|date| date.parse::<HttpDate>().ok())
+                .map(SystemTime::from),
+        }
+    }
+
+    /// Whether the request carries a precondition.
+    pub fn is_present(&self) -> bool {
+        self.if_match.is_some() || self.if_unmodified_since.is_some()
+    }
+
+    /// Fails with `Required` when the request carries no precondition.
+    pub fn require(&self) -> Result<(), PreconditionError> {
+        match self.is_present() {
+            true => Ok(()),
+            false => Err(PreconditionError::Required),
+        }
+    }
+
+    /// Evaluates the preconditions against the `current` validators of an existing resource.
+    ///
+    /// Requests without preconditions pass; combine with `require` to refuse them.
+    pub fn evaluate(&self, current: &Validators) -> Result<(), PreconditionError> {
+        let holds = match (&self.if_match, self.if_unmodified_since) {
+            (Some(IfMatch::Any), _) => true,
+            (Some(IfMatch::Items(tags)), _) => {
+                current.etag.as_ref().is_some_and(|etag| tags.iter().any(|tag| tag.strong_eq(etag)))
+            }
+            (None, Some(since)) => {
+                current.last_modified.is_some_and(|modified| http_date_precision(modified) <= since)
+            }
+            (None, None) => true,
+        };
+        match holds {
+            true => Ok(()),
+            false => Err(PreconditionError::Failed),
+        }
+    }
+}
+
+/// Extracts the preconditions of a request; never fails.
+impl FromRequest for Preconditions {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(Preconditions::from_headers(req.headers())))
+    }
+}
+
+/// Middleware requiring preconditions on writes.
+///
+/// Built either to guard everything it wraps (`new`), or as a route table (`table`)
+/// guarding the paths matching one of its entries.
+#[derive(Clone)]
+pub struct PreconditionGuard {
+    routes: Vec<ResourceDef>,
+    everywhere: bool,
+    methods: Vec<Method>,
+}
+
+impl Default for PreconditionGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PreconditionGuard {
+    /// Creates a guard requiring preconditions on every write.
+    pub fn new() -> Self {
+        Self { everywhere: true, ..Self::table() }
+    }
+
+    /// Creates an empty route table.
+    pub fn table() -> Self {
+        Self {
+            routes: Vec::new(),
+            everywhere: false,
+            methods: vec![Method::PUT, Method::PATCH, Method::DELETE],
+        }
+    }
+
+    /// Requires preconditions on writes to `pattern` or below it (e.g. `/documents/{id}`).
+    pub fn route(mut self, pattern: &str) -> Self {
+        self.routes.push(ResourceDef::prefix(pattern));
+        self
+    }
+
+    /// Sets the methods considered writes (`PUT`, `PATCH` and `DELETE` by default).
+    pub fn with_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+        self.methods = methods.into_iter().collect();
+        self
+    }
+
+    /// Whether a request with `method` to `path` must carry preconditions.
+    pub fn guards(&self, method: &Method, path: &str) -> bool {
+        self.methods.contains(method)
+            && (self.everywhere || self.routes.iter().any(|route| route.is_match(path)))
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for PreconditionGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Transform = PreconditionGuardService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(PreconditionGuardService { service: Rc::new(service), config: Rc::new(self.clone()) })
+    }
+}
+
+/// Service created by `PreconditionGuard` to process requests.
+pub struct PreconditionGuardService<S> {
+    service: Rc<S>,
+    config: Rc<PreconditionGuard>,
+}
+
+impl<S, B> Service<ServiceRequest> for PreconditionGuardService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    /// Answers guarded writes without preconditions with the catalog `428`.
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let guarded = self.config.guards(req.method(), req.path());
+        if guarded {
+            if let Err(err) = Preconditions::from_headers(req.headers()).require() {
+                return Box::pin(async move {
+                    Ok(req.into_response(err.error_response()).map_into_right_body())
+                });
+            }
+        }
+        let fut = self.service.call(req);
+        Box::pin(async move { fut.await.map(|res| res.map_into_left_body()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[actix_web::test]
+    async fn test_evaluate_preconditions() {
+        let modified = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let current = Validators::new().with_etag(version_etag(7)).with_last_modified(modified);
+        let with = |name: header::HeaderName, value: String| {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, value.parse().unwrap());
+            Preconditions::from_headers(&headers)
+        };
+
+        assert_eq!(with(header::IF_MATCH, "\"6\", \"7\"".into()).evaluate(&current), Ok(()));
+        assert_eq!(with(header::IF_MATCH, "*".into()).evaluate(&current), Ok(()));
+        // Weak tags never match
+        assert_eq!(
+            with(header::IF_MATCH, "W/\"7\"".into()).evaluate(&current),
+            Err(PreconditionError::Failed)
+        );
+        assert_eq!(
+            with(header::IF_MATCH, "\"6\"".into()).evaluate(&current),
+            Err(PreconditionError::Failed)
+        );
+
+        let since = |time: SystemTime| HttpDate::from(time).to_string();
+        assert_eq!(with(header::IF_UNMODIFIED_SINCE, since(modified)).evaluate(&current), Ok(()));
+        assert_eq!(
+            with(header::IF_UNMODIFIED_SINCE, since(modified - Duration::from_secs(1)))
+                .evaluate(&current),
+            Err(PreconditionError::Failed)
+        );
+
+        // Clients echo the `Last-Modified` of sub-second modification times
+        let precise = current.clone().with_last_modified(modified + Duration::from_millis(500));
+        assert_eq!(with(header::IF_UNMODIFIED_SINCE, since(modified)).evaluate(&precise), Ok(()));
+        assert_eq!(
+            with(header::IF_UNMODIFIED_SINCE, since(modified - Duration::from_secs(1)))
+                .evaluate(&precise),
+            Err(PreconditionError::Failed)
+        );
+
+        assert_eq!(Preconditions::default().require(), Err(PreconditionError::Required));
+    }
+
+    #[actix_web::test]
+    async fn test_guard_and_handler() {
+        async fn update(preconditions: Preconditions) -> Result<HttpResponse, PreconditionError> {
+            preconditions.evaluate(&Validators::new().with_etag(version_etag(7)))?;
+            Ok(HttpResponse::NoContent().finish())
+        }
+
+        let app = test::init_service(
+            App::new()
+                .wrap(PreconditionGuard::table().route("/documents"))
+                .route("/documents/{id}", web::put().to(update))
+                .route("/documents/{id}", web::get().to(HttpResponse::Ok))
+                .route("/drafts/{id}", web::put().to(update)),
+        )
+        .await;
+
+        let put = |uri: &str| test::TestRequest::put().uri(uri);
+        let res = test::call_service(&app, put("/documents/1").to_request()).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_REQUIRED);
+        assert_eq!(res.headers().get("X-Precondition-Error").unwrap(), "Precondition Required");
+
+        let req = put("/documents/1").insert_header((header::IF_MATCH, "\"6\""));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["details"]["standard http code"]["code"], 412);
+
+        let req = put("/documents/1").insert_header((header::IF_MATCH, "\"7\""));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+        // Reads and unguarded routes are let through
+        let get = test::TestRequest::get().uri("/documents/1").to_request();
+        assert_eq!(test::call_service(&app, get).await.status(), StatusCode::OK);
+        let res = test::call_service(&app, put("/drafts/1").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    }
+}
@@ -0,0 +1,666 @@
+//! # Rate-Limiting Algorithms
+//!
+//! This module provides the pluggable rate-limiting algorithms used by `UnifiedMiddleware`.
+//!
+//! Every algorithm implements the `RateLimitAlgorithm` trait. Algorithms are stateless
+//! strategies: the per-key state lives in a `RateLimitState` owned by the caller, and the
+//! current time is supplied by a `Clock`, so decisions are fully deterministic in tests.
+//!
+//! The following algorithms are available:
+//! - `FixedWindow`: counts requests in a window that starts with the first request.
+//! - `SlidingLog`: keeps one timestamp per accepted request inside the window.
+//! - `SlidingWindowCounter`: weights the previous aligned window against the current one.
+//! - `TokenBucket`: refills `max_requests` tokens over the window, allowing bursts up to capacity.
+//! - `Gcra`: the Generic Cell Rate Algorithm, which spaces requests evenly with a burst tolerance.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use simbld_http::helpers::rate_limit_helper::{
+//!     Clock, ManualClock, RateLimitAlgorithm, RateLimitQuota, RateLimitState, TokenBucket,
+//! };
+//! use std::time::Duration;
+//!
+//! let clock = ManualClock::new();
+//! let quota = RateLimitQuota::new(2, Duration::from_secs(1));
+//! let mut state = RateLimitState::default();
+//!
+//! assert!(TokenBucket.acquire(&mut state, &quota, clock.now()).allowed);
+//! assert!(TokenBucket.acquire(&mut state, &quota, clock.now()).allowed);
+//! assert!(!TokenBucket.acquire(&mut state, &quota, clock.now()).allowed);
+//!
+//! clock.advance(Duration::from_millis(500));
+//! assert!(TokenBucket.acquire(&mut state, &quota, clock.now()).allowed);
+//! ```
+
+use actix_web::http::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Source of the current time for rate limiting.
+///
+/// The returned value is the time elapsed since an arbitrary, fixed epoch.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Duration;
+}
+
+/// Clock backed by the system time (elapsed time since the UNIX epoch).
+///
+/// Using wall-clock time keeps states comparable between processes sharing a store.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+}
+
+/// Clock that only moves when told to, for deterministic tests.
+///
+/// Clones share the same time, so a clone handed to a middleware can be advanced from a test.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    micros: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Creates a clock starting at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the clock forward by the given duration.
+    pub fn advance(&self, duration: Duration) {
+        self.micros.fetch_add(duration.as_micros() as u64, Ordering::SeqCst);
+    }
+
+    /// Sets the clock to an absolute time.
+    pub fn set(&self, now: Duration) {
+        self.micros.store(now.as_micros() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_micros(self.micros.load(Ordering::SeqCst))
+    }
+}
+
+/// Number of requests allowed per window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitQuota {
+    /// Maximum number of requests authorized in the window.
+    pub max_requests: u64,
+    /// Duration of the window.
+    pub window: Duration,
+}
+
+impl RateLimitQuota {
+    /// Creates a new quota.
+    pub fn new(max_requests: u64, window: Duration) -> Self {
+        Self { max_requests, window }
+    }
+}
+
+/// Outcome of a rate-limit check for a single request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitDecision {
+    /// Whether the request may proceed.
+    pub allowed: bool,
+    /// Maximum number of requests in the window.
+    pub limit: u64,
+    /// Requests still available after this one.
+    pub remaining: u64,
+    /// Time until the quota is fully available again.
+    pub reset_after: Duration,
+    /// Time until a rejected request may be retried (`None` when allowed).
+    pub retry_after: Option<Duration>,
+    /// Duration of the window the limit applies to.
+    pub window: Duration,
+}
+
+impl RateLimitDecision {
+    /// Returns the response headers describing this decision in the given style.
+    ///
+    /// `Retry-After` is included for rejected requests regardless of the style,
+    /// unless headers are disabled. Durations are rounded up to whole seconds.
+    pub fn headers(&self, style: RateLimitHeaders) -> Vec<(HeaderName, HeaderValue)> {
+        let reset = ceil_secs(self.reset_after);
+        let policy = format!("{};w={}", self.limit, ceil_secs(self.window));
+
+        let mut headers = match style {
+            RateLimitHeaders::Disabled => return Vec::new(),
+            RateLimitHeaders::Separate => vec![
+                (HeaderName::from_static("ratelimit-limit"), HeaderValue::from(self.limit)),
+                (HeaderName::from_static("ratelimit-remaining"), HeaderValue::from(self.remaining)),
+                (HeaderName::from_static("ratelimit-reset"), HeaderValue::from(reset)),
+            ],
+            RateLimitHeaders::Combined => vec![(
+                HeaderName::from_static("ratelimit"),
+                header_value(format!(
+                    "limit={}, remaining={}, reset={}",
+                    self.limit, self.remaining, reset
+                )),
+            )],
+        };
+        headers.push((HeaderName::from_static("ratelimit-policy"), header_value(policy)));
+
+        if let Some(retry_after) = self.retry_after {
+            headers.push((RETRY_AFTER, HeaderValue::from(ceil_secs(retry_after))));
+        }
+
+        headers
+    }
+}
+
+/// Style of the IETF rate-limit headers (draft-ietf-httpapi-ratelimit-headers) sent to clients.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RateLimitHeaders {
+    /// `RateLimit-Limit`, `RateLimit-Remaining`, `RateLimit-Reset` and `RateLimit-Policy`.
+    #[default]
+    Separate,
+    /// A combined `RateLimit: limit=.., remaining=.., reset=..` field and `RateLimit-Policy`.
+    Combined,
+    /// No rate-limit headers at all.
+    Disabled,
+}
+
+/// Per-key state of a rate limiter.
+///
+/// Each algorithm uses its own variant; a state belonging to another algorithm
+/// (or `Empty`) is reinitialized on the next request.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum RateLimitState {
+    #[default]
+    Empty,
+    FixedWindow {
+        window_start: u64,
+        count: u64,
+    },
+    SlidingLog {
+        timestamps: VecDeque<u64>,
+    },
+    SlidingWindowCounter {
+        window_start: u64,
+        current: u64,
+        previous: u64,
+    },
+    TokenBucket {
+        tokens: f64,
+        last_refill: u64,
+    },
+    Gcra {
+        theoretical_arrival: u64,
+    },
+}
+
+/// A rate-limiting strategy.
+///
+/// Implementations update the key's `state` for one incoming request at time `now`
+/// (as returned by a `Clock`) and decide whether the request is allowed.
+pub trait RateLimitAlgorithm: Send + Sync {
+    /// Returns the name of the algorithm.
+    fn name(&self) -> &'static str;
+
+    /// Applies one request to the state and returns the decision.
+    fn acquire(
+        &self,
+        state: &mut RateLimitState,
+        quota: &RateLimitQuota,
+        now: Duration,
+    ) -> RateLimitDecision;
+}
+
+/// Fixed-window counter: the window opens with the first request and resets wholesale.
+///
+/// This is the historical behaviour of `UnifiedMiddleware`; it allows bursts of up to
+/// twice the limit around window edges.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FixedWindow;
+
+/// Sliding log: exact, but stores one timestamp per accepted request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SlidingLog;
+
+/// Sliding window counter: approximates a sliding window from two aligned counters.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SlidingWindowCounter;
+
+/// Token bucket: holds up to `max_requests` tokens, refilled continuously over the window.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokenBucket;
+
+/// Generic Cell Rate Algorithm: one request every `window / max_requests`,
+/// with a burst tolerance of the whole window.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Gcra;
+
+impl RateLimitAlgorithm for FixedWindow {
+    fn name(&self) -> &'static str {
+        "fixed_window"
+    }
+
+    fn acquire(
+        &self,
+        state: &mut RateLimitState,
+        quota: &RateLimitQuota,
+        now: Duration,
+    ) -> RateLimitDecision {
+        let now = micros(now);
+        let window = micros(quota.window);
+
+        let (window_start, count) = match state {
+            RateLimitState::FixedWindow { window_start, count }
+                if now < window_start.saturating_add(window) =>
+            {
+                (*window_start, *count)
+            }
+            _ => (now, 0),
+        };
+
+        let reset_after = from_micros(window_start.saturating_add(window).saturating_sub(now));
+        let allowed = count < quota.max_requests;
+        let count = if allowed { count + 1 } else { count };
+        *state = RateLimitState::FixedWindow { window_start, count };
+
+        decision(allowed, quota, quota.max_requests.saturating_sub(count), reset_after, reset_after)
+    }
+}
+
+impl RateLimitAlgorithm for SlidingLog {
+    fn name(&self) -> &'static str {
+        "sliding_log"
+    }
+
+    fn acquire(
+        &self,
+        state: &mut RateLimitState,
+        quota: &RateLimitQuota,
+        now: Duration,
+    ) -> RateLimitDecision {
+        let now = micros(now);
+        let window = micros(quota.window);
+
+        if !matches!(state, RateLimitState::SlidingLog { .. }) {
+            *state = RateLimitState::SlidingLog { timestamps: VecDeque::new() };
+        }
+        let RateLimitState::SlidingLog { timestamps } = state else {
+            unreachable!("state was initialized above");
+        };
+
+        while timestamps.front().is_some_and(|oldest| oldest.saturating_add(window) <= now) {
+            timestamps.pop_front();
+        }
+
+        let allowed = (timestamps.len() as u64) < quota.max_requests;
+        if allowed {
+            timestamps.push_back(now);
+        }
+
+        let expiry_of = |timestamp: Option<&u64>| {
+            timestamp.map_or(Duration::ZERO, |t| from_micros(t.saturating_add(window) - now))
+        };
+        let reset_after = expiry_of(timestamps.back());
+        let retry_after = expiry_of(timestamps.front());
+        let remaining = quota.max_requests.saturating_sub(timestamps.len() as u64);
+
+        decision(allowed, quota, remaining, reset_after, retry_after)
+    }
+}
+
+impl RateLimitAlgorithm for SlidingWindowCounter {
+    fn name(&self) -> &'static str {
+        "sliding_window_counter"
+    }
+
+    fn acquire(
+        &self,
+        state: &mut RateLimitState,
+        quota: &RateLimitQuota,
+        now: Duration,
+    ) -> RateLimitDecision {
+        let now = micros(now);
+        let window = micros(quota.window).max(1);
+        let aligned_start = now - now % window;
+
+        let (current, previous) = match state {
+            RateLimitState::SlidingWindowCounter { window_start, current, previous }
+                if *window_start == aligned_start =>
+            {
+                (*current, *previous)
+            }
+            RateLimitState::SlidingWindowCounter { window_start, current, .. }
+                if window_start.saturating_add(window) == aligned_start =>
+            {
+                (0, *current)
+            }
+            _ => (0, 0),
+        };
+
+        let elapsed = now - aligned_start;
+        let limit = quota.max_requests as f64;
+        let estimate = |current: u64| {
+            previous as f64 * (window - elapsed) as f64 / window as f64 + current as f64
+        };
+
+        let allowed = estimate(current) + 1.0 <= limit;
+        let current = if allowed { current + 1 } else { current };
+        *state =
+            RateLimitState::SlidingWindowCounter { window_start: aligned_start, current, previous };
+
+        let remaining = (limit - estimate(current)).max(0.0).floor() as u64;
+        let reset_after = from_micros(window - elapsed);
+
+        // Earliest elapsed time (within this window, then the next one) at which
+        // the weighted estimate leaves room for one more request.
+        let retry_after = if current as f64 + 1.0 <= limit && previous > 0 {
+            let needed = window as f64 * (1.0 - (limit - 1.0 - current as f64) / previous as f64);
+            from_micros((needed.ceil() as u64).saturating_sub(elapsed))
+        } else if current > 0 {
+            let needed = window as f64 * (1.0 - (limit - 1.0).max(0.0) / current as f64);
+            from_micros(window - elapsed + needed.max(0.0).ceil() as u64)
+        } else {
+            reset_after
+        };
+
+        decision(allowed, quota, remaining, reset_after, retry_after)
+    }
+}
+
+impl RateLimitAlgorithm for TokenBucket {
+    fn name(&self) -> &'static str {
+        "token_bucket"
+    }
+
+    fn acquire(
+        &self,
+        state: &mut RateLimitState,
+        quota: &RateLimitQuota,
+        now: Duration,
+    ) -> RateLimitDecision {
+        let now = micros(now);
+        let capacity = quota.max_requests as f64;
+        let window = micros(quota.window).max(1) as f64;
+
+        let tokens = match state {
+            RateLimitState::TokenBucket { tokens, last_refill } => {
+                let refilled = now.saturating_sub(*last_refill) as f64 * capacity / window;
+                (*tokens + refilled).min(capacity)
+            }
+            _ => capacity,
+        };
+
+        let allowed = tokens >= 1.0;
+        let tokens = if allowed { tokens - 1.0 } else { tokens };
+        *state = RateLimitState::TokenBucket { tokens, last_refill: now };
+
+        // Time needed to refill the given number of tokens.
+        let time_for = |missing: f64| {
+            if capacity > 0.0 {
+                from_micros((missing.max(0.0) * window / capacity).ceil() as u64)
+            } else {
+                Duration::ZERO
+            }
+        };
+        let reset_after = time_for(capacity - tokens);
+        let retry_after = time_for(1.0 - tokens);
+
+        decision(allowed, quota, tokens.floor() as u64, reset_after, retry_after)
+    }
+}
+
+impl RateLimitAlgorithm for Gcra {
+    fn name(&self) -> &'static str {
+        "gcra"
+    }
+
+    fn acquire(
+        &self,
+        state: &mut RateLimitState,
+        quota: &RateLimitQuota,
+        now: Duration,
+    ) -> RateLimitDecision {
+        let now = micros(now);
+        let window = micros(quota.window);
+        // Emission interval between two evenly spaced requests, at least one microsecond,
+        // since a zero interval would never limit.
+        let interval = (window / quota.max_requests.max(1)).max(1);
+
+        let theoretical_arrival = match state {
+            RateLimitState::Gcra { theoretical_arrival } => (*theoretical_arrival).max(now),
+            _ => now,
+        };
+
+        let next_arrival = theoretical_arrival.saturating_add(interval);
+        let allow_at = next_arrival.saturating_sub(window);
+        let allowed = quota.max_requests > 0 && now >= allow_at;
+
+        let theoretical_arrival = if allowed { next_arrival } else { theoretical_arrival };
+        *state = RateLimitState::Gcra { theoretical_arrival };
+
+        let used = theoretical_arrival - now;
+        let remaining = window.saturating_sub(used).checked_div(interval).unwrap_or(0);
+        let retry_after = if quota.max_requests == 0 {
+            from_micros(window)
+        } else {
+            from_micros(allow_at.saturating_sub(now))
+        };
+
+        decision(allowed, quota, remaining, from_micros(used), retry_after)
+    }
+}
+
+/// Builds a decision, only exposing `retry_after` for rejected requests.
+fn decision(
+    allowed: bool,
+    quota: &RateLimitQuota,
+    remaining: u64,
+    reset_after: Duration,
+    retry_after: Duration,
+) -> RateLimitDecision {
+    RateLimitDecision {
+        allowed,
+        limit: quota.max_requests,
+        remaining,
+        reset_after,
+        retry_after: if allowed { None } else { Some(retry_after) },
+        window: quota.window,
+    }
+}
+
+/// Rounds a duration up to whole seconds, as required by `Retry-After` and `RateLimit-Reset`.
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+fn header_value(value: String) -> HeaderValue {
+    HeaderValue::from_str(&value).expect("rate-limit header values are ASCII")
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_micros() as u64
+}
+
+fn from_micros(micros: u64) -> Duration {
+    Duration::from_micros(micros)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    /// Sends `count` requests at the current time and returns how many were allowed.
+    fn burst(
+        algorithm: &dyn RateLimitAlgorithm,
+        state: &mut RateLimitState,
+        quota: &RateLimitQuota,
+        clock: &ManualClock,
+        count: usize,
+    ) -> usize {
+        (0..count).filter(|_| algorithm.acquire(state, quota, clock.now()).allowed).count()
+    }
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new();
+        let shared = clock.clone();
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(shared.now(), Duration::from_millis(1500));
+        shared.set(SECOND);
+        assert_eq!(clock.now(), SECOND);
+    }
+
+    #[test]
+    fn test_fixed_window_allows_double_burst_at_edges() {
+        let clock = ManualClock::new();
+        let quota = RateLimitQuota::new(5, SECOND);
+        let mut state = RateLimitState::default();
+
+        let first = FixedWindow.acquire(&mut state, &quota, clock.now());
+        assert!(first.allowed);
+        assert_eq!(first.remaining, 4);
+        assert_eq!(first.reset_after, SECOND);
+
+        clock.advance(Duration::from_millis(999));
+        assert_eq!(burst(&FixedWindow, &mut state, &quota, &clock, 10), 4);
+
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(burst(&FixedWindow, &mut state, &quota, &clock, 10), 5);
+
+        let rejected = FixedWindow.acquire(&mut state, &quota, clock.now());
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.retry_after, Some(SECOND));
+    }
+
+    #[test]
+    fn test_sliding_log_is_exact() {
+        let clock = ManualClock::new();
+        let quota = RateLimitQuota::new(3, SECOND);
+        let mut state = RateLimitState::default();
+
+        assert_eq!(burst(&SlidingLog, &mut state, &quota, &clock, 1), 1);
+        clock.advance(Duration::from_millis(900));
+        assert_eq!(burst(&SlidingLog, &mut state, &quota, &clock, 5), 2);
+
+        let rejected = SlidingLog.acquire(&mut state, &quota, clock.now());
+        assert_eq!(rejected.retry_after, Some(Duration::from_millis(100)));
+
+        // Only the oldest timestamp has expired.
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(burst(&SlidingLog, &mut state, &quota, &clock, 5), 1);
+    }
+
+    #[test]
+    fn test_sliding_window_counter_weights_previous_window() {
+        let clock = ManualClock::new();
+        let quota = RateLimitQuota::new(10, SECOND);
+        let mut state = RateLimitState::default();
+
+        assert_eq!(burst(&SlidingWindowCounter, &mut state, &quota, &clock, 10), 10);
+
+        // Halfway through the next window, half of the previous count still weighs in.
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(burst(&SlidingWindowCounter, &mut state, &quota, &clock, 10), 5);
+
+        let rejected = SlidingWindowCounter.acquire(&mut state, &quota, clock.now());
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.remaining, 0);
+        assert_eq!(rejected.retry_after, Some(Duration::from_millis(100)));
+
+        clock.advance(Duration::from_millis(100));
+        assert!(SlidingWindowCounter.acquire(&mut state, &quota, clock.now()).allowed);
+    }
+
+    #[test]
+    fn test_token_bucket_refills_continuously() {
+        let clock = ManualClock::new();
+        let quota = RateLimitQuota::new(4, SECOND);
+        let mut state = RateLimitState::default();
+
+        assert_eq!(burst(&TokenBucket, &mut state, &quota, &clock, 10), 4);
+
+        let rejected = TokenBucket.acquire(&mut state, &quota, clock.now());
+        assert_eq!(rejected.retry_after, Some(Duration::from_millis(250)));
+        assert_eq!(rejected.reset_after, SECOND);
+
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(burst(&TokenBucket, &mut state, &quota, &clock, 10), 2);
+    }
+
+    #[test]
+    fn test_gcra_spaces_requests() {
+        let clock = ManualClock::new();
+        let quota = RateLimitQuota::new(4, SECOND);
+        let mut state = RateLimitState::default();
+
+        let first = Gcra.acquire(&mut state, &quota, clock.now());
+        assert_eq!(first.remaining, 3);
+        assert_eq!(burst(&Gcra, &mut state, &quota, &clock, 10), 3);
+
+        let rejected = Gcra.acquire(&mut state, &quota, clock.now());
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.retry_after, Some(Duration::from_millis(250)));
+
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(burst(&Gcra, &mut state, &quota, &clock, 10), 1);
+
+        // More requests than microseconds in the window still limit
+        let quota = RateLimitQuota::new(1_000_000, Duration::from_millis(1));
+        let mut state = RateLimitState::default();
+        assert_eq!(burst(&Gcra, &mut state, &quota, &clock, 2000), 1000);
+    }
+
+    #[test]
+    fn test_decision_headers() {
+        let quota = RateLimitQuota::new(2, Duration::from_secs(60));
+        let mut state = RateLimitState::default();
+        let now = Duration::from_millis(1500);
+
+        let allowed = FixedWindow.acquire(&mut state, &quota, now);
+        let headers = allowed.headers(RateLimitHeaders::Separate);
+        let find = |headers: &[(HeaderName, HeaderValue)], name: &str| {
+            headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.to_str().unwrap().to_string())
+        };
+        assert_eq!(find(&headers, "ratelimit-limit").as_deref(), Some("2"));
+        assert_eq!(find(&headers, "ratelimit-remaining").as_deref(), Some("1"));
+        assert_eq!(find(&headers, "ratelimit-reset").as_deref(), Some("60"));
+        assert_eq!(find(&headers, "ratelimit-policy").as_deref(), Some("2;w=60"));
+        assert_eq!(find(&headers, "retry-after"), None);
+
+        FixedWindow.acquire(&mut state, &quota, now);
+        let rejected = FixedWindow.acquire(&mut state, &quota, now + Duration::from_millis(30_500));
+        let headers = rejected.headers(RateLimitHeaders::Combined);
+        assert_eq!(find(&headers, "ratelimit").as_deref(), Some("limit=2, remaining=0, reset=30"));
+        assert_eq!(find(&headers, "retry-after").as_deref(), Some("30"));
+        assert!(rejected.headers(RateLimitHeaders::Disabled).is_empty());
+    }
+
+    #[test]
+    fn test_zero_quota_rejects_everything() {
+        let clock = ManualClock::new();
+        let quota = RateLimitQuota::new(0, SECOND);
+        let algorithms: [&dyn RateLimitAlgorithm; 5] =
+            [&FixedWindow, &SlidingLog, &SlidingWindowCounter, &TokenBucket, &Gcra];
+
+        for algorithm in algorithms {
+            let mut state = RateLimitState::default();
+            assert_eq!(burst(algorithm, &mut state, &quota, &clock, 3), 0, "{}", algorithm.name());
+        }
+    }
+
+    #[test]
+    fn test_state_from_another_algorithm_is_reset() {
+        let clock = ManualClock::new();
+        let quota = RateLimitQuota::new(1, SECOND);
+        let mut state = RateLimitState::Gcra { theoretical_arrival: u64::MAX / 2 };
+
+        assert!(FixedWindow.acquire(&mut state, &quota, clock.now()).allowed);
+        assert!(matches!(state, RateLimitState::FixedWindow { count: 1, .. }));
+    }
+}
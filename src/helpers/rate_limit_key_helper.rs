@@ -0,0 +1,377 @@
+//! # Rate-Limit Key Extractors
+//!
+//! This module decides *who* a request is counted against. `UnifiedMiddleware` asks its
+//! `RateLimitKey` for a key and keeps one rate-limit state per key.
+//!
+//! Built-in extractors produce namespaced keys so buckets never collide:
+//! - `ClientIpKey` → `ip:<address>`
+//! - `RouteKey` → `route:<pattern>`
+//! - `HeaderKey` → `<header-name>:<value>` (e.g. `x-api-key:abc123`)
+//! - `PrincipalKey` → `principal:<id>` (from the `Principal` request extension)
+//! - `CookieKey` → `cookie:<name>:<value>`
+//!
+//! Extractors can be combined with `CompositeKey` (all parts required, joined with `|`) or
+//! `FallbackKey` (first part that yields a key), and any
+//! `Fn(&ServiceRequest) -> Option<String>` closure is an extractor as well.
+//!
+//! `RateLimitOverrides` assigns different quotas to specific keys or key parts,
+//! for instance to give premium API keys ten times the default limit.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use simbld_http::helpers::rate_limit_key_helper::{
+//!     ClientIpKey, FallbackKey, HeaderKey, RateLimitOverrides,
+//! };
+//!
+//! // Count API clients by key, anonymous clients by IP.
+//! let key = FallbackKey::new()
+//!     .or(HeaderKey::api_key())
+//!     .or(ClientIpKey);
+//!
+//! // Premium keys get ten times the default quota.
+//! let overrides = RateLimitOverrides::new().multiply("x-api-key:premium-123", 10);
+//! ```
+
+use crate::helpers::auth_middleware::Principal;
+use crate::helpers::client_ip_helper::client_ip;
+use crate::helpers::rate_limit_helper::RateLimitQuota;
+use actix_web::dev::ServiceRequest;
+use actix_web::HttpMessage;
+use std::collections::HashMap;
+
+/// Separator between the parts of a composite key.
+pub const KEY_SEPARATOR: char = '|';
+
+/// Extracts the rate-limit key of a request.
+///
+/// Returns `None` when the request does not carry the information the extractor needs.
+/// Keys must not contain `KEY_SEPARATOR`, which would let a client forge the parts
+/// `RateLimitOverrides` matches on.
+pub trait RateLimitKey {
+    fn extract(&self, req: &ServiceRequest) -> Option<String>;
+}
+
+/// Whether a client-supplied value can be used in a key: not empty, and without separator.
+fn is_key_part(value: &str) -> bool {
+    !value.is_empty() && !value.contains(KEY_SEPARATOR)
+}
+
+/// Any closure taking a request and returning an optional key is an extractor.
+impl<F> RateLimitKey for F
+where
+    F: Fn(&ServiceRequest) -> Option<String>,
+{
+    fn extract(&self, req: &ServiceRequest) -> Option<String> {
+        self(req)
+    }
+}
+
+/// What the middleware does with a request for which no key could be extracted.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum MissingKeyPolicy {
+    /// Count the request in one bucket shared by all unidentified clients (historical behaviour).
+    #[default]
+    SharedBucket,
+    /// Let the request through without rate limiting.
+    Bypass,
+    /// Reject the request with the catalog `400 Bad Request`.
+    Reject,
+}
+
+/// Name of the shared bucket used by `MissingKeyPolicy::SharedBucket`.
+pub const SHARED_BUCKET_KEY: &str = "unknown";
+
+/// Keys on the client IP address, as resolved by `client_ip_helper`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClientIpKey;
+
+impl RateLimitKey for ClientIpKey {
+    fn extract(&self, req: &ServiceRequest) -> Option<String> {
+        client_ip(req.request()).map(|ip| format!("ip:{}", ip))
+    }
+}
+
+/// Keys on the matched route pattern (e.g. `/users/{id}`), or the raw path when no route matched.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RouteKey;
+
+impl RateLimitKey for RouteKey {
+    fn extract(&self, req: &ServiceRequest) -> Option<String> {
+        let route = req.match_pattern().unwrap_or_else(|| req.path().to_string());
+        Some(format!("route:{}", route.replace(KEY_SEPARATOR, "%7C")))
+    }
+}
+
+/// Keys on the value of a request header, such as an API key.
+#[derive(Debug, Clone)]
+pub struct HeaderKey {
+    name: String,
+}
+
+impl HeaderKey {
+    /// Creates an extractor for the given header name.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into().to_ascii_lowercase() }
+    }
+
+    /// Creates an extractor for the conventional `X-API-Key` header.
+    pub fn api_key() -> Self {
+        Self::new("x-api-key")
+    }
+}
+
+impl RateLimitKey for HeaderKey {
+    fn extract(&self, req: &ServiceRequest) -> Option<String> {
+        let value = req.headers().get(self.name.as_str())?.to_str().ok()?.trim();
+        is_key_part(value).then(|| format!("{}:{}", self.name, value))
+    }
+}
+
+/// Keys on the authenticated principal stored in the request extensions.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrincipalKey;
+
+impl RateLimitKey for PrincipalKey {
+    fn extract(&self, req: &ServiceRequest) -> Option<String> {
+        req.extensions().get::<Principal>().map(|principal| format!("principal:{}", principal.id))
+    }
+}
+
+/// Keys on the value of a cookie, such as a session identifier.
+#[derive(Debug, Clone)]
+pub struct CookieKey {
+    name: String,
+}
+
+impl CookieKey {
+    /// Creates an extractor for the given cookie name.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into() }
+    }
+}
+
+impl RateLimitKey for CookieKey {
+    fn extract(&self, req: &ServiceRequest) -> Option<String> {
+        let cookie = req.cookie(&self.name)?;
+        is_key_part(cookie.value()).then(|| format!("cookie:{}:{}", self.name, cookie.value()))
+    }
+}
+
+/// Combines several extractors into one key; every part is required.
+#[derive(Default)]
+pub struct CompositeKey {
+    parts: Vec<Box<dyn RateLimitKey>>,
+}
+
+impl CompositeKey {
+    /// Creates an empty composite key.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an extractor to the composite key.
+    pub fn with(mut self, part: impl RateLimitKey + 'static) -> Self {
+        self.parts.push(Box::new(part));
+        self
+    }
+
+    /// Keys on the client IP and the matched route, giving each client one bucket per route.
+    pub fn ip_and_route() -> Self {
+        Self::new().with(ClientIpKey).with(RouteKey)
+    }
+}
+
+impl RateLimitKey for CompositeKey {
+    fn extract(&self, req: &ServiceRequest) -> Option<String> {
+        if self.parts.is_empty() {
+            return None;
+        }
+        let parts =
+            self.parts.iter().map(|part| part.extract(req)).collect::<Option<Vec<String>>>()?;
+        Some(parts.join(&KEY_SEPARATOR.to_string()))
+    }
+}
+
+/// Tries several extractors in order and uses the first key found.
+#[derive(Default)]
+pub struct FallbackKey {
+    candidates: Vec<Box<dyn RateLimitKey>>,
+}
+
+impl FallbackKey {
+    /// Creates an empty fallback chain.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an extractor to the chain.
+    pub fn or(mut self, candidate: impl RateLimitKey + 'static) -> Self {
+        self.candidates.push(Box::new(candidate));
+        self
+    }
+}
+
+impl RateLimitKey for FallbackKey {
+    fn extract(&self, req: &ServiceRequest) -> Option<String> {
+        self.candidates.iter().find_map(|candidate| candidate.extract(req))
+    }
+}
+
+/// A quota override for a specific key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuotaOverride {
+    /// Replaces the default quota.
+    Quota(RateLimitQuota),
+    /// Multiplies the default number of requests, keeping the default window.
+    Multiplier(u64),
+}
+
+/// Per-key quota overrides.
+///
+/// A request's key is matched as a whole first, then part by part for composite keys,
+/// so an override on `x-api-key:premium-123` also applies to `x-api-key:premium-123|route:/a`.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitOverrides {
+    overrides: HashMap<String, QuotaOverride>,
+}
+
+impl RateLimitOverrides {
+    /// Creates an empty set of overrides.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gives `key` its own quota.
+    pub fn set(mut self, key: impl Into<String>, quota: RateLimitQuota) -> Self {
+        self.overrides.insert(key.into(), QuotaOverride::Quota(quota));
+        self
+    }
+
+    /// Multiplies the default number of requests for `key` by `factor`.
+    pub fn multiply(mut self, key: impl Into<String>, factor: u64) -> Self {
+        self.overrides.insert(key.into(), QuotaOverride::Multiplier(factor));
+        self
+    }
+
+    /// Returns `true` when no override is configured.
+    pub fn is_empty(&self) -> bool {
+        self.overrides.is_empty()
+    }
+
+    /// Returns the quota applying to `key`, starting from the default quota.
+    pub fn resolve(&self, key: &str, default: RateLimitQuota) -> RateLimitQuota {
+        let found = self
+            .overrides
+            .get(key)
+            .or_else(|| key.split(KEY_SEPARATOR).find_map(|part| self.overrides.get(part)));
+
+        match found {
+            Some(QuotaOverride::Quota(quota)) => *quota,
+            Some(QuotaOverride::Multiplier(factor)) => RateLimitQuota {
+                max_requests: default.max_requests.saturating_mul(*factor),
+                window: default.window,
+            },
+            None => default,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::cookie::Cookie;
+    use actix_web::test::TestRequest;
+    use std::time::Duration;
+
+    fn request() -> TestRequest {
+        TestRequest::get().uri("/users/42").peer_addr("10.0.0.1:4000".parse().unwrap())
+    }
+
+    #[test]
+    fn test_client_ip_key() {
+        let req = request().to_srv_request();
+        assert_eq!(ClientIpKey.extract(&req), Some("ip:10.0.0.1".to_string()));
+
+        let anonymous = TestRequest::get().to_srv_request();
+        assert_eq!(ClientIpKey.extract(&anonymous), None);
+    }
+
+    #[test]
+    fn test_header_and_cookie_keys() {
+        let req = request()
+            .insert_header(("X-API-Key", "abc123"))
+            .cookie(Cookie::new("session", "s-1"))
+            .to_srv_request();
+
+        assert_eq!(HeaderKey::api_key().extract(&req), Some("x-api-key:abc123".to_string()));
+        assert_eq!(HeaderKey::new("Authorization").extract(&req), None);
+        assert_eq!(CookieKey::new("session").extract(&req), Some("cookie:session:s-1".to_string()));
+    }
+
+    #[test]
+    fn test_principal_key() {
+        let req = request().to_srv_request();
+        assert_eq!(PrincipalKey.extract(&req), None);
+
+        req.extensions_mut().insert(Principal::new("alice"));
+        assert_eq!(PrincipalKey.extract(&req), Some("principal:alice".to_string()));
+    }
+
+    #[test]
+    fn test_composite_and_fallback_keys() {
+        let req = request().to_srv_request();
+
+        let composite = CompositeKey::new().with(ClientIpKey).with(RouteKey);
+        assert_eq!(composite.extract(&req), Some("ip:10.0.0.1|route:/users/42".to_string()));
+
+        let strict = CompositeKey::new().with(ClientIpKey).with(HeaderKey::api_key());
+        assert_eq!(strict.extract(&req), None);
+
+        let fallback = FallbackKey::new().or(HeaderKey::api_key()).or(ClientIpKey);
+        assert_eq!(fallback.extract(&req), Some("ip:10.0.0.1".to_string()));
+    }
+
+    #[test]
+    fn test_closure_key() {
+        let tenant = |req: &ServiceRequest| {
+            req.headers().get("x-tenant").and_then(|v| v.to_str().ok()).map(|t| format!("t:{}", t))
+        };
+        let req = request().insert_header(("x-tenant", "acme")).to_srv_request();
+        assert_eq!(tenant.extract(&req), Some("t:acme".to_string()));
+    }
+
+    #[test]
+    fn test_overrides_resolve() {
+        let default = RateLimitQuota::new(10, Duration::from_secs(60));
+        let overrides = RateLimitOverrides::new()
+            .multiply("x-api-key:premium", 10)
+            .set("ip:10.0.0.9", RateLimitQuota::new(1, Duration::from_secs(1)));
+
+        assert_eq!(overrides.resolve("x-api-key:premium", default).max_requests, 100);
+        assert_eq!(overrides.resolve("x-api-key:premium|route:/a", default).max_requests, 100);
+        assert_eq!(
+            overrides.resolve("ip:10.0.0.9", default),
+            RateLimitQuota::new(1, Duration::from_secs(1))
+        );
+        assert_eq!(overrides.resolve("x-api-key:basic", default), default);
+    }
+
+    #[test]
+    fn test_injected_separator() {
+        let default = RateLimitQuota::new(10, Duration::from_secs(60));
+        let overrides = RateLimitOverrides::new().multiply("x-api-key:premium-123", 10);
+        let req = request()
+            .uri("/users/42%7Cx-api-key:premium-123")
+            .insert_header(("X-API-Key", "junk|x-api-key:premium-123"))
+            .cookie(Cookie::new("session", "s-1|x-api-key:premium-123"))
+            .to_srv_request();
+
+        assert_eq!(HeaderKey::api_key().extract(&req), None);
+        assert_eq!(CookieKey::new("session").extract(&req), None);
+
+        let key = CompositeKey::new().with(ClientIpKey).with(RouteKey).extract(&req).unwrap();
+        assert_eq!(key.split(KEY_SEPARATOR).count(), 2);
+        assert_eq!(overrides.resolve(&key, default), default);
+    }
+}
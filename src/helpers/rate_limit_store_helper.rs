@@ -0,0 +1,649 @@
+//! # Rate-Limit Storage Backends
+//!
+//! This module defines where `UnifiedMiddleware` keeps the per-key `RateLimitState`.
+//!
+//! Every backend implements the `RateLimitStore` trait, which atomically applies a state
+//! update for one key and keeps the result for a given time-to-live:
+//! - `InMemoryStore` keeps states in the process, evicts them once their TTL has elapsed
+//!   and never holds more than a configured number of keys.
+//! - `RedisStore` keeps states in a Redis-compatible server, so that several processes
+//!   (e.g. horizontally scaled pods) enforce one global limit.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use simbld_http::helpers::rate_limit_store_helper::{InMemoryStore, RedisStore};
+//! use simbld_http::helpers::unified_middleware_helper::{RateLimiters, UnifiedMiddleware};
+//! use std::sync::Arc;
+//!
+//! // One process: bounded in-memory storage.
+//! let local: RateLimiters = Arc::new(InMemoryStore::with_capacity(50_000));
+//!
+//! // Several processes: shared storage.
+//! let shared: RateLimiters = Arc::new(RedisStore::new("127.0.0.1:6379").with_key_prefix("api:rl:"));
+//! ```
+
+use crate::helpers::rate_limit_helper::{RateLimitDecision, RateLimitState};
+use futures_util::future::{ready, LocalBoxFuture};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Update applied by a store to the state of one key.
+pub type StateUpdate = Arc<dyn Fn(&mut RateLimitState) -> RateLimitDecision + Send + Sync>;
+
+/// Errors raised by rate-limit stores.
+#[derive(Debug, Error)]
+pub enum RateLimitStoreError {
+    #[error("The rate-limit store lock was poisoned.")]
+    Poisoned,
+    #[error("Rate-limit store I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Unexpected reply from the rate-limit store: {0}")]
+    Protocol(String),
+    #[error("Could not serialize the rate-limit state: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("The rate-limit state kept changing concurrently; gave up after {0} attempts.")]
+    Contention(usize),
+}
+
+/// Storage of rate-limit states.
+pub trait RateLimitStore: Send + Sync {
+    /// Atomically applies `update` to the state stored under `key` and returns its decision.
+    ///
+    /// # arguments
+    ///
+    /// * `key` - The rate-limit key of the request
+    /// * `now` - Current time, as returned by the middleware `Clock`
+    /// * `ttl` - How long the updated state must be kept
+    /// * `update` - The algorithm step to apply
+    ///
+    fn update(
+        &self,
+        key: String,
+        now: Duration,
+        ttl: Duration,
+        update: StateUpdate,
+    ) -> LocalBoxFuture<'static, Result<RateLimitDecision, RateLimitStoreError>>;
+}
+
+/// Default maximum number of keys kept by an `InMemoryStore`.
+pub const DEFAULT_IN_MEMORY_CAPACITY: usize = 100_000;
+
+/// In-process store with TTL eviction and a capacity bound.
+///
+/// Expired states are evicted on every update. When the store is full, the state
+/// closest to expiry (i.e. the least recently updated one) is evicted.
+#[derive(Debug)]
+pub struct InMemoryStore {
+    capacity: usize,
+    inner: Mutex<InMemoryEntries>,
+}
+
+#[derive(Debug, Default)]
+struct InMemoryEntries {
+    states: HashMap<String, (RateLimitState, Duration)>,
+    expirations: BTreeSet<(Duration, String)>,
+}
+
+impl InMemoryStore {
+    /// Creates a store holding at most `DEFAULT_IN_MEMORY_CAPACITY` keys.
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_IN_MEMORY_CAPACITY)
+    }
+
+    /// Creates a store holding at most `capacity` keys.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { capacity: capacity.max(1), inner: Mutex::new(InMemoryEntries::default()) }
+    }
+
+    /// Returns the number of keys currently stored, including not yet evicted expired ones.
+    pub fn len(&self) -> usize {
+        self.inner.lock().map(|entries| entries.states.len()).unwrap_or(0)
+    }
+
+    /// Returns `true` when no key is stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Applies the update synchronously.
+    fn update_now(
+        &self,
+        key: String,
+        now: Duration,
+        ttl: Duration,
+        update: &StateUpdate,
+    ) -> Result<RateLimitDecision, RateLimitStoreError> {
+        let mut entries = self.inner.lock().map_err(|_| RateLimitStoreError::Poisoned)?;
+        entries.evict_expired(now);
+
+        let (mut state, previous_expiry) = match entries.states.remove(&key) {
+            Some((state, expires_at)) => (state, Some(expires_at)),
+            None => {
+                while entries.states.len() >= self.capacity {
+                    entries.evict_first();
+                }
+                (RateLimitState::default(), None)
+            }
+        };
+        if let Some(expires_at) = previous_expiry {
+            entries.expirations.remove(&(expires_at, key.clone()));
+        }
+
+        let decision = update(&mut state);
+        let expires_at = now + ttl;
+        entries.expirations.insert((expires_at, key.clone()));
+        entries.states.insert(key, (state, expires_at));
+
+        Ok(decision)
+    }
+}
+
+impl Default for InMemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryEntries {
+    /// Removes every state whose TTL has elapsed.
+    fn evict_expired(&mut self, now: Duration) {
+        while self.expirations.first().is_some_and(|(expires_at, _)| *expires_at <= now) {
+            self.evict_first();
+        }
+    }
+
+    /// Removes the state closest to expiry.
+    fn evict_first(&mut self) {
+        if let Some((_, key)) = self.expirations.pop_first() {
+            self.states.remove(&key);
+        }
+    }
+}
+
+impl RateLimitStore for InMemoryStore {
+    fn update(
+        &self,
+        key: String,
+        now: Duration,
+        ttl: Duration,
+        update: StateUpdate,
+    ) -> LocalBoxFuture<'static, Result<RateLimitDecision, RateLimitStoreError>> {
+        Box::pin(ready(self.update_now(key, now, ttl, &update)))
+    }
+}
+
+/// Maximum number of optimistic transactions attempted per update.
+const REDIS_MAX_ATTEMPTS: usize = 8;
+
+/// Maximum size of a bulk reply, far above any serialized rate-limit state.
+const REDIS_MAX_BULK_SIZE: i64 = 16 * 1024 * 1024;
+
+/// Store backed by a server speaking the Redis protocol (RESP).
+///
+/// States are serialized as JSON and updated with optimistic transactions
+/// (`WATCH` / `GET` / `MULTI` / `SET .. PX` / `EXEC`), retried when another process
+/// changed the key in between. Network I/O runs on Actix's blocking thread pool.
+#[derive(Debug, Clone)]
+pub struct RedisStore {
+    inner: Arc<RedisConfig>,
+}
+
+#[derive(Debug)]
+struct RedisConfig {
+    address: String,
+    key_prefix: String,
+    password: Option<String>,
+    timeout: Duration,
+    connections: Mutex<Vec<RespConnection>>,
+}
+
+impl RedisStore {
+    /// Creates a store connecting to `address` (e.g. `127.0.0.1:6379` or `redis:6379`).
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            inner: Arc::new(RedisConfig {
+                address: address.into(),
+                key_prefix: "simbld:ratelimit:".to_string(),
+                password: None,
+                timeout: Duration::from_secs(1),
+                connections: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Sets the prefix prepended to every rate-limit key.
+    pub fn with_key_prefix(self, prefix: impl Into<String>) -> Self {
+        self.reconfigure(|config| config.key_prefix = prefix.into())
+    }
+
+    /// Authenticates new connections with `AUTH <password>`.
+    pub fn with_password(self, password: impl Into<String>) -> Self {
+        self.reconfigure(|config| config.password = Some(password.into()))
+    }
+
+    /// Sets the connect, read and write timeout of the connections.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.reconfigure(|config| config.timeout = timeout)
+    }
+
+    fn reconfigure(self, change: impl FnOnce(&mut RedisConfig)) -> Self {
+        let mut config = RedisConfig {
+            address: self.inner.address.clone(),
+            key_prefix: self.inner.key_prefix.clone(),
+            password: self.inner.password.clone(),
+            timeout: self.inner.timeout,
+            connections: Mutex::new(Vec::new()),
+        };
+        change(&mut config);
+        Self { inner: Arc::new(config) }
+    }
+}
+
+impl RedisConfig {
+    /// Opens a new connection, trying every address the host name resolves to.
+    fn connect(&self) -> Result<RespConnection, RateLimitStoreError> {
+        let mut last_error = None;
+        let mut stream = None;
+        for address in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, self.timeout) {
+                Ok(connected) => {
+                    stream = Some(connected);
+                    break;
+                }
+                Err(err) => last_error = Some(err),
+            }
+        }
+        let stream = match (stream, last_error) {
+            (Some(stream), _) => stream,
+            (None, Some(err)) => return Err(err.into()),
+            (None, None) => {
+                return Err(RateLimitStoreError::Protocol(format!(
+                    "cannot resolve {}",
+                    self.address
+                )))
+            }
+        };
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut connection = RespConnection { reader: BufReader::new(stream) };
+
+        if let Some(password) = &self.password {
+            connection.expect_ok(&["AUTH", password])?;
+        }
+        Ok(connection)
+    }
+
+    /// Runs the optimistic transaction until it commits.
+    fn update_blocking(
+        &self,
+        key: &str,
+        ttl: Duration,
+        update: &StateUpdate,
+    ) -> Result<RateLimitDecision, RateLimitStoreError> {
+        let key = format!("{}{}", self.key_prefix, key);
+        let ttl_ms = ttl.as_millis().max(1).to_string();
+        let pooled = self.connections.lock().ok().and_then(|mut pool| pool.pop());
+        let mut connection = match pooled {
+            // The server may have closed the idle connection: reconnect once
+            Some(mut connection) => match connection.expect_ok(&["WATCH", &key]) {
+                Ok(()) => connection,
+                Err(_) => self.watching(&key)?,
+            },
+            None => self.watching(&key)?,
+        };
+
+        for attempt in 0..REDIS_MAX_ATTEMPTS {
+            if attempt > 0 {
+                connection.expect_ok(&["WATCH", &key])?;
+            }
+            let mut state = match connection.command(&["GET", &key])? {
+                RespValue::Bulk(Some(json)) => serde_json::from_slice(&json)?,
+                RespValue::Bulk(None) => RateLimitState::default(),
+                other => return Err(unexpected(other)),
+            };
+
+            let decision = update(&mut state);
+            let json = serde_json::to_string(&state)?;
+
+            connection.expect_ok(&["MULTI"])?;
+            connection.command(&["SET", &key, &json, "PX", &ttl_ms])?;
+            match connection.command(&["EXEC"])? {
+                RespValue::Array(Some(_)) => {
+                    if let Ok(mut pool) = self.connections.lock() {
+                        pool.push(connection);
+                    }
+                    return Ok(decision);
+                }
+                // The watched key changed: start over with the new state
+                RespValue::Array(None) | RespValue::Bulk(None) => continue,
+                other => return Err(unexpected(other)),
+            }
+        }
+
+        Err(RateLimitStoreError::Contention(REDIS_MAX_ATTEMPTS))
+    }
+
+    /// Opens a new connection watching `key`.
+    fn watching(&self, key: &str) -> Result<RespConnection, RateLimitStoreError> {
+        let mut connection = self.connect()?;
+        connection.expect_ok(&["WATCH", key])?;
+        Ok(connection)
+    }
+}
+
+impl RateLimitStore for RedisStore {
+    fn update(
+        &self,
+        key: String,
+        _now: Duration,
+        ttl: Duration,
+        update: StateUpdate,
+    ) -> LocalBoxFuture<'static, Result<RateLimitDecision, RateLimitStoreError>> {
+        let config = self.inner.clone();
+        Box::pin(async move {
+            actix_web::web::block(move || config.update_blocking(&key, ttl, &update))
+                .await
+                .map_err(|err| RateLimitStoreError::Protocol(err.to_string()))?
+        })
+    }
+}
+
+/// A RESP2 reply.
+#[derive(Debug, Clone, PartialEq)]
+enum RespValue {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<RespValue>>),
+}
+
+/// A blocking connection speaking RESP2.
+#[derive(Debug)]
+struct RespConnection {
+    reader: BufReader<TcpStream>,
+}
+
+impl RespConnection {
+    /// Sends a command and reads its reply.
+    fn command(&mut self, args: &[&str]) -> Result<RespValue, RateLimitStoreError> {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            request.extend_from_slice(arg.as_bytes());
+            request.extend_from_slice(b"\r\n");
+        }
+        self.reader.get_mut().write_all(&request)?;
+
+        match read_value(&mut self.reader)? {
+            RespValue::Error(message) => Err(RateLimitStoreError::Protocol(message)),
+            value => Ok(value),
+        }
+    }
+
+    /// Sends a command expecting a simple-string reply (`OK`, `QUEUED`, ...).
+    fn expect_ok(&mut self, args: &[&str]) -> Result<(), RateLimitStoreError> {
+        match self.command(args)? {
+            RespValue::Simple(_) => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+}
+
+fn unexpected(value: RespValue) -> RateLimitStoreError {
+    RateLimitStoreError::Protocol(format!("{:?}", value))
+}
+
+/// Reads one RESP2 value.
+fn read_value(reader: &mut impl BufRead) -> Result<RespValue, RateLimitStoreError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(RateLimitStoreError::Protocol("connection closed".to_string()));
+    }
+    let line = line.trim_end_matches("\r\n");
+    let (kind, rest) = line.split_at(line.len().min(1));
+    let length = || {
+        rest.parse::<i64>()
+            .map_err(|_| RateLimitStoreError::Protocol(format!("bad length: {}", rest)))
+    };
+
+    match kind {
+        "+" => Ok(RespValue::Simple(rest.to_string())),
+        "-" => Ok(RespValue::Error(rest.to_string())),
+        ":" => Ok(RespValue::Integer(length()?)),
+        "$" => match length()? {
+            -1 => Ok(RespValue::Bulk(None)),
+            len if !(0..=REDIS_MAX_BULK_SIZE).contains(&len) => {
+                Err(RateLimitStoreError::Protocol(format!("bad length: {}", len)))
+            }
+            len => {
+                let mut data = vec![0; len as usize + 2];
+                reader.read_exact(&mut data)?;
+                data.truncate(len as usize);
+                Ok(RespValue::Bulk(Some(data)))
+            }
+        },
+        "*" => match length()? {
+            -1 => Ok(RespValue::Array(None)),
+            len if len < -1 => Err(RateLimitStoreError::Protocol(format!("bad length: {}", len))),
+            len => (0..len)
+                .map(|_| read_value(reader))
+                .collect::<Result<_, _>>()
+                .map(|v| RespValue::Array(Some(v))),
+        },
+        _ => Err(RateLimitStoreError::Protocol(format!("unknown reply: {}", line))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::rate_limit_helper::{FixedWindow, RateLimitAlgorithm, RateLimitQuota};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn fixed_window(max_requests: u64, now: Duration) -> StateUpdate {
+        let quota = RateLimitQuota::new(max_requests, SECOND);
+        Arc::new(move |state: &mut RateLimitState| FixedWindow.acquire(state, &quota, now))
+    }
+
+    #[actix_web::test]
+    async fn test_in_memory_store_updates_state() {
+        let store = InMemoryStore::new();
+        let now = Duration::ZERO;
+
+        let first = store.update("a".into(), now, SECOND, fixed_window(1, now)).await.unwrap();
+        let second = store.update("a".into(), now, SECOND, fixed_window(1, now)).await.unwrap();
+        let other = store.update("b".into(), now, SECOND, fixed_window(1, now)).await.unwrap();
+
+        assert!(first.allowed);
+        assert!(!second.allowed);
+        assert!(other.allowed);
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn test_in_memory_store_evicts_expired_states() {
+        let store = InMemoryStore::new();
+        store
+            .update_now("a".into(), Duration::ZERO, SECOND, &fixed_window(1, Duration::ZERO))
+            .unwrap();
+        store.update_now("b".into(), SECOND / 2, SECOND, &fixed_window(1, SECOND / 2)).unwrap();
+        assert_eq!(store.len(), 2);
+
+        // "a" expires at 1s, "b" at 1.5s
+        let now = SECOND;
+        store.update_now("c".into(), now, SECOND, &fixed_window(1, now)).unwrap();
+        assert_eq!(store.len(), 2);
+
+        // Touching "b" extends its TTL
+        let now = SECOND + SECOND / 4;
+        store.update_now("b".into(), now, SECOND, &fixed_window(1, now)).unwrap();
+        let now = 2 * SECOND;
+        store.update_now("d".into(), now, SECOND, &fixed_window(1, now)).unwrap();
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn test_in_memory_store_capacity_bound() {
+        let store = InMemoryStore::with_capacity(2);
+        for (i, key) in ["a", "b", "c"].iter().enumerate() {
+            let now = Duration::from_millis(i as u64);
+            store.update_now(key.to_string(), now, SECOND, &fixed_window(1, now)).unwrap();
+        }
+        assert_eq!(store.len(), 2);
+
+        // "a" was evicted, so it starts a fresh window
+        let now = Duration::from_millis(3);
+        assert!(store.update_now("a".into(), now, SECOND, &fixed_window(1, now)).unwrap().allowed);
+        // "c" is still tracked
+        assert!(!store.update_now("c".into(), now, SECOND, &fixed_window(1, now)).unwrap().allowed);
+    }
+
+    /// Minimal single-threaded stand-in for a Redis server, supporting the commands used by
+    /// `RedisStore`. The first `conflicts` transactions are aborted as if a watched key changed,
+    /// and connections are closed after `max_commands` commands, as if they had been idle.
+    fn spawn_stand_in_server(conflicts: usize, max_commands: usize) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let aborted = Arc::new(AtomicUsize::new(0));
+        let aborted_count = aborted.clone();
+
+        std::thread::spawn(move || {
+            let data = Arc::new(Mutex::new(HashMap::<String, String>::new()));
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { break };
+                let data = data.clone();
+                let aborted = aborted_count.clone();
+                std::thread::spawn(move || serve(stream, data, aborted, conflicts, max_commands));
+            }
+        });
+
+        (address, aborted)
+    }
+
+    fn serve(
+        stream: TcpStream,
+        data: Arc<Mutex<HashMap<String, String>>>,
+        aborted: Arc<AtomicUsize>,
+        conflicts: usize,
+        max_commands: usize,
+    ) {
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut queued: Option<Vec<Vec<String>>> = None;
+
+        for _ in 0..max_commands {
+            let Ok(RespValue::Array(Some(args))) = read_value(&mut reader) else { return };
+            let args: Vec<String> = args
+                .into_iter()
+                .map(|arg| match arg {
+                    RespValue::Bulk(Some(bytes)) => String::from_utf8(bytes).unwrap(),
+                    other => panic!("unexpected argument {:?}", other),
+                })
+                .collect();
+
+            let reply = match (args[0].as_str(), queued.as_mut()) {
+                ("MULTI", _) => {
+                    queued = Some(Vec::new());
+                    "+OK\r\n".to_string()
+                }
+                ("EXEC", Some(_)) if aborted.load(Ordering::SeqCst) < conflicts => {
+                    aborted.fetch_add(1, Ordering::SeqCst);
+                    queued = None;
+                    "*-1\r\n".to_string()
+                }
+                ("EXEC", Some(_)) => {
+                    let commands = queued.take().unwrap();
+                    let mut data = data.lock().unwrap();
+                    for command in &commands {
+                        assert_eq!(command[0], "SET");
+                        assert_eq!(command[3], "PX");
+                        data.insert(command[1].clone(), command[2].clone());
+                    }
+                    format!("*{}\r\n{}", commands.len(), "+OK\r\n".repeat(commands.len()))
+                }
+                (_, Some(commands)) => {
+                    commands.push(args.clone());
+                    "+QUEUED\r\n".to_string()
+                }
+                ("WATCH", None) | ("AUTH", None) => "+OK\r\n".to_string(),
+                ("GET", None) => match data.lock().unwrap().get(&args[1]) {
+                    Some(value) => format!("${}\r\n{}\r\n", value.len(), value),
+                    None => "$-1\r\n".to_string(),
+                },
+                (command, None) => format!("-ERR unknown command '{}'\r\n", command),
+            };
+            writer.write_all(reply.as_bytes()).unwrap();
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_redis_store_shares_state_between_instances() {
+        let (address, _) = spawn_stand_in_server(0, usize::MAX);
+        let pod_a = RedisStore::new(address.clone()).with_password("secret");
+        let pod_b = RedisStore::new(address).with_password("secret");
+        let now = Duration::ZERO;
+
+        let first = pod_a.update("ip:1".into(), now, SECOND, fixed_window(2, now)).await.unwrap();
+        let second = pod_b.update("ip:1".into(), now, SECOND, fixed_window(2, now)).await.unwrap();
+        let third = pod_a.update("ip:1".into(), now, SECOND, fixed_window(2, now)).await.unwrap();
+
+        assert_eq!((first.allowed, first.remaining), (true, 1));
+        assert_eq!((second.allowed, second.remaining), (true, 0));
+        assert!(!third.allowed);
+    }
+
+    #[actix_web::test]
+    async fn test_redis_store_retries_conflicting_transactions() {
+        let (address, aborted) = spawn_stand_in_server(2, usize::MAX);
+        let store = RedisStore::new(address);
+        let now = Duration::ZERO;
+
+        let decision =
+            store.update("ip:1".into(), now, SECOND, fixed_window(1, now)).await.unwrap();
+        assert!(decision.allowed);
+        assert_eq!(aborted.load(Ordering::SeqCst), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_redis_store_resolves_host_names_and_reconnects() {
+        // WATCH, GET, MULTI, SET and EXEC: one transaction per connection
+        let (address, _) = spawn_stand_in_server(0, 5);
+        let port = address.rsplit(':').next().unwrap();
+        let store = RedisStore::new(format!("localhost:{}", port));
+        let now = Duration::ZERO;
+
+        let first = store.update("ip:1".into(), now, SECOND, fixed_window(2, now)).await.unwrap();
+        let second = store.update("ip:1".into(), now, SECOND, fixed_window(2, now)).await.unwrap();
+        assert_eq!((first.remaining, second.remaining), (1, 0));
+    }
+
+    #[test]
+    fn test_resp_rejects_invalid_lengths() {
+        for reply in ["$-2\r\n", "$-9223372036854775808\r\n", "$99999999999\r\n", "*-5\r\n"] {
+            let result = read_value(&mut BufReader::new(reply.as_bytes()));
+            assert!(matches!(result, Err(RateLimitStoreError::Protocol(_))), "{}", reply);
+        }
+        let bulk = read_value(&mut BufReader::new("$2\r\nok\r\n".as_bytes())).unwrap();
+        assert_eq!(bulk, RespValue::Bulk(Some(b"ok".to_vec())));
+    }
+
+    #[actix_web::test]
+    async fn test_redis_store_reports_connection_errors() {
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let store = RedisStore::new(address).with_timeout(Duration::from_millis(200));
+
+        let result = store
+            .update("ip:1".into(), Duration::ZERO, SECOND, fixed_window(1, Duration::ZERO))
+            .await;
+        assert!(matches!(result, Err(RateLimitStoreError::Io(_))));
+    }
+}
@@ -0,0 +1,464 @@
+//! # Request Size Limits
+//!
+//! Provides `RequestSizeGuard`, a middleware rejecting oversized requests with the precise
+//! catalog code instead of actix's plain-text errors:
+//! - a URI longer than allowed → `URITooLong` (414)
+//! - a header field larger than allowed → `RequestHeaderTooLarge` (494)
+//! - header fields larger than allowed in total → `RequestHeaderFieldsTooLarge` (431)
+//! - an upload without `Content-Length`, when required → `LengthRequired` (411)
+//! - a body larger than allowed → `ContentTooLarge` (413)
+//!
+//! The body limit is checked against `Content-Length` before the handler runs, then by
+//! counting the bytes actually streamed, which covers chunked uploads and lying clients.
+//! The size of a header field is the length of its name plus the length of its value.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::{web, App, HttpResponse};
+//! use simbld_http::helpers::request_limits_helper::{RequestLimits, RequestSizeGuard};
+//!
+//! let app = App::new()
+//!     .wrap(
+//!         RequestSizeGuard::new(RequestLimits::new().with_max_body_size(64 * 1024))
+//!             .route(
+//!                 "/uploads",
+//!                 RequestLimits::new()
+//!                     .with_max_body_size(10 * 1024 * 1024)
+//!                     .require_content_length(),
+//!             ),
+//!     )
+//!     .route("/uploads", web::post().to(HttpResponse::Created));
+//! ```
+
+use crate::responses::{ResponsesClientCodes, ResponsesTypes};
+use actix_web::{
+    body::{BoxBody, EitherBody, MessageBody},
+    dev::{Payload, ResourceDef, Service, ServiceRequest, ServiceResponse, Transform},
+    error::PayloadError,
+    http::{header, Method},
+    web::Bytes,
+    Error, HttpMessage, HttpResponse, ResponseError,
+};
+use futures_util::future::{ok, LocalBoxFuture, Ready};
+use futures_util::Stream;
+use std::cell::Cell;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use thiserror::Error;
+
+/// Limits a request exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Error)]
+pub enum LimitError {
+    #[error("Content Too Large")]
+    ContentTooLarge,
+    #[error("URI Too Long")]
+    URITooLong,
+    #[error("Request Header Fields Too Large")]
+    HeaderFieldsTooLarge,
+    #[error("Request Header Too Large")]
+    HeaderTooLarge,
+    #[error("Length Required")]
+    LengthRequired,
+}
+
+impl LimitError {
+    /// Returns the catalog response describing the exceeded limit.
+    pub fn response_type(&self) -> ResponsesTypes {
+        ResponsesTypes::ClientError(match self {
+            LimitError::ContentTooLarge => ResponsesClientCodes::ContentTooLarge,
+            LimitError::URITooLong => ResponsesClientCodes::URITooLong,
+            LimitError::HeaderFieldsTooLarge => ResponsesClientCodes::RequestHeaderFieldsTooLarge,
+            LimitError::HeaderTooLarge => ResponsesClientCodes::RequestHeaderTooLarge,
+            LimitError::LengthRequired => ResponsesClientCodes::LengthRequired,
+        })
+    }
+}
+
+impl ResponseError for LimitError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        actix_web::http::StatusCode::from_u16(self.response_type().get_code())
+            .unwrap_or(actix_web::http::StatusCode::BAD_REQUEST)
+    }
+
+    /// Renders the catalog body, with `X-Limit-Error` naming the exceeded limit.
+    fn error_response(&self) -> HttpResponse {
+        let response = self.response_type();
+        response
+            .response_builder()
+            .insert_header(("X-HTTP-Status-Code", response.get_code().to_string()))
+            .insert_header(("X-Limit-Error", self.to_string()))
+            .content_type("application/json")
+            .body(response.as_json().to_string())
+    }
+}
+
+/// Size limits of a request. Limits left unset are not enforced.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RequestLimits {
+    /// Maximum body size, in bytes.
+    pub max_body_size: Option<u64>,
+    /// Maximum length of the request target (path and query), in bytes.
+    pub max_uri_length: Option<usize>,
+    /// Maximum size of all header fields together, in bytes.
+    pub max_headers_size: Option<usize>,
+    /// Maximum size of a single header field, in bytes.
+    pub max_header_size: Option<usize>,
+    /// Whether `POST`, `PUT` and `PATCH` requests must declare their `Content-Length`.
+    pub require_content_length: bool,
+}
+
+impl RequestLimits {
+    /// Creates limits enforcing nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum body size, in bytes.
+    pub fn with_max_body_size(mut self, bytes: u64) -> Self {
+        self.max_body_size = Some(bytes);
+        self
+    }
+
+    /// Sets the maximum length of the request target, in bytes.
+    pub fn with_max_uri_length(mut self, bytes: usize) -> Self {
+        self.max_uri_length = Some(bytes);
+        self
+    }
+
+    /// Sets the maximum size of all header fields together, in bytes.
+    pub fn with_max_headers_size(mut self, bytes: usize) -> Self {
+        self.max_headers_size = Some(bytes);
+        self
+    }
+
+    /// Sets the maximum size of a single header field, in bytes.
+    pub fn with_max_header_size(mut self, bytes: usize) -> Self {
+        self.max_header_size = Some(bytes);
+        self
+    }
+
+    /// Requires uploads (`POST`, `PUT` and `PATCH`) to declare their `Content-Length`.
+    pub fn require_content_length(mut self) -> Self {
+        self.require_content_length = true;
+        self
+    }
+
+    /// Checks everything known before the body is read.
+    pub fn check(&self, req: &ServiceRequest) -> Result<(), LimitError> {
+        if let Some(max) = self.max_uri_length {
+            let uri = req.uri().path_and_query().map_or(req.path(), |target| target.as_str());
+            if uri.len() > max {
+                return Err(LimitError::URITooLong);
+            }
+        }
+
+        let sizes = req.headers().iter().map(|(name, value)| name.as_str().len() + value.len());
+        let (mut total, mut largest) = (0, 0);
+        for size in sizes {
+            total += size;
+            largest = largest.max(size);
+        }
+        if self.max_header_size.is_some_and(|max| largest > max) {
+            return Err(LimitError::HeaderTooLarge);
+        }
+        if self.max_headers_size.is_some_and(|max| total > max) {
+            return Err(LimitError::HeaderFieldsTooLarge);
+        }
+
+        let length = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok());
+        let upload = [Method::POST, Method::PUT, Method::PATCH].contains(req.method());
+        if self.require_content_length && upload && length.is_none() {
+            return Err(LimitError::LengthRequired);
+        }
+        if let (Some(max), Some(length)) = (self.max_body_size, length) {
+            if length > max {
+                return Err(LimitError::ContentTooLarge);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct RouteLimits {
+    path: ResourceDef,
+    limits: RequestLimits,
+}
+
+/// Middleware enforcing request size limits.
+///
+/// Built either around limits applying to everything it wraps (`new`), or as a route
+/// table (`table`) whose first entry matching the path applies. Requests matching no
+/// entry are let through, unless fallback limits are set.
+///
+#[derive(Clone)]
+pub struct RequestSizeGuard {
+    routes: Vec<RouteLimits>,
+    fallback: Option<RequestLimits>,
+}
+
+impl RequestSizeGuard {
+    /// Creates a guard applying `limits` to every request.
+    pub fn new(limits: RequestLimits) -> Self {
+        Self::table().otherwise(limits)
+    }
+
+    /// Creates an empty route table.
+    pub fn table() -> Self {
+        Self { routes: Vec::new(), fallback: None }
+    }
+
+    /// Applies `limits` to requests whose path matches `pattern` or lies below it
+    /// (e.g. `/uploads/{id}`).
+    pub fn route(mut self, pattern: &str, limits: RequestLimits) -> Self {
+        self.routes.push(RouteLimits { path: ResourceDef::prefix(pattern), limits });
+        self
+    }
+
+    /// Applies `limits` to requests matching no route.
+    pub fn otherwise(mut self, limits: RequestLimits) -> Self {
+        self.fallback = Some(limits);
+        self
+    }
+
+    /// Returns the limits applying to `path`, if any.
+    pub fn limits_for(&self, path: &str) -> Option<RequestLimits> {
+        self.routes
+            .iter()
+            .find(|route| route.path.is_match(path))
+            .map(|route| route.limits)
+            .or(self.fallback)
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestSizeGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Transform = RequestSizeGuardService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestSizeGuardService { service: Rc::new(service), config: Rc::new(self.clone()) })
+    }
+}
+
+/// Service created by `RequestSizeGuard` to process requests.
+pub struct RequestSizeGuardService<S> {
+    service: Rc<S>,
+    config: Rc<RequestSizeGuard>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestSizeGuardService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    /// Rejects requests exceeding the limits of their route with the catalog response,
+    /// including those whose streamed body turns out larger than allowed.
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let Some(limits) = self.config.limits_for(req.path()) else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_left_body()) });
+        };
+        if let Err(err) = limits.check(&req) {
+            return Box::pin(async move {
+                Ok(req.into_response(err.error_response()).map_into_right_body())
+            });
+        }
+
+        let overflowed = Rc::new(Cell::new(false));
+        if let Some(max) = limits.max_body_size {
+            let payload = CountedPayload {
+                inner: req.take_payload(),
+                remaining: max,
+                overflowed: overflowed.clone(),
+            };
+            req.set_payload(Payload::Stream { payload: Box::pin(payload) });
+        }
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let result = fut.await;
+            if !overflowed.get() {
+                return result.map(|res| res.map_into_left_body());
+            }
+            // Whatever the handler made of the overflow, report it from the catalog
+            let error = LimitError::ContentTooLarge;
+            match result {
+                Ok(res) => Ok(res.into_response(error.error_response()).map_into_right_body()),
+                Err(_) => Err(error.into()),
+            }
+        })
+    }
+}
+
+/// Body stream failing once more bytes than allowed were received.
+struct CountedPayload {
+    inner: Payload,
+    remaining: u64,
+    overflowed: Rc<Cell<bool>>,
+}
+
+impl Stream for CountedPayload {
+    type Item = Result<Bytes, PayloadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.overflowed.get() {
+            return Poll::Ready(Some(Err(PayloadError::Overflow)));
+        }
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            match self.remaining.checked_sub(chunk.len() as u64) {
+                Some(remaining) => self.remaining = remaining,
+                None => {
+                    self.overflowed.set(true);
+                    return Poll::Ready(Some(Err(PayloadError::Overflow)));
+                }
+            }
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App};
+    use futures_util::{stream, StreamExt};
+
+    #[actix_web::test]
+    async fn test_uri_and_header_limits() {
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    RequestSizeGuard::new(
+                        RequestLimits::new()
+                            .with_max_uri_length(32)
+                            .with_max_header_size(64)
+                            .with_max_headers_size(128),
+                    )
+                    .route("/open", RequestLimits::new()),
+                )
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let cases = [
+            (test::TestRequest::get().uri("/short"), StatusCode::OK, None),
+            (
+                test::TestRequest::get().uri(&format!("/search?q={}", "x".repeat(32))),
+                StatusCode::URI_TOO_LONG,
+                Some("URI Too Long"),
+            ),
+            (
+                test::TestRequest::get().uri("/").insert_header(("X-Big", "x".repeat(64))),
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                Some("Request Header Too Large"),
+            ),
+            (
+                test::TestRequest::get()
+                    .uri("/")
+                    .insert_header(("X-One", "x".repeat(50)))
+                    .insert_header(("X-Two", "x".repeat(50)))
+                    .insert_header(("X-Three", "x".repeat(50))),
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                Some("Request Header Fields Too Large"),
+            ),
+            (
+                test::TestRequest::get().uri(&format!("/open?q={}", "x".repeat(64))),
+                StatusCode::OK,
+                None,
+            ),
+        ];
+        for (req, status, error) in cases {
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), status);
+            assert_eq!(
+                resp.headers().get("X-Limit-Error").map(|value| value.to_str().unwrap()),
+                error
+            );
+        }
+
+        let req = test::TestRequest::get().uri("/").insert_header(("X-Big", "x".repeat(64)));
+        let resp = test::call_service(&app, req.to_request()).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["details"]["internal http code"]["code"], 494);
+    }
+
+    #[actix_web::test]
+    async fn test_declared_body_limits() {
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestSizeGuard::new(
+                    RequestLimits::new().with_max_body_size(8).require_content_length(),
+                ))
+                .default_service(web::to(
+                    |body: Bytes| async move { HttpResponse::Ok().body(body) },
+                )),
+        )
+        .await;
+
+        let req = test::TestRequest::post().uri("/").set_payload("small");
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+
+        let req = test::TestRequest::post().uri("/").set_payload("far too large");
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let req = test::TestRequest::post().uri("/");
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), StatusCode::LENGTH_REQUIRED);
+
+        // Only uploads need to declare their length
+        let req = test::TestRequest::get().uri("/");
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn test_streamed_body_limit() {
+        let handler = actix_service::fn_service(|mut req: ServiceRequest| async move {
+            let mut payload = req.take_payload();
+            let mut received = 0;
+            while let Some(chunk) = payload.next().await {
+                match chunk {
+                    Ok(chunk) => received += chunk.len(),
+                    Err(_) => return Ok(req.into_response(HttpResponse::BadRequest().finish())),
+                }
+            }
+            Ok::<_, Error>(req.into_response(HttpResponse::Ok().body(received.to_string())))
+        });
+        let guard = RequestSizeGuard::new(RequestLimits::new().with_max_body_size(8));
+        let service = guard.new_transform(handler).await.unwrap();
+
+        for (chunks, status) in [
+            (vec!["1234", "5678"], StatusCode::OK),
+            (vec!["1234", "5678", "9"], StatusCode::PAYLOAD_TOO_LARGE),
+        ] {
+            // Chunked upload, without `Content-Length`
+            let mut req = test::TestRequest::post().uri("/").to_srv_request();
+            let chunks = chunks.into_iter().map(|chunk| Ok(Bytes::from_static(chunk.as_bytes())));
+            req.set_payload(Payload::Stream { payload: Box::pin(stream::iter(chunks)) });
+            let resp = service.call(req).await.unwrap();
+            assert_eq!(resp.status(), status);
+        }
+    }
+}
@@ -14,13 +14,13 @@
 //! These utilities are designed to provide a consistent API for handling
 //! HTTP responses throughout the application.
 
+use crate::helpers::tracing_helper::trace_event;
 use crate::responses::ResponsesTypes;
 use crate::responses::{
     ResponsesClientCodes, ResponsesCrawlerCodes, ResponsesInformationalCodes,
     ResponsesLocalApiCodes, ResponsesRedirectionCodes, ResponsesServerCodes, ResponsesServiceCodes,
     ResponsesSuccessCodes,
 };
-/// The code provides functions for handling HTTP response codes, including retrieving descriptions, converting to JSON/XML, filtering by range, and adding metadata.
 use crate::traits::get_description_trait::GetDescription;
 use crate::utils::populate_metadata::populate_metadata;
 use serde_json::{json, Value};
@@ -29,6 +29,9 @@ use std::time::Duration;
 use std::time::SystemTime;
 use strum::IntoEnumIterator;
 
+/// Target of the catalog lookup events.
+const CATALOG_TARGET: &str = "simbld_http::catalog";
+
 /// Returns the standard code and description for a given response type.
 pub fn get_response_get_description(response: ResponsesTypes) -> (u16, &'static str) {
     let code = response.get_code();
@@ -47,7 +50,10 @@ pub fn get_advance_response_get_description(response: ResponsesTypes) -> (u16, &
     let timestamp = SystemTime::now();
 
     // Simulate a middleware call here to record logs
-    log::info!(
+    trace_event!(
+        info,
+        target: CATALOG_TARGET,
+        { code = response.get_code(), family = crate::utils::populate_metadata::status_family(response.get_code()), },
         "Fetching description for code: {}, timestamp: {:?}",
         response.get_code(),
         timestamp
@@ -55,9 +61,9 @@ pub fn get_advance_response_get_description(response: ResponsesTypes) -> (u16, &
 
     // Simulate a CORS check or any other advanced security logic
     if let Ok(origin) = std::env::var("ALLOWED_ORIGIN") {
-        log::debug!("Applying CORS check for origin: {}", origin);
+        trace_event!(debug, target: CATALOG_TARGET, {}, "Applying CORS check for origin: {}", origin);
     } else {
-        log::warn!("No ALLOWED_ORIGIN set; defaulting to open.");
+        trace_event!(warn, target: CATALOG_TARGET, {}, "No ALLOWED_ORIGIN set; defaulting to open.");
     }
 
     // Provide a fallback description if not present
@@ -90,17 +96,24 @@ pub fn get_description_by_code(code: u16) -> Option<&'static str> {
 
 /// Matches the input code with predefined HTTP response codes and returns the corresponding description as a static string if a match is found.
 pub fn get_advance_description_by_code(code: u16) -> Option<&'static str> {
-    log::info!("Fetching description for code: {}", code);
+    trace_event!(info, target: CATALOG_TARGET, { code, }, "Fetching description for code: {}", code);
 
     let fetched_description = get_response_by_code(code).map(|response_type| {
         let description = GetDescription::get_description_field(&response_type, "Description")
             .unwrap_or("No description");
-        log::debug!("Code {} corresponds to description: {}", code, description);
+        trace_event!(
+            debug,
+            target: CATALOG_TARGET,
+            { code, family = crate::utils::populate_metadata::status_family(code), },
+            "Code {} corresponds to description: {}",
+            code,
+            description
+        );
         description
     });
 
     if fetched_description.is_none() {
-        log::warn!("No response type found for code: {}", code);
+        trace_event!(warn, target: CATALOG_TARGET, { code, }, "No response type found for code: {}", code);
     }
 
     fetched_description
@@ -0,0 +1,769 @@
+//! # Security Headers
+//!
+//! Provides `SecurityHeaders`, a middleware setting the response headers that harden
+//! browsers against common attacks, and enforcing HTTPS.
+//!
+//! A `SecurityPolicy` describes the headers to send:
+//! - `Strict-Transport-Security` (`Hsts`), only sent over HTTPS.
+//! - `Content-Security-Policy` (`ContentSecurityPolicy` builder), optionally with a nonce
+//!   generated per request, added to `script-src` and `style-src`, and given to handlers
+//!   through the `CspNonce` extractor.
+//! - `X-Content-Type-Options`, `X-Frame-Options`, `Referrer-Policy`, `Permissions-Policy`,
+//!   `Cross-Origin-Opener-Policy`, `Cross-Origin-Embedder-Policy` and
+//!   `Cross-Origin-Resource-Policy`.
+//!
+//! Three presets are provided: `strict` for HTML applications, `api` for JSON APIs and
+//! `legacy` for applications relying on inline scripts or framing by their own origin.
+//! Headers already set by a handler are left untouched, and routes can use another policy.
+//!
+//! Plain HTTP requests can be redirected to HTTPS with `PermanentRedirect` (308), or, for
+//! APIs whose clients would silently follow the redirect, rejected with `HTTPToHTTPS` (497).
+//!
+//! The scheme is the one of the connection (`App` configuration), and redirects point to
+//! the configured host, or else the server host name. The scheme and host forwarded by a
+//! reverse proxy are only believed when the peer is one of the trusted proxies of a
+//! `ClientIpResolver`, and only read from its `ForwardedHeader` (`X-Forwarded-Proto` and
+//! `X-Forwarded-Host`, or `Forwarded`), so that clients cannot skip the enforcement nor
+//! poison redirects.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::{web, App, HttpResponse};
+//! use simbld_http::helpers::security_headers_helper::{CspNonce, SecurityHeaders, SecurityPolicy};
+//!
+//! let headers = SecurityHeaders::new(SecurityPolicy::strict())
+//!     .route("/api", SecurityPolicy::api())
+//!     .with_host("www.example.com");
+//!
+//! let app = App::new().wrap(headers).route(
+//!     "/",
+//!     web::get().to(|nonce: CspNonce| async move {
+//!         HttpResponse::Ok().body(format!("<script nonce=\"{}\">init()</script>", nonce))
+//!     }),
+//! );
+//! ```
+
+use crate::helpers::client_ip_helper::{ClientIpResolver, ForwardedHeader};
+use crate::responses::{ResponsesClientCodes, ResponsesRedirectionCodes, ResponsesTypes};
+use actix_web::{
+    body::{BoxBody, EitherBody, MessageBody},
+    dev::{Payload, ResourceDef, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{self, HeaderName, HeaderValue},
+    Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError,
+};
+use base64::Engine;
+use futures_util::future::{ok, ready, LocalBoxFuture, Ready};
+use std::fmt;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Reasons for refusing a request.
+#[derive(Debug, Clone, Copy, PartialEq, Error)]
+pub enum SecurityError {
+    #[error("HTTP To HTTPS")]
+    HTTPToHTTPS,
+}
+
+impl SecurityError {
+    /// Returns the catalog response describing the refusal.
+    pub fn response_type(&self) -> ResponsesTypes {
+        match self {
+            SecurityError::HTTPToHTTPS => {
+                ResponsesTypes::ClientError(ResponsesClientCodes::HTTPToHTTPS)
+            }
+        }
+    }
+}
+
+impl ResponseError for SecurityError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        actix_web::http::StatusCode::from_u16(self.response_type().get_code())
+            .unwrap_or(actix_web::http::StatusCode::BAD_REQUEST)
+    }
+
+    /// Renders the catalog body, with `X-Security-Error` naming the refusal.
+    fn error_response(&self) -> HttpResponse {
+        let response = self.response_type();
+        response
+            .response_builder()
+            .insert_header(("X-HTTP-Status-Code", response.get_code().to_string()))
+            .insert_header(("X-Security-Error", self.to_string()))
+            .content_type("application/json")
+            .body(response.as_json().to_string())
+    }
+}
+
+/// `Strict-Transport-Security` settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hsts {
+    pub max_age: Duration,
+    pub include_subdomains: bool,
+    pub preload: bool,
+}
+
+impl Hsts {
+    /// Creates settings for the given `max-age`, without subdomains or preloading.
+    pub fn new(max_age: Duration) -> Self {
+        Self { max_age, include_subdomains: false, preload: false }
+    }
+
+    /// Applies the policy to subdomains as well.
+    pub fn include_subdomains(mut self) -> Self {
+        self.include_subdomains = true;
+        self
+    }
+
+    /// Asks for inclusion in browser preload lists.
+    pub fn preload(mut self) -> Self {
+        self.preload = true;
+        self
+    }
+}
+
+impl fmt::Display for Hsts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "max-age={}", self.max_age.as_secs())?;
+        if self.include_subdomains {
+            write!(f, "; includeSubDomains")?;
+        }
+        if self.preload {
+            write!(f, "; preload")?;
+        }
+        Ok(())
+    }
+}
+
+/// Builder of `Content-Security-Policy` values.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContentSecurityPolicy {
+    directives: Vec<(String, Vec<String>)>,
+    nonce: bool,
+    report_only: bool,
+}
+
+impl ContentSecurityPolicy {
+    /// Creates an empty policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a directive, replacing any previous value.
+    pub fn directive<I, S>(mut self, name: &str, sources: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let sources = sources.into_iter().map(Into::into).collect();
+        match self.directives.iter_mut().find(|(existing, _)| existing == name) {
+            Some((_, existing)) => *existing = sources,
+            None => self.directives.push((name.to_string(), sources)),
+        }
+        self
+    }
+
+    pub fn default_src<I: IntoIterator<Item = S>, S: Into<String>>(self, sources: I) -> Self {
+        self.directive("default-src", sources)
+    }
+
+    pub fn script_src<I: IntoIterator<Item = S>, S: Into<String>>(self, sources: I) -> Self {
+        self.directive("script-src", sources)
+    }
+
+    pub fn style_src<I: IntoIterator<Item = S>, S: Into<String>>(self, sources: I) -> Self {
+        self.directive("style-src", sources)
+    }
+
+    pub fn img_src<I: IntoIterator<Item = S>, S: Into<String>>(self, sources: I) -> Self {
+        self.directive("img-src", sources)
+    }
+
+    pub fn connect_src<I: IntoIterator<Item = S>, S: Into<String>>(self, sources: I) -> Self {
+        self.directive("connect-src", sources)
+    }
+
+    pub fn object_src<I: IntoIterator<Item = S>, S: Into<String>>(self, sources: I) -> Self {
+        self.directive("object-src", sources)
+    }
+
+    pub fn base_uri<I: IntoIterator<Item = S>, S: Into<String>>(self, sources: I) -> Self {
+        self.directive("base-uri", sources)
+    }
+
+    pub fn form_action<I: IntoIterator<Item = S>, S: Into<String>>(self, sources: I) -> Self {
+        self.directive("form-action", sources)
+    }
+
+    /// Sets the origins allowed to frame the page, superseding `X-Frame-Options`.
+    pub fn frame_ancestors<I: IntoIterator<Item = S>, S: Into<String>>(self, sources: I) -> Self {
+        self.directive("frame-ancestors", sources)
+    }
+
+    /// Asks browsers to load `http:` subresources over HTTPS.
+    pub fn upgrade_insecure_requests(self) -> Self {
+        self.directive("upgrade-insecure-requests", Vec::<String>::new())
+    }
+
+    /// Generates a nonce per request, allowed by `script-src` and `style-src`.
+    pub fn with_nonce(mut self) -> Self {
+        self.nonce = true;
+        self
+    }
+
+    /// Sends the policy as `Content-Security-Policy-Report-Only`.
+    pub fn report_only(mut self) -> Self {
+        self.report_only = true;
+        self
+    }
+
+    /// Whether a nonce is generated per request.
+    pub fn uses_nonce(&self) -> bool {
+        self.nonce
+    }
+
+    /// Returns the header carrying the policy.
+    pub fn header_name(&self) -> HeaderName {
+        match self.report_only {
+            true => header::CONTENT_SECURITY_POLICY_REPORT_ONLY,
+            false => header::CONTENT_SECURITY_POLICY,
+        }
+    }
+
+    /// Renders the policy, allowing `nonce` in `script-src` and `style-src`.
+    pub fn render(&self, nonce: Option<&str>) -> String {
+        self.directives
+            .iter()
+            .map(|(name, sources)| {
+                let mut value = vec![name.clone()];
+                value.extend(sources.iter().cloned());
+                if let Some(nonce) = nonce.filter(|_| name == "script-src" || name == "style-src") {
+                    value.push(format!("'nonce-{}'", nonce));
+                }
+                value.join(" ")
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// The CSP nonce of a request, to put in the `nonce` attribute of inline scripts and styles.
+///
+/// Extracting it fails with `500 Internal Server Error` when the policy uses no nonce.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CspNonce(pub String);
+
+impl CspNonce {
+    /// Generates a nonce of 128 random bits.
+    pub fn generate() -> Self {
+        let bytes = uuid::Uuid::new_v4().into_bytes();
+        Self(base64::engine::general_purpose::STANDARD.encode(bytes))
+    }
+}
+
+impl fmt::Display for CspNonce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromRequest for CspNonce {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let nonce = req.extensions().get::<CspNonce>().cloned();
+        ready(nonce.ok_or_else(|| {
+            actix_web::error::ErrorInternalServerError("No CSP nonce for this request")
+        }))
+    }
+}
+
+/// What to do with plain HTTP requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HttpsEnforcement {
+    /// Serve them.
+    #[default]
+    Off,
+    /// Redirect them to HTTPS with `308 Permanent Redirect`.
+    Redirect,
+    /// Reject them with `HTTPToHTTPS` (497).
+    Reject,
+}
+
+/// The security headers of a response, and the handling of plain HTTP requests.
+#[derive(Debug, Clone, Default)]
+pub struct SecurityPolicy {
+    hsts: Option<Hsts>,
+    csp: Option<ContentSecurityPolicy>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    https: HttpsEnforcement,
+}
+
+impl SecurityPolicy {
+    /// Creates a policy setting no header.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Preset for HTML applications: nonce-based CSP, no framing, cross-origin isolation,
+    /// two-year preloaded HSTS, and redirection to HTTPS.
+    pub fn strict() -> Self {
+        Self::new()
+            .with_hsts(
+                Hsts::new(Duration::from_secs(2 * 365 * 24 * 3600)).include_subdomains().preload(),
+            )
+            .with_csp(
+                ContentSecurityPolicy::new()
+                    .default_src(["'self'"])
+                    .script_src(["'self'"])
+                    .style_src(["'self'"])
+                    .img_src(["'self'", "data:"])
+                    .object_src(["'none'"])
+                    .base_uri(["'none'"])
+                    .form_action(["'self'"])
+                    .frame_ancestors(["'none'"])
+                    .upgrade_insecure_requests()
+                    .with_nonce(),
+            )
+            .with_content_type_options()
+            .with_frame_options("DENY")
+            .with_referrer_policy("no-referrer")
+            .with_permissions_policy("camera=(), microphone=(), geolocation=(), payment=()")
+            .with_cross_origin_opener_policy("same-origin")
+            .with_cross_origin_embedder_policy("require-corp")
+            .with_cross_origin_resource_policy("same-origin")
+            .with_https(HttpsEnforcement::Redirect)
+    }
+
+    /// Preset for JSON APIs: nothing may be loaded or framed, and plain HTTP is rejected.
+    pub fn api() -> Self {
+        Self::new()
+            .with_hsts(Hsts::new(Duration::from_secs(365 * 24 * 3600)).include_subdomains())
+            .with_csp(
+                ContentSecurityPolicy::new().default_src(["'none'"]).frame_ancestors(["'none'"]),
+            )
+            .with_content_type_options()
+            .with_frame_options("DENY")
+            .with_referrer_policy("no-referrer")
+            .with_cross_origin_resource_policy("same-origin")
+            .with_https(HttpsEnforcement::Reject)
+    }
+
+    /// Preset for older applications: framing by the same origin, no CSP on scripts.
+    pub fn legacy() -> Self {
+        Self::new()
+            .with_hsts(Hsts::new(Duration::from_secs(365 * 24 * 3600)))
+            .with_csp(ContentSecurityPolicy::new().frame_ancestors(["'self'"]))
+            .with_content_type_options()
+            .with_frame_options("SAMEORIGIN")
+            .with_referrer_policy("strict-origin-when-cross-origin")
+            .with_https(HttpsEnforcement::Redirect)
+    }
+
+    /// Sets `Strict-Transport-Security`.
+    pub fn with_hsts(mut self, hsts: Hsts) -> Self {
+        self.hsts = Some(hsts);
+        self
+    }
+
+    /// Sets `Content-Security-Policy`.
+    pub fn with_csp(mut self, csp: ContentSecurityPolicy) -> Self {
+        self.csp = Some(csp);
+        self
+    }
+
+    /// Sets a header, replacing any previous value.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `value` is not a valid header value.
+    pub fn with_header(mut self, name: HeaderName, value: &str) -> Self {
+        let value = HeaderValue::from_str(value).expect("invalid security header value");
+        self.headers.retain(|(existing, _)| *existing != name);
+        self.headers.push((name, value));
+        self
+    }
+
+    /// Stops sending a header, including `Strict-Transport-Security` and
+    /// `Content-Security-Policy`.
+    pub fn without(mut self, name: HeaderName) -> Self {
+        if name == header::STRICT_TRANSPORT_SECURITY {
+            self.hsts = None;
+        }
+        if name == header::CONTENT_SECURITY_POLICY {
+            self.csp = None;
+        }
+        self.headers.retain(|(existing, _)| *existing != name);
+        self
+    }
+
+    /// Sets `X-Content-Type-Options: nosniff`.
+    pub fn with_content_type_options(self) -> Self {
+        self.with_header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+    }
+
+    /// Sets `X-Frame-Options` (`DENY` or `SAMEORIGIN`).
+    pub fn with_frame_options(self, value: &str) -> Self {
+        self.with_header(header::X_FRAME_OPTIONS, value)
+    }
+
+    pub fn with_referrer_policy(self, value: &str) -> Self {
+        self.with_header(header::REFERRER_POLICY, value)
+    }
+
+    pub fn with_permissions_policy(self, value: &str) -> Self {
+        self.with_header(HeaderName::from_static("permissions-policy"), value)
+    }
+
+    pub fn with_cross_origin_opener_policy(self, value: &str) -> Self {
+        self.with_header(header::CROSS_ORIGIN_OPENER_POLICY, value)
+    }
+
+    pub fn with_cross_origin_embedder_policy(self, value: &str) -> Self {
+        self.with_header(header::CROSS_ORIGIN_EMBEDDER_POLICY, value)
+    }
+
+    pub fn with_cross_origin_resource_policy(self, value: &str) -> Self {
+        self.with_header(header::CROSS_ORIGIN_RESOURCE_POLICY, value)
+    }
+
+    /// Sets the handling of plain HTTP requests.
+    pub fn with_https(mut self, https: HttpsEnforcement) -> Self {
+        self.https = https;
+        self
+    }
+
+    /// Returns the headers of a response, over HTTPS when `secure`.
+    pub fn headers(
+        &self,
+        secure: bool,
+        nonce: Option<&CspNonce>,
+    ) -> Vec<(HeaderName, HeaderValue)> {
+        let mut headers = self.headers.clone();
+        if let Some(Ok(hsts)) = self.hsts.filter(|_| secure).map(|hsts| hsts.to_string().parse()) {
+            headers.push((header::STRICT_TRANSPORT_SECURITY, hsts));
+        }
+        if let Some(csp) = &self.csp {
+            let value = csp.render(nonce.map(|nonce| nonce.0.as_str()));
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.push((csp.header_name(), value));
+            }
+        }
+        headers
+    }
+}
+
+#[derive(Clone)]
+struct RoutePolicy {
+    path: ResourceDef,
+    policy: Rc<SecurityPolicy>,
+}
+
+/// Middleware applying security policies.
+///
+/// The policy of the first route matching the path applies, or the default one.
+#[derive(Clone)]
+pub struct SecurityHeaders {
+    routes: Vec<RoutePolicy>,
+    default: Rc<SecurityPolicy>,
+    host: Option<String>,
+    proxies: Option<ClientIpResolver>,
+}
+
+impl SecurityHeaders {
+    /// Creates the middleware applying `policy` to every request.
+    pub fn new(policy: SecurityPolicy) -> Self {
+        Self { routes: Vec::new(), default: Rc::new(policy), host: None, proxies: None }
+    }
+
+    /// Sets the host HTTPS redirects point to, instead of the server host name.
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Believes the scheme and host forwarded by the trusted proxies of `resolver`.
+    pub fn with_trusted_proxies(mut self, resolver: ClientIpResolver) -> Self {
+        self.proxies = Some(resolver);
+        self
+    }
+
+    /// Applies `policy` to requests whose path matches `pattern` or lies below it.
+    pub fn route(mut self, pattern: &str, policy: SecurityPolicy) -> Self {
+        self.routes
+            .push(RoutePolicy { path: ResourceDef::prefix(pattern), policy: Rc::new(policy) });
+        self
+    }
+
+    /// The header carrying the scheme and host, when the request comes from a trusted proxy.
+    fn forwarded_header(&self, req: &ServiceRequest) -> Option<ForwardedHeader> {
+        let peer = req.peer_addr().map(|addr| addr.ip())?;
+        let proxies = self.proxies.as_ref().filter(|proxies| proxies.is_trusted(peer))?;
+        Some(proxies.forwarded_header())
+    }
+
+    /// Whether the request was received over HTTPS.
+    pub fn is_secure(&self, req: &ServiceRequest) -> bool {
+        let proto = self.forwarded_header(req).and_then(|forwarded| forwarded.proto(req.headers()));
+        match proto {
+            Some(proto) => proto.eq_ignore_ascii_case("https"),
+            None => req.app_config().secure(),
+        }
+    }
+
+    /// Builds the catalog `308` pointing to the HTTPS version of the request URL.
+    fn https_redirect(&self, req: &ServiceRequest) -> HttpResponse {
+        let forwarded = || {
+            let forwarded = self.forwarded_header(req)?;
+            forwarded
+                .host(req.headers())
+                .or_else(|| req.headers().get(header::HOST)?.to_str().ok().map(str::to_string))
+        };
+        let host = self
+            .host
+            .clone()
+            .or_else(forwarded)
+            .unwrap_or_else(|| req.app_config().host().to_string());
+        let target = req.uri().path_and_query().map_or(req.path(), |target| target.as_str());
+        ResponsesTypes::Redirection(ResponsesRedirectionCodes::PermanentRedirect)
+            .response_builder()
+            .insert_header((header::LOCATION, format!("https://{}{}", host, target)))
+            .finish()
+    }
+
+    /// Returns the policy applying to `path`.
+    pub fn policy_for(&self, path: &str) -> Rc<SecurityPolicy> {
+        self.routes
+            .iter()
+            .find(|route| route.path.is_match(path))
+            .map_or_else(|| self.default.clone(), |route| route.policy.clone())
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SecurityHeaders
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Transform = SecurityHeadersService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SecurityHeadersService { service: Rc::new(service), config: Rc::new(self.clone()) })
+    }
+}
+
+/// Service created by `SecurityHeaders` to process requests.
+pub struct SecurityHeadersService<S> {
+    service: Rc<S>,
+    config: Rc<SecurityHeaders>,
+}
+
+impl<S, B> Service<ServiceRequest> for SecurityHeadersService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B, BoxBody>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    /// Enforces HTTPS, then adds the headers the handler did not set itself.
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let policy = self.config.policy_for(req.path());
+        let secure = self.config.is_secure(&req);
+
+        if !secure {
+            let refusal = match policy.https {
+                HttpsEnforcement::Off => None,
+                HttpsEnforcement::Redirect => Some(self.config.https_redirect(&req)),
+                HttpsEnforcement::Reject => Some(SecurityError::HTTPToHTTPS.error_response()),
+            };
+            if let Some(refusal) = refusal {
+                return Box::pin(
+                    async move { Ok(req.into_response(refusal).map_into_right_body()) },
+                );
+            }
+        }
+
+        let nonce =
+            policy.csp.as_ref().filter(|csp| csp.uses_nonce()).map(|_| CspNonce::generate());
+        if let Some(nonce) = &nonce {
+            req.extensions_mut().insert(nonce.clone());
+        }
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+            for (name, value) in policy.headers(secure, nonce.as_ref()) {
+                if !res.headers().contains_key(&name) {
+                    res.headers_mut().insert(name, value);
+                }
+            }
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App};
+
+    fn proxies() -> ClientIpResolver {
+        ClientIpResolver::new().with_trusted_proxies(["10.0.0.0/8"]).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_csp_builder() {
+        let csp = ContentSecurityPolicy::new()
+            .default_src(["'self'"])
+            .script_src(["'self'", "https://cdn.example.com"])
+            .default_src(["'none'"])
+            .upgrade_insecure_requests()
+            .with_nonce();
+        assert_eq!(
+            csp.render(Some("abc")),
+            "default-src 'none'; script-src 'self' https://cdn.example.com 'nonce-abc'; \
+             upgrade-insecure-requests"
+        );
+        assert_eq!(csp.header_name(), header::CONTENT_SECURITY_POLICY);
+        assert_eq!(csp.report_only().header_name(), header::CONTENT_SECURITY_POLICY_REPORT_ONLY);
+
+        let hsts = Hsts::new(Duration::from_secs(60)).include_subdomains().preload();
+        assert_eq!(hsts.to_string(), "max-age=60; includeSubDomains; preload");
+    }
+
+    #[actix_web::test]
+    async fn test_strict_preset_and_overrides() {
+        let headers =
+            SecurityHeaders::new(SecurityPolicy::strict().with_https(HttpsEnforcement::Off))
+                .route("/api", SecurityPolicy::api().with_https(HttpsEnforcement::Off))
+                .with_trusted_proxies(proxies());
+        let app = test::init_service(
+            App::new()
+                .wrap(headers)
+                .route("/", web::get().to(|nonce: CspNonce| async move { nonce.to_string() }))
+                .route(
+                    "/api/items",
+                    web::get().to(|| async {
+                        HttpResponse::Ok().insert_header(("referrer-policy", "origin")).finish()
+                    }),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .insert_header(("x-forwarded-proto", "https"));
+        let res = test::call_service(&app, req.to_request()).await;
+        let header = |res: &ServiceResponse<_>, name: &str| {
+            res.headers().get(name).map(|value| value.to_str().unwrap().to_string())
+        };
+        assert_eq!(header(&res, "x-frame-options").as_deref(), Some("DENY"));
+        assert_eq!(header(&res, "cross-origin-opener-policy").as_deref(), Some("same-origin"));
+        assert!(header(&res, "strict-transport-security").unwrap().contains("preload"));
+        let csp = header(&res, "content-security-policy").unwrap();
+        let nonce = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+        assert!(csp.contains(&format!("script-src 'self' 'nonce-{}'", nonce)));
+
+        // Route policy, without HSTS over plain HTTP, keeping the handler's own headers
+        let req = test::TestRequest::get().uri("/api/items").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(
+            header(&res, "content-security-policy").as_deref(),
+            Some("default-src 'none'; frame-ancestors 'none'")
+        );
+        assert_eq!(header(&res, "referrer-policy").as_deref(), Some("origin"));
+        assert!(header(&res, "strict-transport-security").is_none());
+        assert!(header(&res, "cross-origin-opener-policy").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_https_enforcement() {
+        let headers = SecurityHeaders::new(SecurityPolicy::strict())
+            .route("/api", SecurityPolicy::api())
+            .with_trusted_proxies(proxies());
+        let app =
+            test::init_service(App::new().wrap(headers).default_service(web::to(HttpResponse::Ok)))
+                .await;
+        let location = |res: &ServiceResponse<_>| {
+            res.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string()
+        };
+
+        // Redirects point to the server host name, whatever the client claims
+        let req = test::TestRequest::get()
+            .uri("/login?next=/home")
+            .insert_header((header::HOST, "evil.example"))
+            .insert_header(("x-forwarded-host", "evil.example"));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(location(&res), "https://localhost:8080/login?next=/home");
+
+        // ... or to the host forwarded by a trusted proxy
+        let req = test::TestRequest::get()
+            .uri("/login")
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .insert_header(("x-forwarded-host", "example.com"));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(location(&res), "https://example.com/login");
+
+        let req = test::TestRequest::get().uri("/api/items").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["details"]["internal http code"]["code"], 497);
+
+        // Only trusted proxies tell that the client used HTTPS
+        let req = test::TestRequest::get()
+            .uri("/api/items")
+            .peer_addr("203.0.113.9:4000".parse().unwrap())
+            .insert_header(("x-forwarded-proto", "https"));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::get()
+            .uri("/api/items")
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .insert_header(("x-forwarded-proto", "https"));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // Behind a proxy speaking `X-Forwarded-*`, a `Forwarded` header comes from the client
+        let req = test::TestRequest::get()
+            .uri("/login")
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .insert_header((header::FORWARDED, "proto=https;host=evil.example"));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+        assert!(!location(&res).contains("evil.example"));
+    }
+
+    #[actix_web::test]
+    async fn test_configured_host() {
+        let headers = SecurityHeaders::new(SecurityPolicy::strict())
+            .with_host("www.example.com")
+            .with_trusted_proxies(proxies());
+        let app =
+            test::init_service(App::new().wrap(headers).default_service(web::to(HttpResponse::Ok)))
+                .await;
+
+        // The configured host wins over the forwarded one
+        let req = test::TestRequest::get()
+            .uri("/cart")
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .insert_header(("x-forwarded-host", "cdn.example.net"));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.headers().get(header::LOCATION).unwrap(), "https://www.example.com/cart");
+    }
+}
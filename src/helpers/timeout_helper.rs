@@ -0,0 +1,335 @@
+//! # Request Timeouts
+//!
+//! Provides `Timeout`, a middleware giving handlers a deadline per route.
+//!
+//! When the deadline expires, the handler future is dropped, which cancels whatever it was
+//! awaiting, and the request is answered from the catalog:
+//! - `RequestTimeout` (408) when the handler was waiting for the client to send the body
+//! - `GatewayTimeout` (504) otherwise, the handler being presumably stuck on an upstream
+//! - `TransactionTimeout` (906) instead of 504 on payment routes
+//!
+//! The expired request resolves to a `TimeoutError`, which actix renders as the catalog
+//! response; middlewares wrapping `Timeout` see it as an error.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use actix_web::{web, App, HttpResponse};
+//! use simbld_http::helpers::timeout_helper::Timeout;
+//! use std::time::Duration;
+//!
+//! let app = App::new()
+//!     .wrap(
+//!         Timeout::new(Duration::from_secs(30))
+//!             .route("/reports", Duration::from_secs(120))
+//!             .payment_route("/payments", Duration::from_secs(10)),
+//!     )
+//!     .route("/payments", web::post().to(HttpResponse::Created));
+//! ```
+
+use crate::responses::{
+    ResponsesClientCodes, ResponsesLocalApiCodes, ResponsesServerCodes, ResponsesTypes,
+};
+use actix_web::{
+    body::MessageBody,
+    dev::{Payload, ResourceDef, Service, ServiceRequest, ServiceResponse, Transform},
+    error::PayloadError,
+    web::Bytes,
+    Error, HttpMessage, HttpResponse, ResponseError,
+};
+use futures_util::future::{ok, LocalBoxFuture, Ready};
+use futures_util::Stream;
+use std::cell::Cell;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Reasons for abandoning a request whose deadline expired.
+#[derive(Debug, Clone, Copy, PartialEq, Error)]
+pub enum TimeoutError {
+    /// The client did not send the body in time.
+    #[error("Request Timeout")]
+    Request,
+    /// The handler did not answer in time.
+    #[error("Gateway Timeout")]
+    Gateway,
+    /// The handler of a payment route did not answer in time.
+    #[error("Transaction Timeout")]
+    Transaction,
+}
+
+impl TimeoutError {
+    /// Returns the catalog response describing the timeout.
+    pub fn response_type(&self) -> ResponsesTypes {
+        match self {
+            TimeoutError::Request => {
+                ResponsesTypes::ClientError(ResponsesClientCodes::RequestTimeout)
+            }
+            TimeoutError::Gateway => {
+                ResponsesTypes::ServerError(ResponsesServerCodes::GatewayTimeout)
+            }
+            TimeoutError::Transaction => {
+                ResponsesTypes::LocalApiError(ResponsesLocalApiCodes::TransactionTimeout)
+            }
+        }
+    }
+}
+
+impl ResponseError for TimeoutError {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        actix_web::http::StatusCode::from_u16(self.response_type().get_code())
+            .unwrap_or(actix_web::http::StatusCode::GATEWAY_TIMEOUT)
+    }
+
+    /// Renders the catalog body, with `X-Timeout-Error` naming the timeout.
+    fn error_response(&self) -> HttpResponse {
+        let response = self.response_type();
+        response
+            .response_builder()
+            .insert_header(("X-HTTP-Status-Code", response.get_code().to_string()))
+            .insert_header(("X-Timeout-Error", self.to_string()))
+            .content_type("application/json")
+            .body(response.as_json().to_string())
+    }
+}
+
+/// Deadline of the requests matching a route, and how its expiry is reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Deadline {
+    /// Time allowed to the handler, body reception included.
+    pub budget: Duration,
+    /// Error reported when the handler was not waiting for the client.
+    pub on_expiry: TimeoutError,
+}
+
+#[derive(Clone)]
+struct RouteDeadline {
+    path: ResourceDef,
+    deadline: Deadline,
+}
+
+/// Middleware enforcing handler deadlines.
+///
+/// Built either around a single deadline applying to everything it wraps (`new`), or as a
+/// route table (`table`) whose first entry matching the path applies. Requests matching no
+/// entry have no deadline, unless a fallback deadline is set.
+///
+#[derive(Clone)]
+pub struct Timeout {
+    routes: Vec<RouteDeadline>,
+    fallback: Option<Deadline>,
+}
+
+impl Timeout {
+    /// Creates a middleware giving every request `budget`.
+    pub fn new(budget: Duration) -> Self {
+        Self::table().otherwise(budget)
+    }
+
+    /// Creates an empty route table.
+    pub fn table() -> Self {
+        Self { routes: Vec::new(), fallback: None }
+    }
+
+    /// Gives `budget` to requests whose path matches `pattern` or lies below it
+    /// (e.g. `/reports/{id}`), answering `GatewayTimeout` on expiry.
+    pub fn route(self, pattern: &str, budget: Duration) -> Self {
+        self.deadline(pattern, Deadline { budget, on_expiry: TimeoutError::Gateway })
+    }
+
+    /// Gives `budget` to requests whose path matches `pattern` or lies below it,
+    /// answering `TransactionTimeout` on expiry.
+    pub fn payment_route(self, pattern: &str, budget: Duration) -> Self {
+        self.deadline(pattern, Deadline { budget, on_expiry: TimeoutError::Transaction })
+    }
+
+    /// Applies `deadline` to requests whose path matches `pattern` or lies below it.
+    pub fn deadline(mut self, pattern: &str, deadline: Deadline) -> Self {
+        self.routes.push(RouteDeadline { path: ResourceDef::prefix(pattern), deadline });
+        self
+    }
+
+    /// Gives `budget` to requests matching no route, answering `GatewayTimeout` on expiry.
+    pub fn otherwise(mut self, budget: Duration) -> Self {
+        self.fallback = Some(Deadline { budget, on_expiry: TimeoutError::Gateway });
+        self
+    }
+
+    /// Returns the deadline applying to `path`, if any.
+    pub fn deadline_for(&self, path: &str) -> Option<Deadline> {
+        self.routes
+            .iter()
+            .find(|route| route.path.is_match(path))
+            .map(|route| route.deadline)
+            .or(self.fallback)
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Timeout
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TimeoutService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TimeoutService { service: Rc::new(service), config: Rc::new(self.clone()) })
+    }
+}
+
+/// Service created by `Timeout` to process requests.
+pub struct TimeoutService<S> {
+    service: Rc<S>,
+    config: Rc<Timeout>,
+}
+
+impl<S, B> Service<ServiceRequest> for TimeoutService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, ctx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    /// Runs the inner service within the deadline of the route, if any.
+    ///
+    /// Expired requests resolve to a `TimeoutError`, rendered as its catalog response.
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let Some(deadline) = self.config.deadline_for(req.path()) else {
+            return Box::pin(self.service.call(req));
+        };
+
+        // Watch the body, to tell slow clients from slow handlers
+        let waiting = Rc::new(Cell::new(false));
+        let payload = WatchedPayload { inner: req.take_payload(), waiting: waiting.clone() };
+        req.set_payload(Payload::Stream { payload: Box::pin(payload) });
+
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            match tokio::time::timeout(deadline.budget, fut).await {
+                Ok(result) => result,
+                Err(_) if waiting.get() => Err(TimeoutError::Request.into()),
+                Err(_) => Err(deadline.on_expiry.into()),
+            }
+        })
+    }
+}
+
+/// Body stream recording whether the handler is waiting for the client.
+struct WatchedPayload {
+    inner: Payload,
+    waiting: Rc<Cell<bool>>,
+}
+
+impl Stream for WatchedPayload {
+    type Item = Result<Bytes, PayloadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        self.waiting.set(poll.is_pending());
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web, App};
+    use futures_util::{stream, StreamExt};
+
+    async fn slow() -> HttpResponse {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn test_handler_deadlines() {
+        tokio::time::pause();
+        let app = test::init_service(
+            App::new()
+                .wrap(
+                    Timeout::table()
+                        .route("/reports", Duration::from_secs(5))
+                        .payment_route("/payments", Duration::from_secs(5)),
+                )
+                .route("/reports", web::get().to(slow))
+                .route("/payments/{id}", web::post().to(slow))
+                .route("/fast", web::get().to(HttpResponse::Ok))
+                .route("/unbounded", web::get().to(slow)),
+        )
+        .await;
+
+        // Expired requests resolve to an error, rendered by the server
+        let req = test::TestRequest::get().uri("/reports").to_request();
+        let resp = test::try_call_service(&app, req).await.unwrap_err().error_response();
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(resp.headers().get("X-Timeout-Error").unwrap(), "Gateway Timeout");
+
+        let req = test::TestRequest::post().uri("/payments/42").to_request();
+        let resp = test::try_call_service(&app, req).await.unwrap_err().error_response();
+        assert_eq!(resp.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(resp.headers().get("X-Timeout-Error").unwrap(), "Transaction Timeout");
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["details"]["internal http code"]["code"], 906);
+
+        let req = test::TestRequest::get().uri("/fast").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        // Routes without a deadline may take as long as they need
+        let start = tokio::time::Instant::now();
+        let req = test::TestRequest::get().uri("/unbounded").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        assert!(start.elapsed() >= Duration::from_secs(60));
+    }
+
+    #[actix_web::test]
+    async fn test_slow_client_and_cancellation() {
+        tokio::time::pause();
+
+        /// Sets its flag when dropped with the handler future.
+        struct DropFlag(Rc<Cell<bool>>);
+        impl Drop for DropFlag {
+            fn drop(&mut self) {
+                self.0.set(true);
+            }
+        }
+
+        let dropped = Rc::new(Cell::new(false));
+        let flag = dropped.clone();
+        let handler = actix_service::fn_service(move |mut req: ServiceRequest| {
+            let guard = DropFlag(flag.clone());
+            async move {
+                let _guard = guard;
+                let mut payload = req.take_payload();
+                while payload.next().await.is_some() {}
+                Ok::<_, Error>(req.into_response(HttpResponse::Ok().finish()))
+            }
+        });
+        let service = Timeout::new(Duration::from_secs(5)).new_transform(handler).await.unwrap();
+
+        // The client sends a first chunk, then stalls
+        let mut req = test::TestRequest::post().uri("/upload").to_srv_request();
+        let chunks = stream::iter([Ok::<_, PayloadError>(Bytes::from_static(b"partial"))])
+            .chain(stream::pending());
+        req.set_payload(Payload::Stream { payload: Box::pin(chunks) });
+        let err = service.call(req).await.unwrap_err();
+        let resp = err.error_response();
+
+        assert_eq!(resp.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(resp.headers().get("X-Timeout-Error").unwrap(), "Request Timeout");
+        assert!(dropped.get());
+    }
+}
@@ -0,0 +1,218 @@
+//! # Tracing
+//!
+//! W3C trace context propagation, and the glue used to report events through `tracing`.
+//!
+//! `HttpInterceptor` stores a `TraceContext` in the request extensions. It continues the
+//! trace of an incoming `traceparent` header (keeping `tracestate`), or starts a new one,
+//! and gives the request its own span id. Handlers receive it through the `TraceContext`
+//! extractor and forward `traceparent()` to downstream services.
+//!
+//! With the `tracing` feature, the interceptor also opens an `http.request` span per request
+//! carrying the trace ids, and records `http.status_code`, `simbld.internal_code` and
+//! `simbld.family` on it once the response is ready. Catalog lookups, authentication and
+//! authorization decisions and rate-limit rejections are reported as `tracing` events.
+//! Without the feature, the same messages go through the `log` crate.
+//!
+//! | Target                        | Events                                   |
+//! |-------------------------------|------------------------------------------|
+//! | `simbld_http::catalog`        | Catalog lookups                          |
+//! | `simbld_http::authentication` | Accepted and rejected credentials        |
+//! | `simbld_http::authorization`  | Granted and denied accesses              |
+//! | `simbld_http::rate_limit`     | Rejected requests                        |
+
+use actix_web::dev::Payload;
+use actix_web::http::header::HeaderMap;
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use futures_util::future::{ready, Ready};
+use std::fmt;
+
+/// Reports an event through `tracing` with the `tracing` feature, or through `log` otherwise.
+///
+/// Structured fields, given between braces in `tracing` syntax, are only kept by `tracing`,
+/// so the message should mention what matters on its own.
+macro_rules! trace_event {
+    ($level:ident, target: $target:expr, { $($fields:tt)* }, $($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::$level!(target: $target, $($fields)* $($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        log::$level!(target: $target, $($arg)+);
+    }};
+}
+
+pub(crate) use trace_event;
+
+/// W3C trace context of a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// Trace id, 32 lowercase hex digits.
+    pub trace_id: String,
+    /// Span id of the caller, when the trace was continued from a `traceparent` header.
+    pub parent_id: Option<String>,
+    /// Span id given to this request, 16 lowercase hex digits.
+    pub span_id: String,
+    /// Trace flags (bit 0: sampled).
+    pub flags: u8,
+    /// Vendor-specific `tracestate` header, forwarded as is.
+    pub state: Option<String>,
+}
+
+impl TraceContext {
+    /// Starts a new sampled trace.
+    pub fn new() -> Self {
+        Self {
+            trace_id: random_hex(32),
+            parent_id: None,
+            span_id: random_hex(16),
+            flags: 0x01,
+            state: None,
+        }
+    }
+
+    /// Continues the trace of a well-formed `traceparent` header, or starts a new one.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let Some((trace_id, parent_id, flags)) = headers
+            .get("traceparent")
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_traceparent)
+        else {
+            return Self::new();
+        };
+        let state = headers
+            .get_all("tracestate")
+            .filter_map(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        Self {
+            trace_id,
+            parent_id: Some(parent_id),
+            span_id: random_hex(16),
+            flags,
+            state: (!state.is_empty()).then_some(state),
+        }
+    }
+
+    /// Whether the caller asked for the trace to be recorded.
+    pub fn sampled(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+
+    /// `traceparent` value to send to downstream services, with this request as parent.
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", self.trace_id, self.span_id, self.flags)
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.traceparent())
+    }
+}
+
+/// Extracts the trace context stored by `HttpInterceptor`, or starts a new trace.
+impl FromRequest for TraceContext {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let context = req.extensions().get::<TraceContext>().cloned();
+        ready(Ok(context.unwrap_or_else(|| TraceContext::from_headers(req.headers()))))
+    }
+}
+
+/// Parses `version "-" trace-id "-" parent-id "-" flags`.
+///
+/// Versions other than `00` may append fields, which are ignored.
+pub(crate) fn parse_traceparent(value: &str) -> Option<(String, String, u8)> {
+    let mut parts = value.trim().split('-');
+    let (version, trace_id, parent_id, flags) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+    let hex = |value: &str, len: usize| {
+        value.len() == len
+            && value.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+            && value.bytes().any(|b| b != b'0')
+    };
+    let well_formed = version.len() == 2
+        && version.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        && version != "ff"
+        && (version != "00" || parts.next().is_none())
+        && hex(trace_id, 32)
+        && hex(parent_id, 16)
+        && flags.len() == 2;
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+    well_formed.then(|| (trace_id.to_string(), parent_id.to_string(), flags))
+}
+
+fn random_hex(len: usize) -> String {
+    let uuid = uuid::Uuid::new_v4().simple().to_string();
+    uuid[uuid.len() - len..].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+
+    #[actix_web::test]
+    async fn test_continues_incoming_trace() {
+        let req = test::TestRequest::default()
+            .insert_header((
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            ))
+            .insert_header(("tracestate", "congo=t61rcWkgMzE"))
+            .to_http_request();
+        let context = TraceContext::from_headers(req.headers());
+
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.parent_id.as_deref(), Some("00f067aa0ba902b7"));
+        assert_ne!(context.span_id, "00f067aa0ba902b7");
+        assert_eq!(context.state.as_deref(), Some("congo=t61rcWkgMzE"));
+        assert!(context.sampled());
+        assert_eq!(
+            context.traceparent(),
+            format!("00-4bf92f3577b34da6a3ce929d0e0e4736-{}-01", context.span_id)
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_rejects_malformed_traceparent() {
+        for value in [
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        ] {
+            let req = test::TestRequest::default()
+                .insert_header(("traceparent", value))
+                .insert_header(("tracestate", "congo=t61rcWkgMzE"))
+                .to_http_request();
+            let context = TraceContext::from_headers(req.headers());
+            assert_eq!(context.parent_id, None, "{}", value);
+            assert_eq!(context.state, None, "{}", value);
+            assert_eq!(context.trace_id.len(), 32);
+            assert_eq!(context.span_id.len(), 16);
+        }
+
+        // Future versions may carry more fields
+        let req = test::TestRequest::default()
+            .insert_header((
+                "traceparent",
+                "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-x",
+            ))
+            .to_http_request();
+        let context = TraceContext::from_headers(req.headers());
+        assert_eq!(context.parent_id.as_deref(), Some("00f067aa0ba902b7"));
+        assert!(!context.sampled());
+    }
+}
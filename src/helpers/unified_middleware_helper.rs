@@ -1,41 +1,60 @@
+use crate::helpers::cors_helper::{CorsError, CorsPolicy};
+use crate::helpers::rate_limit_helper::{
+    Clock, FixedWindow, RateLimitAlgorithm, RateLimitDecision, RateLimitHeaders, RateLimitQuota,
+    SystemClock,
+};
+use crate::helpers::rate_limit_key_helper::{
+    ClientIpKey, MissingKeyPolicy, RateLimitKey, RateLimitOverrides, SHARED_BUCKET_KEY,
+};
+use crate::helpers::rate_limit_store_helper::{InMemoryStore, RateLimitStore};
+use crate::helpers::tracing_helper::trace_event;
+use crate::responses::{ResponsesClientCodes, ResponsesSuccessCodes, ResponsesTypes};
 use actix_service::{Service, Transform};
 use actix_web::{
+    body::EitherBody,
     dev::{ServiceRequest, ServiceResponse},
-    error::Error as ActixError,
+    error::{Error as ActixError, InternalError},
     http::{header, StatusCode},
     HttpResponse,
 };
 use futures_util::future::{ready, LocalBoxFuture, Ready};
-use std::{
-    collections::{HashMap, HashSet},
-    rc::Rc,
-    sync::{Arc, Mutex},
-    time::{Duration, Instant},
-};
+use std::{collections::HashSet, rc::Rc, sync::Arc, time::Duration};
 use thiserror::Error;
 
 pub type ConditionFunction = Rc<Box<dyn for<'a> Fn(&'a ServiceRequest) -> bool + 'static>>;
 pub type InterceptFunction = Rc<dyn Fn(&ServiceRequest) -> bool>;
-pub type RateLimiters = Arc<Mutex<HashMap<String, (u64, Instant)>>>;
+pub type RateLimiters = Arc<dyn RateLimitStore>;
 pub type AllowedOrigins = HashSet<String>;
 
 impl std::fmt::Debug for UnifiedMiddleware {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("UnifiedMiddleware")
             .field("allowed_origins", &self.allowed_origins)
+            .field("cors", &self.cors)
             .field("max_requests", &self.max_requests)
             .field("window_duration", &self.window_duration)
+            .field("rate_limit_algorithm", &self.rate_limit_algorithm.name())
+            .field("missing_key_policy", &self.missing_key_policy)
+            .field("rate_limit_overrides", &self.rate_limit_overrides)
+            .field("rate_limit_headers", &self.rate_limit_headers)
             .finish()
     }
 }
 
 pub struct UnifiedMiddleware {
     pub allowed_origins: AllowedOrigins,
+    pub cors: CorsPolicy,
     pub rate_limiters: RateLimiters,
     pub max_requests: usize,
     pub window_duration: Duration,
     pub intercept_dependencies: InterceptFunction,
     pub condition: ConditionFunction,
+    pub rate_limit_algorithm: Arc<dyn RateLimitAlgorithm>,
+    pub clock: Arc<dyn Clock>,
+    pub rate_limit_key: Rc<dyn RateLimitKey>,
+    pub missing_key_policy: MissingKeyPolicy,
+    pub rate_limit_overrides: Rc<RateLimitOverrides>,
+    pub rate_limit_headers: RateLimitHeaders,
 }
 
 #[derive(Debug, Error)]
@@ -46,6 +65,12 @@ pub enum UnifiedError {
     InvalidRequest,
     #[error("Unauthorized access.")]
     Unauthorized,
+    #[error("Missing rate-limit key.")]
+    MissingRateLimitKey,
+    #[error("Too many requests.")]
+    TooManyRequests(RateLimitDecision, RateLimitHeaders),
+    #[error("Cross-origin request rejected: {0}")]
+    Cors(CorsError),
 }
 
 impl actix_web::ResponseError for UnifiedError {
@@ -54,10 +79,37 @@ impl actix_web::ResponseError for UnifiedError {
             UnifiedError::InternalMiddlewareError => StatusCode::INTERNAL_SERVER_ERROR,
             UnifiedError::InvalidRequest => StatusCode::BAD_REQUEST,
             UnifiedError::Unauthorized => StatusCode::UNAUTHORIZED,
+            UnifiedError::MissingRateLimitKey => StatusCode::BAD_REQUEST,
+            UnifiedError::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            UnifiedError::Cors(err) => StatusCode::from_u16(err.response_type().get_code())
+                .unwrap_or(StatusCode::BAD_REQUEST),
         }
     }
 
     fn error_response(&self) -> HttpResponse {
+        if let UnifiedError::TooManyRequests(decision, headers) = self {
+            let response = ResponsesTypes::ClientError(ResponsesClientCodes::TooManyRequests);
+            let mut builder = response.response_builder();
+            for header in decision.headers(*headers) {
+                builder.insert_header(header);
+            }
+            return builder.content_type("application/json").body(response.as_json().to_string());
+        }
+
+        if let UnifiedError::MissingRateLimitKey = self {
+            let response = ResponsesTypes::ClientError(ResponsesClientCodes::BadRequest);
+            return response
+                .response_builder()
+                .insert_header(("X-HTTP-Status-Code", response.get_code().to_string()))
+                .insert_header(("X-Rate-Limit-Error", self.to_string()))
+                .content_type("application/json")
+                .body(response.as_json().to_string());
+        }
+
+        if let UnifiedError::Cors(err) = self {
+            return err.response_type().into_http_response();
+        }
+
         HttpResponse::build(self.status_code())
             .content_type("application/json")
             .body(format!("{{\"error\": \"{}\"}}", self))
@@ -73,12 +125,15 @@ impl UnifiedMiddleware {
     /// # arguments
     ///
     /// * `Allowed_origins' - Authorized Kid Origins, separated by commas (ex:" http: //example.com,http: // Localhost: 3000 ")
-    /// * `rate_limiters` - Storage of rate limiters by IP
+    /// * `rate_limiters` - Store keeping the rate-limit state of each key (see `rate_limit_store_helper`)
     /// * `Max_requests' - Maximum number of requests authorized in the time window
     /// * `Window_Duration` - Duration of the window for the rate limiter
     /// * `intercept_dependencies' - function that determines if the request must be intercepted
     /// * `Condition ' - Additional condition to apply the middleware
     ///
+    /// The rate limiter uses the `FixedWindow` algorithm and the system clock, and counts
+    /// requests per client IP; see the `with_*` methods to change these defaults.
+    ///
     pub fn new(
         allowed_origins: String,
         rate_limiters: RateLimiters,
@@ -95,11 +150,18 @@ impl UnifiedMiddleware {
 
         Self {
             allowed_origins: origins,
+            cors: CorsPolicy::new(),
             rate_limiters,
             max_requests,
             window_duration,
             intercept_dependencies,
             condition: Rc::new(condition.unwrap_or(default_condition)),
+            rate_limit_algorithm: Arc::new(FixedWindow),
+            clock: Arc::new(SystemClock),
+            rate_limit_key: Rc::new(ClientIpKey),
+            missing_key_policy: MissingKeyPolicy::SharedBucket,
+            rate_limit_overrides: Rc::new(RateLimitOverrides::new()),
+            rate_limit_headers: RateLimitHeaders::Separate,
         }
     }
 
@@ -121,13 +183,73 @@ impl UnifiedMiddleware {
     ) -> Self {
         Self::new(
             allowed_origins.join(","),
-            Arc::new(Mutex::new(HashMap::new())),
+            Arc::new(InMemoryStore::new()),
             max_requests,
             window_duration,
             Rc::new(|_| true),
             Some(Box::new(|_| true)),
         )
     }
+
+    /// Selects the rate-limiting algorithm used by this middleware instance.
+    ///
+    /// # arguments
+    ///
+    /// * `algorithm` - Any `RateLimitAlgorithm` (e.g. `SlidingLog`, `TokenBucket`, `Gcra`)
+    ///
+    pub fn with_rate_limit_algorithm(
+        mut self,
+        algorithm: impl RateLimitAlgorithm + 'static,
+    ) -> Self {
+        self.rate_limit_algorithm = Arc::new(algorithm);
+        self
+    }
+
+    /// Replaces the clock used by the rate limiter (e.g. a `ManualClock` in tests).
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Selects how requests are grouped for rate limiting.
+    ///
+    /// # arguments
+    ///
+    /// * `key` - Any `RateLimitKey` (e.g. `HeaderKey::api_key()`, `CompositeKey::ip_and_route()`)
+    ///
+    pub fn with_rate_limit_key(mut self, key: impl RateLimitKey + 'static) -> Self {
+        self.rate_limit_key = Rc::new(key);
+        self
+    }
+
+    /// Defines what happens to requests for which no rate-limit key can be extracted.
+    pub fn with_missing_key_policy(mut self, policy: MissingKeyPolicy) -> Self {
+        self.missing_key_policy = policy;
+        self
+    }
+
+    /// Selects the style of the rate-limit headers added to allowed and rejected responses.
+    pub fn with_rate_limit_headers(mut self, headers: RateLimitHeaders) -> Self {
+        self.rate_limit_headers = headers;
+        self
+    }
+
+    /// Sets the CORS policy (methods, headers, credentials, max age, pattern origins).
+    ///
+    /// The entries of `allowed_origins` are added to the policy's origins when the
+    /// middleware is built; they may be exact origins, `*` or wildcard subdomains
+    /// such as `https://*.example.com`.
+    ///
+    pub fn with_cors(mut self, cors: CorsPolicy) -> Self {
+        self.cors = cors;
+        self
+    }
+
+    /// Applies per-key quota overrides on top of `max_requests` / `window_duration`.
+    pub fn with_rate_limit_overrides(mut self, overrides: RateLimitOverrides) -> Self {
+        self.rate_limit_overrides = Rc::new(overrides);
+        self
+    }
 }
 
 impl<S, B> Transform<S, ServiceRequest> for UnifiedMiddleware
@@ -135,7 +257,7 @@ where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError> + 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = ActixError;
     type Transform = UnifiedMiddlewareService<S>;
     type InitError = ();
@@ -144,24 +266,45 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(UnifiedMiddlewareService {
             service: Rc::new(service),
-            allowed_origins: self.allowed_origins.clone(),
-            rate_limiters: self.rate_limiters.clone(),
-            max_requests: self.max_requests,
-            window_duration: self.window_duration,
+            cors: Rc::new(
+                self.allowed_origins
+                    .iter()
+                    .fold(self.cors.clone(), |cors, origin| cors.with_origin(origin)),
+            ),
             intercept_dependencies: self.intercept_dependencies.clone(),
             condition: self.condition.clone(),
+            rate_limit: Rc::new(RateLimitSettings {
+                rate_limiters: self.rate_limiters.clone(),
+                quota: RateLimitQuota::new(self.max_requests as u64, self.window_duration),
+                algorithm: self.rate_limit_algorithm.clone(),
+                clock: self.clock.clone(),
+                key: self.rate_limit_key.clone(),
+                missing_key_policy: self.missing_key_policy.clone(),
+                overrides: self.rate_limit_overrides.clone(),
+                headers: self.rate_limit_headers,
+            }),
         }))
     }
 }
 
 pub struct UnifiedMiddlewareService<S> {
     service: Rc<S>,
-    allowed_origins: AllowedOrigins,
-    rate_limiters: RateLimiters,
-    max_requests: usize,
-    window_duration: Duration,
+    cors: Rc<CorsPolicy>,
     intercept_dependencies: InterceptFunction,
     condition: ConditionFunction,
+    rate_limit: Rc<RateLimitSettings>,
+}
+
+/// Rate-limiting configuration captured by the middleware service.
+struct RateLimitSettings {
+    rate_limiters: RateLimiters,
+    quota: RateLimitQuota,
+    algorithm: Arc<dyn RateLimitAlgorithm>,
+    clock: Arc<dyn Clock>,
+    key: Rc<dyn RateLimitKey>,
+    missing_key_policy: MissingKeyPolicy,
+    overrides: Rc<RateLimitOverrides>,
+    headers: RateLimitHeaders,
 }
 
 impl<S, B> Service<ServiceRequest> for UnifiedMiddlewareService<S>
@@ -169,7 +312,7 @@ where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = ActixError> + 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = ActixError;
     type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
@@ -184,90 +327,154 @@ where
         let service = self.service.clone();
         let condition = self.condition.clone();
         let intercept = self.intercept_dependencies.clone();
-        let allowed_origins = self.allowed_origins.clone();
-        let rate_limiters = self.rate_limiters.clone();
-        let max_requests = self.max_requests;
-        let window_duration = self.window_duration;
+        let cors = self.cors.clone();
+        let rate_limit = self.rate_limit.clone();
 
         Box::pin(async move {
             // Check if the conditions are met to apply the middleware
             if !(*condition)(&req) {
-                return service.call(req).await;
+                return service.call(req).await.map(ServiceResponse::map_into_left_body);
             }
 
-            if (*intercept)(&req) {
-                // Check the origin if it is a CORS request
-                check_origin(&req, &allowed_origins)?;
+            if !(*intercept)(&req) {
+                return service.call(req).await.map(ServiceResponse::map_into_left_body);
+            }
+
+            // Answer CORS preflights directly, before they reach the handler or the rate limiter
+            if CorsPolicy::is_preflight(req.method(), req.headers()) {
+                let headers = cors.preflight_headers(req.headers()).map_err(UnifiedError::Cors)?;
+                return Ok(req.into_response(preflight_response(headers)).map_into_right_body());
+            }
+
+            // Check the origin if it is a CORS request
+            let cors_headers = cors.actual_headers(req.headers()).map_err(UnifiedError::Cors)?;
 
-                // Check the rate limiting
-                check_rate_limit(&req, rate_limiters, max_requests, window_duration)?;
+            // Check the rate limiting
+            let decision = check_rate_limit(&req, &rate_limit)
+                .await
+                .map_err(|err| with_cors_headers(err, &cors_headers))?;
+
+            let mut res =
+                service.call(req).await.map_err(|err| with_cors_headers(err, &cors_headers))?;
+
+            // Let the browser expose the response to the calling origin
+            insert_cors_headers(res.headers_mut(), &cors_headers);
+
+            // Tell the client where it stands with respect to its quota
+            if let Some(decision) = decision {
+                for (name, value) in decision.headers(rate_limit.headers) {
+                    res.headers_mut().insert(name, value);
+                }
             }
 
-            service.call(req).await
+            Ok(res.map_into_left_body())
         })
     }
 }
 
-// Function to check the origin of the request
-fn check_origin(req: &ServiceRequest, allowed_origins: &AllowedOrigins) -> Result<(), ActixError> {
-    if let Some(origin) = req.headers().get(header::ORIGIN) {
-        if let Ok(origin_str) = origin.to_str() {
-            if !allowed_origins.contains(origin_str) && !allowed_origins.contains("*") {
-                return Err(UnifiedError::Unauthorized.into());
-            }
+// Function to add the CORS headers of an actual request, appending to `Vary`
+fn insert_cors_headers(
+    headers: &mut header::HeaderMap,
+    cors_headers: &[(header::HeaderName, header::HeaderValue)],
+) {
+    for (name, value) in cors_headers {
+        if name == header::VARY {
+            headers.append(name.clone(), value.clone());
+        } else {
+            headers.insert(name.clone(), value.clone());
         }
     }
-    Ok(())
 }
 
-// Function to update the rate limiter for a specific client IP
-fn check_rate_limit(
+// Function to render an error with the CORS headers, so that browsers can read rejections
+fn with_cors_headers(
+    err: ActixError,
+    cors_headers: &[(header::HeaderName, header::HeaderValue)],
+) -> ActixError {
+    if cors_headers.is_empty() {
+        return err;
+    }
+    let mut response = err.error_response();
+    insert_cors_headers(response.headers_mut(), cors_headers);
+    InternalError::from_response(err, response).into()
+}
+
+// Function to build the `204 No Content` answer of an accepted CORS preflight
+fn preflight_response(headers: Vec<(header::HeaderName, header::HeaderValue)>) -> HttpResponse {
+    let mut builder = ResponsesTypes::Success(ResponsesSuccessCodes::NoContent).response_builder();
+    for header in headers {
+        builder.insert_header(header);
+    }
+    builder.finish()
+}
+
+// Function to check the rate limit of the client identified by the configured key.
+// Returns the decision for allowed requests, or `None` when the request bypasses the limiter.
+async fn check_rate_limit(
     req: &ServiceRequest,
-    rate_limiters: RateLimiters,
-    max_requests: usize,
-    window_duration: Duration,
-) -> Result<(), ActixError> {
-    let client_ip = match req.connection_info().realip_remote_addr() {
-        Some(ip) => ip.to_string(),
-        None => "unknown".to_string(),
+    settings: &RateLimitSettings,
+) -> Result<Option<RateLimitDecision>, ActixError> {
+    let key = match settings.key.extract(req) {
+        Some(key) => key,
+        None => match settings.missing_key_policy {
+            MissingKeyPolicy::SharedBucket => SHARED_BUCKET_KEY.to_string(),
+            MissingKeyPolicy::Bypass => return Ok(None),
+            MissingKeyPolicy::Reject => return Err(UnifiedError::MissingRateLimitKey.into()),
+        },
     };
 
-    let should_limit =
-        update_rate_limiter(&client_ip, rate_limiters, max_requests, window_duration)?;
-
-    if should_limit {
-        return Err(ActixError::from(UnifiedError::InvalidRequest));
+    let decision = update_rate_limiter(key.clone(), settings).await?;
+
+    if !decision.allowed {
+        trace_event!(
+            warn,
+            target: "simbld_http::rate_limit",
+            {
+                key = key.as_str(),
+                limit = decision.limit,
+                retry_after_secs = decision.retry_after.unwrap_or_default().as_secs(),
+            },
+            "rejected key={} path={} limit={}",
+            key,
+            req.path(),
+            decision.limit
+        );
+        return Err(ActixError::from(UnifiedError::TooManyRequests(decision, settings.headers)));
     }
 
-    Ok(())
+    Ok(Some(decision))
 }
 
-// Function to update the rate limiter for a specific client IP
-fn update_rate_limiter(
-    client_ip: &str,
-    rate_limiters: RateLimiters,
-    max_requests: usize,
-    window_duration: Duration,
-) -> Result<bool, ActixError> {
-    let mut limiters = rate_limiters.lock().map_err(|_| UnifiedError::InternalMiddlewareError)?;
-
-    let now = Instant::now();
-    let entry = limiters.entry(client_ip.to_string()).or_insert_with(|| (0, now));
-
-    if now.duration_since(entry.1) > window_duration {
-        // Reinitialize the entry if the window has expired
-        *entry = (1, now);
-        Ok(false)
-    } else {
-        // Increment the request count
-        entry.0 += 1;
-        Ok(entry.0 > max_requests as u64)
-    }
+// Function to update the rate limiter for a specific key in the configured store.
+// States are kept for two windows, long enough for every algorithm to look back one window.
+async fn update_rate_limiter(
+    key: String,
+    settings: &RateLimitSettings,
+) -> Result<RateLimitDecision, ActixError> {
+    let quota = settings.overrides.resolve(&key, settings.quota);
+    let now = settings.clock.now();
+    let algorithm = settings.algorithm.clone();
+
+    settings
+        .rate_limiters
+        .update(
+            key,
+            now,
+            quota.window.saturating_mul(2),
+            Arc::new(move |state| algorithm.acquire(state, &quota, now)),
+        )
+        .await
+        .map_err(|err| {
+            log::error!("Rate-limit store failure: {}", err);
+            UnifiedError::InternalMiddlewareError.into()
+        })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::helpers::rate_limit_helper::{ManualClock, SlidingLog};
+    use crate::helpers::rate_limit_key_helper::HeaderKey;
     use actix_web::{
         dev::Service,
         http::StatusCode,
@@ -282,7 +489,7 @@ mod tests {
 
     #[actix_web::test]
     async fn test_rate_limiting() {
-        let rate_limiters = Arc::new(Mutex::new(HashMap::new()));
+        let rate_limiters: RateLimiters = Arc::new(InMemoryStore::new());
         let max_requests = 2;
         let window_duration = Duration::from_secs(1);
 
@@ -312,9 +519,45 @@ mod tests {
         assert!(resp.is_err());
     }
 
+    #[actix_web::test]
+    async fn test_rate_limit_headers_and_429() {
+        let clock = ManualClock::new();
+        let middleware =
+            UnifiedMiddleware::simple(vec!["*".to_string()], 1, Duration::from_secs(60))
+                .with_clock(clock.clone());
+
+        let app =
+            init_service(App::new().wrap(middleware).route("/test", web::get().to(test_handler)))
+                .await;
+
+        // Allowed responses carry the quota headers
+        let resp = app.call(TestRequest::get().uri("/test").to_request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("ratelimit-limit").unwrap(), "1");
+        assert_eq!(resp.headers().get("ratelimit-remaining").unwrap(), "0");
+        assert_eq!(resp.headers().get("ratelimit-reset").unwrap(), "60");
+        assert_eq!(resp.headers().get("ratelimit-policy").unwrap(), "1;w=60");
+
+        // Rejected requests get the catalog 429 with Retry-After
+        clock.advance(Duration::from_secs(20));
+        let err = app.call(TestRequest::get().uri("/test").to_request()).await.unwrap_err();
+        let resp = err.error_response();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers().get("retry-after").unwrap(), "40");
+        assert_eq!(resp.headers().get("ratelimit-remaining").unwrap(), "0");
+        assert_eq!(
+            resp.extensions().get::<ResponsesTypes>(),
+            Some(&ResponsesTypes::ClientError(ResponsesClientCodes::TooManyRequests))
+        );
+
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["details"]["standard http code"]["code"], 429);
+    }
+
     #[actix_web::test]
     async fn test_allowed_origins() {
-        let rate_limiters = Arc::new(Mutex::new(HashMap::new()));
+        let rate_limiters: RateLimiters = Arc::new(InMemoryStore::new());
         let allowed_origins = "https://example.com,https://test.com".to_string();
 
         let middleware = UnifiedMiddleware::new(
@@ -343,9 +586,185 @@ mod tests {
         assert!(resp.is_err());
     }
 
+    #[actix_web::test]
+    async fn test_cors_preflight_and_headers() {
+        let middleware = UnifiedMiddleware::simple(
+            vec!["https://*.example.com".to_string()],
+            1,
+            Duration::from_secs(60),
+        )
+        .with_cors(
+            CorsPolicy::new()
+                .with_credentials(true)
+                .with_expose_headers(["x-request-id"])
+                .with_max_age(Duration::from_secs(600)),
+        );
+
+        let app =
+            init_service(App::new().wrap(middleware).route("/test", web::post().to(test_handler)))
+                .await;
+
+        // Preflights are answered without reaching the handler or consuming the quota
+        for _ in 0..2 {
+            let req = TestRequest::default()
+                .method(actix_web::http::Method::OPTIONS)
+                .uri("/test")
+                .insert_header((header::ORIGIN, "https://app.example.com"))
+                .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+                .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type"))
+                .to_request();
+            let resp = app.call(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+            assert_eq!(
+                resp.headers().get("access-control-allow-origin").unwrap(),
+                "https://app.example.com"
+            );
+            assert_eq!(resp.headers().get("access-control-allow-headers").unwrap(), "content-type");
+            assert_eq!(resp.headers().get("access-control-max-age").unwrap(), "600");
+        }
+
+        // Actual requests get the CORS headers on top of the handler's response
+        let req = TestRequest::post()
+            .uri("/test")
+            .insert_header((header::ORIGIN, "https://app.example.com"))
+            .to_request();
+        let resp = app.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("access-control-allow-credentials").unwrap(), "true");
+        assert_eq!(resp.headers().get("access-control-expose-headers").unwrap(), "x-request-id");
+        assert_eq!(resp.headers().get(header::VARY).unwrap(), "Origin");
+
+        // Unknown origins are rejected with the catalog's OriginError
+        let req = TestRequest::post()
+            .uri("/test")
+            .insert_header((header::ORIGIN, "https://example.org"))
+            .to_request();
+        let resp = app.call(req).await.unwrap_err().error_response();
+        assert_eq!(
+            resp.extensions().get::<ResponsesTypes>(),
+            Some(&ResponsesTypes::ClientError(ResponsesClientCodes::OriginError))
+        );
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["details"]["internal http code"]["code"], 433);
+    }
+
+    #[actix_web::test]
+    async fn test_cors_headers_on_rejections() {
+        let middleware = UnifiedMiddleware::simple(
+            vec!["https://app.example.com".to_string()],
+            1,
+            Duration::from_secs(60),
+        )
+        .with_cors(CorsPolicy::new().with_expose_headers(["retry-after"]));
+
+        let app =
+            init_service(App::new().wrap(middleware).route("/test", web::get().to(test_handler)))
+                .await;
+        let call = || {
+            let req = TestRequest::get()
+                .uri("/test")
+                .insert_header((header::ORIGIN, "https://app.example.com"))
+                .to_request();
+            app.call(req)
+        };
+
+        assert!(call().await.is_ok());
+
+        // Browsers can read the 429 and its Retry-After
+        let resp = call().await.unwrap_err().error_response();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            resp.headers().get("access-control-allow-origin").unwrap(),
+            "https://app.example.com"
+        );
+        assert_eq!(resp.headers().get("access-control-expose-headers").unwrap(), "retry-after");
+        assert_eq!(resp.headers().get(header::VARY).unwrap(), "Origin");
+        assert!(resp.headers().contains_key("retry-after"));
+        assert_eq!(
+            resp.extensions().get::<ResponsesTypes>(),
+            Some(&ResponsesTypes::ClientError(ResponsesClientCodes::TooManyRequests))
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limit_algorithm_per_instance() {
+        let clock = ManualClock::new();
+        let middleware =
+            UnifiedMiddleware::simple(vec!["*".to_string()], 2, Duration::from_secs(1))
+                .with_rate_limit_algorithm(SlidingLog)
+                .with_clock(clock.clone());
+        assert_eq!(middleware.rate_limit_algorithm.name(), "sliding_log");
+
+        let app =
+            init_service(App::new().wrap(middleware).route("/test", web::get().to(test_handler)))
+                .await;
+
+        // t = 0ms and t = 900ms - both authorized
+        let resp = app.call(TestRequest::get().uri("/test").to_request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        clock.advance(Duration::from_millis(900));
+        let resp = app.call(TestRequest::get().uri("/test").to_request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // t = 1000ms - a fixed window would have reset, the sliding log still holds t = 900ms
+        clock.advance(Duration::from_millis(100));
+        let resp = app.call(TestRequest::get().uri("/test").to_request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = app.call(TestRequest::get().uri("/test").to_request()).await;
+        assert!(resp.is_err());
+
+        // t = 1900ms - the t = 900ms entry has expired
+        clock.advance(Duration::from_millis(900));
+        let resp = app.call(TestRequest::get().uri("/test").to_request()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limit_key_and_overrides() {
+        let middleware =
+            UnifiedMiddleware::simple(vec!["*".to_string()], 1, Duration::from_secs(60))
+                .with_rate_limit_key(HeaderKey::api_key())
+                .with_missing_key_policy(MissingKeyPolicy::Reject)
+                .with_rate_limit_overrides(
+                    RateLimitOverrides::new().multiply("x-api-key:premium", 3),
+                );
+
+        let app =
+            init_service(App::new().wrap(middleware).route("/test", web::get().to(test_handler)))
+                .await;
+        let call = |key: Option<&'static str>| {
+            let mut req = TestRequest::get().uri("/test");
+            if let Some(key) = key {
+                req = req.insert_header(("X-API-Key", key));
+            }
+            app.call(req.to_request())
+        };
+
+        // Each API key has its own bucket
+        assert!(call(Some("basic-1")).await.is_ok());
+        assert!(call(Some("basic-1")).await.is_err());
+        assert!(call(Some("basic-2")).await.is_ok());
+
+        // Premium keys get three times the default limit
+        for _ in 0..3 {
+            assert!(call(Some("premium")).await.is_ok());
+        }
+        assert!(call(Some("premium")).await.is_err());
+
+        // Requests without a key are rejected instead of sharing a bucket
+        let resp = call(None).await.unwrap_err().error_response();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(resp.headers().get("X-HTTP-Status-Code").unwrap(), "400");
+        assert_eq!(resp.headers().get("X-Rate-Limit-Error").unwrap(), "Missing rate-limit key.");
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["details"]["standard http code"]["code"], 400);
+    }
+
     #[actix_web::test]
     async fn test_reset_rate_limiting_window() {
-        let rate_limiters = Arc::new(Mutex::new(HashMap::new()));
+        let rate_limiters: RateLimiters = Arc::new(InMemoryStore::new());
         let max_requests = 1;
         let window_duration = Duration::from_millis(10); // short duration for the test
 
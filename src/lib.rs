@@ -9,6 +9,7 @@ pub mod utils;
 
 // Public exports for helpers
 pub use helpers::auth_middleware::AuthMiddleware;
+pub use helpers::batch_response_helper::BatchResponse;
 pub use helpers::generate_responses_functions;
 pub use helpers::http_interceptor_helper::HttpInterceptor;
 pub use helpers::unified_middleware_helper::UnifiedMiddleware;
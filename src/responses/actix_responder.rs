@@ -54,6 +54,7 @@
 //! }
 //! ```
 
+use crate::helpers::conditional_helper::{strong_etag, Conditional, Validators};
 use crate::helpers::http_code_helper::HttpCode;
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpRequest, HttpResponse, Responder};
@@ -117,6 +118,15 @@ impl CustomResponse {
             description: desc_str,
         }
     }
+
+    /// Wraps the response to answer conditional requests, with a strong ETag hashing `data`.
+    ///
+    /// Requests whose `If-None-Match` or `If-Modified-Since` still match are answered with
+    /// `304 Not Modified` (see `conditional_helper`).
+    pub fn conditional(self) -> Conditional<Self> {
+        let etag = strong_etag(self.data.as_bytes());
+        Conditional::new(self, Validators::new().with_etag(etag))
+    }
 }
 
 /// Implements Actix's Responder trait for CustomResponse.
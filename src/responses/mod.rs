@@ -34,6 +34,8 @@ pub use success::ResponsesSuccessCodes;
 
 // Public exports for response types
 use crate::helpers::http_code_helper::HttpCode;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, HttpResponseBuilder};
 
 /// Enum representing the main categories of HTTP response codes.
 /// Combines multiple categories into a unified type for simplified handling.
@@ -152,6 +154,20 @@ impl ResponsesTypes {
         }
     }
 
+    /// Returns the status family of the response, using the same labels as `populate_metadata`.
+    pub fn get_family(&self) -> &'static str {
+        match self {
+            ResponsesTypes::Informational(_) => "Informational",
+            ResponsesTypes::Success(_) => "Success",
+            ResponsesTypes::Redirection(_) => "Redirection",
+            ResponsesTypes::ClientError(_) => "Client Error",
+            ResponsesTypes::ServerError(_) => "Server Error",
+            ResponsesTypes::ServiceError(_) => "Service Error",
+            ResponsesTypes::CrawlerError(_) => "Crawler Error",
+            ResponsesTypes::LocalApiError(_) => "Local API Error",
+        }
+    }
+
     /// Returns the code and description associated with a response code.
     pub fn get_response_get_description(&self) -> (u16, &'static str) {
         match self {
@@ -191,6 +207,23 @@ impl ResponsesTypes {
         }
     }
 
+    /// Returns an Actix response builder preset with the standard status code of the response.
+    ///
+    /// The response type is stored in the response extensions, so middleware can recognize
+    /// responses rendered from the catalog.
+    pub fn response_builder(&self) -> HttpResponseBuilder {
+        let status =
+            StatusCode::from_u16(self.get_code()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut builder = HttpResponse::build(status);
+        builder.extensions_mut().insert(*self);
+        builder
+    }
+
+    /// Converts the response type into an Actix `HttpResponse` with its JSON representation as body.
+    pub fn into_http_response(&self) -> HttpResponse {
+        self.response_builder().content_type("application/json").body(self.as_json().to_string())
+    }
+
     /// returns a destructured tuple (code, name, description).
     pub fn to_tuple(&self) -> (u16, &'static str, &'static str) {
         let http_code = self.as_tuple();
@@ -256,6 +289,27 @@ mod tests {
         assert_eq!(json_value, expected_json);
     }
 
+    #[test]
+    fn test_get_family() {
+        assert_eq!(
+            ResponsesTypes::Success(ResponsesSuccessCodes::MultiStatus).get_family(),
+            "Success"
+        );
+        assert_eq!(
+            ResponsesTypes::LocalApiError(ResponsesLocalApiCodes::InvalidRole).get_family(),
+            "Local API Error"
+        );
+    }
+
+    #[test]
+    fn test_into_http_response() {
+        let response = ResponsesTypes::ClientError(ResponsesClientCodes::TooManyRequests);
+        let http_response = response.into_http_response();
+
+        assert_eq!(http_response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(http_response.extensions().get::<ResponsesTypes>(), Some(&response));
+    }
+
     #[test]
     fn test_from_u16() {
         assert_eq!(
@@ -278,7 +332,7 @@ mod tests {
         // Verification of an unknown code
         let unknown_code = ResponsesTypes::from_u16(9999);
         let normalized_unknown =
-            unknown_code.as_ref().and_then(|code| response_helpers::get_response_by_type(code));
+            unknown_code.as_ref().and_then(response_helpers::get_response_by_type);
 
         assert_eq!(normalized_unknown, None);
     }
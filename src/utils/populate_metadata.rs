@@ -33,21 +33,7 @@ pub fn populate_metadata(
 
     metadata.insert("description".to_string(), description.to_string());
     metadata.insert("is_error".to_string(), (code >= 400).to_string());
-    metadata.insert(
-        "status_family".to_string(),
-        match code {
-            100..=199 => "Informational",
-            200..=299 => "Success",
-            300..=399 => "Redirection",
-            400..=499 => "Client Error",
-            500..=599 => "Server Error",
-            600..=699 => "Service Error",
-            700..=799 => "Crawler Error",
-            900..=999 => "Local API Error",
-            _ => "Unknown",
-        }
-        .to_string(),
-    );
+    metadata.insert("status_family".to_string(), status_family(code).to_string());
 
     if let Some(req_meta) = request_metadata {
         for (key, value) in req_meta {
@@ -57,3 +43,19 @@ pub fn populate_metadata(
 
     metadata
 }
+
+/// Returns the family of a status code, standard or internal (e.g. `Client Error` for 404,
+/// `Local API Error` for 904).
+pub fn status_family(code: u16) -> &'static str {
+    match code {
+        100..=199 => "Informational",
+        200..=299 => "Success",
+        300..=399 => "Redirection",
+        400..=499 => "Client Error",
+        500..=599 => "Server Error",
+        600..=699 => "Service Error",
+        700..=799 => "Crawler Error",
+        900..=999 => "Local API Error",
+        _ => "Unknown",
+    }
+}
@@ -4,7 +4,7 @@ use simbld_http::helpers::response_helpers;
 use simbld_http::helpers::response_with_cookie_helper::bad_request_with_cookie;
 use simbld_http::helpers::response_with_cookie_helper::ok_with_cookie;
 use simbld_http::helpers::response_with_headers_helper::{
-  bad_request_with_headers, ok_with_headers,
+    bad_request_with_headers, ok_with_headers,
 };
 use simbld_http::responses::ResponsesTypes;
 use simbld_http::ResponsesClientCodes;
@@ -71,8 +71,8 @@ fn test_helper_headers_output() {
     headers.insert("x-trace-id", "123456");
     headers.insert("x-correlation-id", "abc-def");
 
-    let response = test_ok_with_headers();
-    println!("OK with Headers: {:?}", response);
+    let response = ok_with_headers(headers.clone());
+    println!("OK with Headers: {}", response);
 
     let response = bad_request_with_headers(headers.clone());
     println!("Bad Request with Headers: {}", response);
@@ -228,18 +228,20 @@ mod tests {
         let code = response.get_code();
         let description = response.get_description();
 
-        let is_standard_code = match code {
-            100..=103 => true,
-            200..=208 | 226 => true,
-            300..=308 => true,
-            400..=418 | 421..=426 | 428 | 429 | 431 | 451 => true,
-            500..=511 => true,
-            _ => false,
-        };
-
-        if !is_standard_code && false {
-            return Err("Not a standard HTTP status code");
-        }
+        let is_standard_code = matches!(
+            code,
+            100..=103
+                | 200..=208
+                | 226
+                | 300..=308
+                | 400..=418
+                | 421..=426
+                | 428
+                | 429
+                | 431
+                | 451
+                | 500..=511
+        );
 
         Ok(json!({
             "code": code,